@@ -0,0 +1,108 @@
+use crate::sha::{hmac_sha256, sha256};
+
+/// Deriva uma chave de 32 bytes a partir de um segredo e de um rótulo.
+///
+/// Rótulos diferentes produzem chaves independentes a partir do mesmo segredo.
+pub fn derive_key(secret: &[u8], label: &str) -> [u8; 32] {
+    hmac_sha256(secret, label.as_bytes())
+}
+
+/// Deriva uma chave de 32 bytes de uma senha digitada (PBKDF2-HMAC-SHA-256).
+///
+/// O `salt` impede que a mesma senha gere a mesma chave em arquivos diferentes;
+/// as `rounds` iterações deixam cada tentativa de adivinhar a senha mais cara.
+pub fn passphrase_key(passphrase: &str, salt: &[u8], rounds: u32) -> [u8; 32] {
+    let mut first_block = salt.to_vec();
+    first_block.extend_from_slice(&1u32.to_be_bytes());
+    let mut u = hmac_sha256(passphrase.as_bytes(), &first_block);
+    let mut key = u;
+    for _ in 1..rounds {
+        u = hmac_sha256(passphrase.as_bytes(), &u);
+        for (k, byte) in key.iter_mut().zip(u.iter()) {
+            *k ^= byte;
+        }
+    }
+    key
+}
+
+/// Cifra de fluxo baseada em SHA-256 em modo contador.
+///
+/// O bloco `i` do fluxo de chave é `SHA-256(chave || nonce || i)`; cifrar e
+/// decifrar são a mesma operação (XOR com o fluxo). O mesmo par (chave, nonce)
+/// nunca deve ser usado para duas mensagens diferentes.
+pub fn apply_keystream(key: &[u8; 32], nonce: u64, data: &mut [u8]) {
+    let mut block_input = [0u8; 48];
+    block_input[..32].copy_from_slice(key);
+    block_input[32..40].copy_from_slice(&nonce.to_be_bytes());
+
+    for (counter, chunk) in data.chunks_mut(32).enumerate() {
+        block_input[40..].copy_from_slice(&(counter as u64).to_be_bytes());
+        let keystream = sha256(&block_input);
+        for (byte, k) in chunk.iter_mut().zip(keystream.iter()) {
+            *byte ^= k;
+        }
+    }
+}
+
+/// Calcula o MAC (HMAC-SHA-256) que autentica o nonce, os dados associados
+/// (metadados que vão às claras, como o remetente) e o texto cifrado.
+pub fn mac(key: &[u8; 32], nonce: u64, associated: &[u8], ciphertext: &[u8]) -> [u8; 32] {
+    hmac_sha256(key, &authenticated_data(nonce, associated, ciphertext))
+}
+
+/// Serializa nonce, dados associados e conteúdo sem ambiguidade: o tamanho dos
+/// dados associados vem antes deles, então nenhum byte pode migrar de um campo a outro.
+pub fn authenticated_data(nonce: u64, associated: &[u8], content: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(16 + associated.len() + content.len());
+    data.extend_from_slice(&nonce.to_be_bytes());
+    data.extend_from_slice(&(associated.len() as u64).to_be_bytes());
+    data.extend_from_slice(associated);
+    data.extend_from_slice(content);
+    data
+}
+
+/// Compara dois MACs sem sair cedo no primeiro byte diferente.
+pub fn macs_equal(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keystream_round_trips_and_depends_on_nonce() {
+        let key = derive_key(b"segredo", "teste");
+        let text = b"uma mensagem com mais de trinta e dois bytes de comprimento";
+        let mut data = text.to_vec();
+        apply_keystream(&key, 1, &mut data);
+        assert_ne!(&data[..], &text[..]);
+
+        let mut other_nonce = text.to_vec();
+        apply_keystream(&key, 2, &mut other_nonce);
+        assert_ne!(data, other_nonce);
+
+        apply_keystream(&key, 1, &mut data);
+        assert_eq!(&data[..], &text[..]);
+    }
+
+    #[test]
+    fn labels_derive_independent_keys() {
+        assert_eq!(derive_key(b"segredo", "cifra"), derive_key(b"segredo", "cifra"));
+        assert_ne!(derive_key(b"segredo", "cifra"), derive_key(b"segredo", "mac"));
+        assert_ne!(derive_key(b"segredo", "cifra"), derive_key(b"outro", "cifra"));
+    }
+
+    #[test]
+    fn mac_covers_nonce_associated_data_and_ciphertext() {
+        let key = derive_key(b"segredo", "mac");
+        let tag = mac(&key, 7, b"ana", b"texto");
+        assert!(macs_equal(&tag, &mac(&key, 7, b"ana", b"texto")));
+        assert!(!macs_equal(&tag, &mac(&key, 8, b"ana", b"texto")));
+        assert!(!macs_equal(&tag, &mac(&key, 7, b"bia", b"texto")));
+        assert!(!macs_equal(&tag, &mac(&key, 7, b"ana", b"textO")));
+        assert!(!macs_equal(&tag, &mac(&derive_key(b"outro", "mac"), 7, b"ana", b"texto")));
+        // Bytes não podem passar dos dados associados para o texto cifrado.
+        assert!(!macs_equal(&tag, &mac(&key, 7, b"an", b"atexto")));
+    }
+}
@@ -0,0 +1,316 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use clap::{Args, Parser, Subcommand};
+use crate::config::Profile;
+use crate::handshake::{CipherAlgorithm, Offer};
+use crate::mailbox::MailboxConfig;
+use crate::network::{ClientConfig, ServerConfig, DEFAULT_PORT, DEFAULT_SERVER_KEY_FILE};
+use crate::rsa::{MAX_KEY_BITS, MIN_KEY_BITS};
+use crate::ui::{Theme, UiMode, Verbosity};
+
+/// Chat cifrado com RSA, handshake Diffie-Hellman e mensagens autenticadas.
+///
+/// Sem subcomando, conecta ao servidor como `connect`. As opções não dadas vêm do
+/// perfil escolhido no arquivo de configuração e, na falta dele, dos padrões.
+#[derive(Parser)]
+#[command(name = "chat_rsa", version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Arquivo de configuração com perfis (padrão: chat_rsa.json, se existir)
+    #[arg(long, global = true, value_name = "ARQUIVO")]
+    pub config: Option<PathBuf>,
+    /// Perfil do arquivo de configuração
+    #[arg(long, global = true, value_name = "NOME")]
+    pub profile: Option<String>,
+
+    #[command(flatten)]
+    pub connect: ConnectArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Inicia o servidor, com o console do operador
+    Server(ServerArgs),
+    /// Conecta a um servidor e entra na conversa
+    Connect(ConnectArgs),
+    /// Gera um par de chaves RSA e o salva em um arquivo (para --identity ou --key)
+    Keygen {
+        /// Arquivo onde o par de chaves é salvo
+        #[arg(value_name = "ARQUIVO")]
+        file: PathBuf,
+        /// Tamanho de n, em bits
+        #[arg(long, value_name = "BITS", value_parser = key_bits())]
+        bits: Option<u32>,
+        /// Substitui o arquivo se ele já existir
+        #[arg(long)]
+        force: bool,
+    },
+    /// Calcula o SHA-256 de um arquivo (ou da entrada padrão)
+    Hash {
+        /// Arquivo a resumir; sem ele, lê a entrada padrão
+        #[arg(value_name = "ARQUIVO")]
+        file: Option<PathBuf>,
+    },
+    /// Mostra a impressão digital e os parâmetros de um par de chaves salvo
+    Fingerprint {
+        /// Arquivo do par de chaves
+        #[arg(value_name = "ARQUIVO")]
+        file: PathBuf,
+    },
+}
+
+/// Opções de interface comuns ao servidor e ao cliente.
+#[derive(Args)]
+pub struct UiArgs {
+    /// Interface: linhas no terminal ou tela cheia [padrão: plain]
+    #[arg(long, value_name = "MODO", value_enum)]
+    ui: Option<UiMode>,
+    /// Atalho para --ui tui
+    #[arg(long, conflicts_with = "ui")]
+    tui: bool,
+    /// Começa com o inspetor criptográfico ligado
+    #[arg(short, long, conflicts_with = "quiet")]
+    verbose: bool,
+    /// Esconde as linhas de progresso da conexão
+    #[arg(short, long)]
+    quiet: bool,
+    /// Cores da tela cheia [padrão: dark]
+    #[arg(long, value_name = "TEMA", value_enum)]
+    theme: Option<Theme>,
+}
+
+impl UiArgs {
+    fn mode(&self, profile: Option<UiMode>) -> UiMode {
+        if self.tui { UiMode::Tui } else { self.ui.or(profile).unwrap_or(UiMode::Plain) }
+    }
+
+    fn theme(&self, profile: Option<Theme>) -> Theme {
+        self.theme.or(profile).unwrap_or_default()
+    }
+
+    fn verbosity(&self) -> Verbosity {
+        match (self.verbose, self.quiet) {
+            (true, _) => Verbosity::Verbose,
+            (_, true) => Verbosity::Quiet,
+            _ => Verbosity::Normal,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ServerArgs {
+    /// Endereço em que o servidor escuta [padrão: 0.0.0.0]
+    #[arg(long, value_name = "IP")]
+    bind: Option<IpAddr>,
+    /// Porta em que o servidor escuta [padrão: 8080]
+    #[arg(short, long, value_name = "PORTA")]
+    port: Option<u16>,
+    /// Relay cego: só repassa mensagens cifradas de ponta a ponta
+    #[arg(long)]
+    relay: bool,
+    /// Por quantas horas uma mensagem guardada espera o destinatário
+    #[arg(long, value_name = "HORAS")]
+    retention_hours: Option<u64>,
+    /// Quantas mensagens ficam guardadas por destinatário
+    #[arg(long, value_name = "N")]
+    quota: Option<usize>,
+    /// Arquivo com o par de chaves do servidor, criado se não existir [padrão: chat_rsa_servidor.json]
+    #[arg(long, value_name = "ARQUIVO")]
+    key: Option<PathBuf>,
+    /// Tamanho de n, em bits, da chave do servidor, se o arquivo dela for criado agora
+    #[arg(long, value_name = "BITS", value_parser = key_bits())]
+    key_bits: Option<u32>,
+    /// Cifra aceita, em ordem de preferência (repita para mais de uma) [padrão: todas]
+    #[arg(long = "cipher", value_name = "CIFRA", value_enum)]
+    ciphers: Vec<CipherAlgorithm>,
+    #[command(flatten)]
+    ui: UiArgs,
+}
+
+impl ServerArgs {
+    /// Opções do servidor: as da linha de comando, completadas pelo perfil.
+    pub fn config(self, profile: Profile) -> ServerConfig {
+        let mut mailbox = MailboxConfig::default();
+        if let Some(hours) = self.retention_hours.or(profile.retention_hours) {
+            mailbox.retention = Duration::from_secs(hours * 60 * 60);
+        }
+        if let Some(quota) = self.quota.or(profile.quota) {
+            mailbox.quota = quota;
+        }
+        let bind = self.bind.or(profile.bind).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        ServerConfig {
+            bind: SocketAddr::new(bind, self.port.or(profile.port).unwrap_or(DEFAULT_PORT)),
+            relay: self.relay || profile.relay.unwrap_or(false),
+            mailbox,
+            key_file: self.key.or(profile.key).unwrap_or_else(|| PathBuf::from(DEFAULT_SERVER_KEY_FILE)),
+            key_bits: self.key_bits.or(profile.key_bits),
+            offer: offer(self.ciphers, profile.ciphers),
+            ui: self.ui.mode(profile.ui),
+            verbosity: self.ui.verbosity(),
+            theme: self.ui.theme(profile.theme),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ConnectArgs {
+    /// Endereço ou nome do servidor [padrão: 127.0.0.1]
+    #[arg(long)]
+    host: Option<String>,
+    /// Porta do servidor [padrão: 8080]
+    #[arg(short, long, value_name = "PORTA")]
+    port: Option<u16>,
+    /// Arquivo com o par de chaves do cliente, para manter a mesma identidade (criado se não existir)
+    #[arg(long, value_name = "ARQUIVO")]
+    identity: Option<PathBuf>,
+    /// Arquivo do histórico local, cifrado com uma senha
+    #[arg(long, value_name = "ARQUIVO")]
+    history: Option<PathBuf>,
+    /// Tamanho de n, em bits, das chaves geradas
+    #[arg(long, value_name = "BITS", value_parser = key_bits())]
+    key_bits: Option<u32>,
+    /// Arquivo com as impressões digitais dos servidores já vistos; avisa se a chave de um deles mudar
+    #[arg(long, value_name = "ARQUIVO")]
+    known_hosts: Option<PathBuf>,
+    /// Apelido tentado no registro, sem perguntar (se o servidor recusar, pergunta)
+    #[arg(long, value_name = "APELIDO")]
+    nickname: Option<String>,
+    /// Cifra aceita, em ordem de preferência (repita para mais de uma) [padrão: todas]
+    #[arg(long = "cipher", value_name = "CIFRA", value_enum)]
+    ciphers: Vec<CipherAlgorithm>,
+    #[command(flatten)]
+    ui: UiArgs,
+}
+
+impl ConnectArgs {
+    /// Opções do cliente: as da linha de comando, completadas pelo perfil.
+    pub fn config(self, profile: Profile) -> ClientConfig {
+        let host = self.host.or(profile.host).unwrap_or_else(|| "127.0.0.1".to_string());
+        ClientConfig {
+            server: server_address(&host, self.port.or(profile.port).unwrap_or(DEFAULT_PORT)),
+            identity: self.identity.or(profile.identity),
+            history: self.history.or(profile.history),
+            key_bits: self.key_bits.or(profile.key_bits),
+            known_hosts: self.known_hosts.or(profile.known_hosts),
+            nickname: self.nickname.or(profile.nickname),
+            offer: offer(self.ciphers, profile.ciphers),
+            ui: self.ui.mode(profile.ui),
+            verbosity: self.ui.verbosity(),
+            theme: self.ui.theme(profile.theme),
+        }
+    }
+}
+
+// As cifras da linha de comando substituem as do perfil; sem nenhuma, todas as suportadas
+fn offer(cli: Vec<CipherAlgorithm>, profile: Option<Vec<CipherAlgorithm>>) -> Offer {
+    let ciphers = if cli.is_empty() { profile.unwrap_or_default() } else { cli };
+    Offer::with_ciphers(&ciphers)
+}
+
+// Endereço `host:porta` do servidor. Um IP (IPv6 com ou sem colchetes) é formatado
+// por `SocketAddr`, que põe os colchetes; um nome vai como veio.
+fn server_address(host: &str, port: u16) -> String {
+    let bare = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+    match bare.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port).to_string(),
+        Err(_) => format!("{}:{}", host, port),
+    }
+}
+
+// Tamanhos de chave que a aritmética em u64 de `rsa` aguenta
+fn key_bits() -> clap::builder::RangedI64ValueParser<u32> {
+    clap::value_parser!(u32).range(MIN_KEY_BITS as i64..=MAX_KEY_BITS as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("chat_rsa").chain(args.iter().copied())).unwrap()
+    }
+
+    // Como em `main`: sem subcomando, as opções de conexão ficam em `Cli::connect`
+    fn client(args: &[&str], profile: Profile) -> ClientConfig {
+        let cli = parse(args);
+        match cli.command.unwrap_or(Command::Connect(cli.connect)) {
+            Command::Connect(args) => args.config(profile),
+            _ => panic!("esperava o subcomando connect"),
+        }
+    }
+
+    fn server(args: &[&str], profile: Profile) -> ServerConfig {
+        match parse(&[&["server"], args].concat()).command {
+            Some(Command::Server(args)) => args.config(profile),
+            _ => panic!("esperava o subcomando server"),
+        }
+    }
+
+    #[test]
+    fn client_defaults() {
+        assert_eq!(client(&[], Profile::default()).server, format!("127.0.0.1:{}", DEFAULT_PORT));
+    }
+
+    #[test]
+    fn client_host_and_port_formats() {
+        let cases = [
+            ("::1", "[::1]:9000"),
+            ("[::1]", "[::1]:9000"),
+            ("fe80::1", "[fe80::1]:9000"),
+            ("10.0.0.1", "10.0.0.1:9000"),
+            ("chat.exemplo.org", "chat.exemplo.org:9000"),
+        ];
+        for (host, expected) in cases {
+            assert_eq!(client(&["--host", host, "--port", "9000"], Profile::default()).server, expected);
+        }
+    }
+
+    #[test]
+    fn profile_fills_what_the_command_line_leaves_out() {
+        let profile = Profile { host: Some("::1".to_string()), port: Some(9000), nickname: Some("ana".to_string()), ..Profile::default() };
+        let config = client(&[], profile.clone());
+        assert_eq!(config.server, "[::1]:9000");
+        assert_eq!(config.nickname.as_deref(), Some("ana"));
+
+        assert_eq!(client(&["--port", "7000"], profile.clone()).server, "[::1]:7000");
+        assert_eq!(client(&["connect", "--host", "chat.exemplo.org"], profile).server, "chat.exemplo.org:9000");
+    }
+
+    #[test]
+    fn command_line_ciphers_replace_the_profile() {
+        let profile = Profile { ciphers: Some(vec![CipherAlgorithm::RsaBlock]), ..Profile::default() };
+        assert_eq!(client(&[], profile.clone()).offer.ciphers, [CipherAlgorithm::RsaBlock]);
+        assert_eq!(client(&["--cipher", "sha256-ctr"], profile).offer.ciphers, [CipherAlgorithm::Sha256Ctr]);
+        assert_eq!(client(&[], Profile::default()).offer.ciphers, Offer::supported().ciphers);
+    }
+
+    #[test]
+    fn server_bind_and_key_merging() {
+        let config = server(&[], Profile::default());
+        assert_eq!(config.bind, SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT)));
+        assert_eq!(config.key_file, PathBuf::from(DEFAULT_SERVER_KEY_FILE));
+
+        let profile = Profile { bind: Some("::1".parse().unwrap()), port: Some(9000), key: Some("servidor.json".into()), ..Profile::default() };
+        let config = server(&["--port", "9100"], profile);
+        assert_eq!(config.bind.to_string(), "[::1]:9100");
+        assert_eq!(config.key_file, PathBuf::from("servidor.json"));
+    }
+
+    #[test]
+    fn server_mailbox_limits() {
+        let profile = Profile { retention_hours: Some(2), quota: Some(5), ..Profile::default() };
+        let config = server(&["--quota", "9"], profile);
+        assert_eq!(config.mailbox.retention, Duration::from_secs(2 * 60 * 60));
+        assert_eq!(config.mailbox.quota, 9);
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        for args in [&["--key-bits", "8"][..], &["--key-bits", "33"], &["--port", "70000"], &["--ui", "plain", "--tui"], &["-v", "-q"]] {
+            assert!(Cli::try_parse_from(std::iter::once("chat_rsa").chain(args.iter().copied())).is_err(), "{:?}", args);
+        }
+    }
+}
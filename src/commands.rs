@@ -0,0 +1,154 @@
+/// Comando digitado no terminal, começando com `/`.
+///
+/// `action` identifica o comando para quem o executa; cada lado (cliente e
+/// servidor) tem seu próprio enum de ações e sua própria tabela de comandos.
+pub struct Command<A> {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub action: A,
+}
+
+/// Uma linha digitada, já classificada.
+pub enum Input<'a, A> {
+    /// Texto comum, para ser enviado como mensagem.
+    Text(&'a str),
+    /// Comando conhecido, com o resto da linha como argumentos (já sem espaços nas pontas).
+    Command(&'a Command<A>, &'a str),
+    /// Linha começando com `/` que não corresponde a nenhum comando.
+    Unknown(&'a str),
+}
+
+/// Ações disponíveis no terminal do cliente.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAction {
+    Help,
+    Quit,
+    Nick,
+    Join,
+    Who,
+    Fingerprint,
+    Msg,
+    History,
+    Search,
+    Send,
+    Accept,
+    Decline,
+    Inspect,
+}
+
+/// Ações disponíveis no console do servidor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerAction {
+    Help,
+    List,
+    To,
+    All,
+    Kick,
+    Tamper,
+    Shutdown,
+    Inspect,
+}
+
+/// Comandos do cliente. Para criar um comando novo, basta acrescentá-lo aqui e
+/// tratar a ação correspondente no laço de entrada do cliente.
+pub const CLIENT_COMMANDS: &[Command<ClientAction>] = &[
+    Command { name: "help", usage: "/help", help: "mostra esta lista de comandos", action: ClientAction::Help },
+    Command { name: "quit", usage: "/quit", help: "encerra a conversa", action: ClientAction::Quit },
+    Command { name: "nick", usage: "/nick <apelido>", help: "troca o seu apelido", action: ClientAction::Nick },
+    Command { name: "join", usage: "/join <sala>", help: "muda para outra sala", action: ClientAction::Join },
+    Command { name: "msg", usage: "/msg <apelido> <texto>", help: "envia uma mensagem privada, cifrada de ponta a ponta", action: ClientAction::Msg },
+    Command { name: "who", usage: "/who", help: "lista os participantes da sala atual", action: ClientAction::Who },
+    Command { name: "fingerprint", usage: "/fingerprint", help: "mostra as impressões digitais da sua chave e da do servidor", action: ClientAction::Fingerprint },
+    Command { name: "history", usage: "/history [página]", help: "mostra o histórico local das conversas, da página mais recente (1) para trás", action: ClientAction::History },
+    Command { name: "search", usage: "/search <termo>", help: "procura o termo em todas as conversas do histórico local", action: ClientAction::Search },
+    Command { name: "send", usage: "/send <apelido> <caminho>", help: "oferece um arquivo ao participante, cifrado de ponta a ponta", action: ClientAction::Send },
+    Command { name: "accept", usage: "/accept <número>", help: "aceita a oferta de arquivo com esse número", action: ClientAction::Accept },
+    Command { name: "decline", usage: "/decline <número>", help: "recusa a oferta de arquivo com esse número", action: ClientAction::Decline },
+    Command { name: "inspect", usage: "/inspect", help: "liga ou desliga o inspetor com os passos criptográficos de cada mensagem (F2 na tela cheia)", action: ClientAction::Inspect },
+];
+
+/// Comandos do console do servidor. Texto sem `/` vai para o destino escolhido com `/to`.
+pub const SERVER_COMMANDS: &[Command<ServerAction>] = &[
+    Command { name: "help", usage: "/help", help: "mostra esta lista de comandos", action: ServerAction::Help },
+    Command { name: "list", usage: "/list", help: "lista as conexões ativas", action: ServerAction::List },
+    Command { name: "to", usage: "/to [apelido]", help: "envia as próximas mensagens só para o cliente (sem apelido: para todos)", action: ServerAction::To },
+    Command { name: "all", usage: "/all <texto>", help: "envia uma mensagem para todos os clientes", action: ServerAction::All },
+    Command { name: "kick", usage: "/kick <apelido> [motivo]", help: "desconecta o cliente", action: ServerAction::Kick },
+    Command { name: "testar", usage: "/testar <texto>", help: "envia o texto adulterado depois de calculado o hash/MAC, para testar a verificação", action: ServerAction::Tamper },
+    Command { name: "inspect", usage: "/inspect", help: "liga ou desliga o inspetor com os passos criptográficos de cada mensagem (F2 no painel)", action: ServerAction::Inspect },
+    Command { name: "shutdown", usage: "/shutdown [motivo]", help: "avisa todos os clientes e desliga o servidor", action: ServerAction::Shutdown },
+];
+
+/// Classifica uma linha digitada usando a tabela de comandos `registry`.
+///
+/// Linhas começando com `//` são texto comum que começa com uma barra.
+pub fn parse<'a, A>(registry: &'a [Command<A>], line: &'a str) -> Input<'a, A> {
+    let Some(rest) = line.strip_prefix('/') else { return Input::Text(line) };
+    if rest.starts_with('/') {
+        return Input::Text(rest);
+    }
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    match registry.iter().find(|command| command.name.eq_ignore_ascii_case(name)) {
+        Some(command) => Input::Command(command, args.trim()),
+        None => Input::Unknown(name),
+    }
+}
+
+/// Texto de ajuda com todos os comandos da tabela, um por linha.
+pub fn help_text<A>(registry: &[Command<A>]) -> String {
+    let width = registry.iter().map(|command| command.usage.len()).max().unwrap_or(0);
+    let mut text = String::from("Comandos disponíveis:");
+    for command in registry {
+        text.push_str(&format!("\n  {:width$}  {}", command.usage, command.help, width = width));
+    }
+    text.push_str("\n  (para enviar um texto que começa com /, use //)");
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(line: &str) -> Option<(ClientAction, &str)> {
+        match parse(CLIENT_COMMANDS, line) {
+            Input::Command(command, args) => Some((command.action, args)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn commands_are_found_with_trimmed_args() {
+        assert_eq!(client("/quit"), Some((ClientAction::Quit, "")));
+        assert_eq!(client("/msg bia   oi, tudo bem?  "), Some((ClientAction::Msg, "bia   oi, tudo bem?")));
+        assert_eq!(client("/JOIN geral"), Some((ClientAction::Join, "geral")));
+        assert!(matches!(parse(SERVER_COMMANDS, "/shutdown manutenção"), Input::Command(command, "manutenção") if command.action == ServerAction::Shutdown));
+    }
+
+    #[test]
+    fn plain_and_escaped_lines_are_text() {
+        assert!(matches!(parse(CLIENT_COMMANDS, "oi /quit"), Input::Text("oi /quit")));
+        assert!(matches!(parse(CLIENT_COMMANDS, "//quit"), Input::Text("/quit")));
+        assert!(matches!(parse(CLIENT_COMMANDS, ""), Input::Text("")));
+    }
+
+    #[test]
+    fn unknown_commands_are_reported() {
+        assert!(matches!(parse(CLIENT_COMMANDS, "/voar alto"), Input::Unknown("voar")));
+        assert!(matches!(parse(CLIENT_COMMANDS, "/"), Input::Unknown("")));
+        // Cada lado só conhece a própria tabela.
+        assert!(matches!(parse(CLIENT_COMMANDS, "/kick bia"), Input::Unknown("kick")));
+        assert!(matches!(parse(SERVER_COMMANDS, "/nick bia"), Input::Unknown("nick")));
+    }
+
+    fn lists_every_command<A>(registry: &[Command<A>]) -> bool {
+        let help = help_text(registry);
+        registry.iter().all(|command| help.contains(command.usage) && help.contains(command.help)) && help.contains("//")
+    }
+
+    #[test]
+    fn help_lists_every_command() {
+        assert!(lists_every_command(CLIENT_COMMANDS));
+        assert!(lists_every_command(SERVER_COMMANDS));
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use crate::handshake::CipherAlgorithm;
+use crate::rsa::{MAX_KEY_BITS, MIN_KEY_BITS};
+use crate::ui::{Theme, UiMode};
+
+/// Arquivo lido quando `--config` não é dado, se existir no diretório atual.
+pub const DEFAULT_CONFIG_FILE: &str = "chat_rsa.json";
+
+/// Perfil usado quando nem `--profile` nem `default-profile` escolhem outro.
+const DEFAULT_PROFILE: &str = "default";
+
+// Conteúdo do arquivo de configuração
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    default_profile: Option<String>,
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// Valores de um perfil do arquivo de configuração.
+///
+/// As chaves têm os mesmos nomes das opções da linha de comando, que têm
+/// prioridade sobre elas; o que ficar sem valor nos dois usa o padrão do programa.
+/// Caminhos relativos são resolvidos a partir do diretório do arquivo.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub bind: Option<IpAddr>,
+    pub relay: Option<bool>,
+    pub retention_hours: Option<u64>,
+    pub quota: Option<usize>,
+    pub key: Option<PathBuf>,
+    pub key_bits: Option<u32>,
+    pub identity: Option<PathBuf>,
+    pub history: Option<PathBuf>,
+    pub known_hosts: Option<PathBuf>,
+    pub nickname: Option<String>,
+    /// Cifras aceitas, em ordem de preferência.
+    pub ciphers: Option<Vec<CipherAlgorithm>>,
+    pub ui: Option<UiMode>,
+    pub theme: Option<Theme>,
+}
+
+impl Profile {
+    // Caminhos relativos passam a ser relativos ao diretório do arquivo de configuração
+    fn resolve_paths(&mut self, base: &Path) {
+        for path in [&mut self.key, &mut self.identity, &mut self.history, &mut self.known_hosts].into_iter().flatten() {
+            if path.is_relative() {
+                *path = base.join(&*path);
+            }
+        }
+    }
+}
+
+/// Lê o perfil escolhido do arquivo de configuração.
+///
+/// Sem `path`, usa `chat_rsa.json` do diretório atual se existir, e nenhum perfil
+/// (só os padrões) caso contrário. O perfil é o de `profile`, ou o indicado em
+/// `default-profile`, ou o chamado `default`, nessa ordem; um perfil pedido que não
+/// existe no arquivo é um erro.
+pub fn load(path: Option<&Path>, profile: Option<&str>) -> anyhow::Result<Profile> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None if Path::new(DEFAULT_CONFIG_FILE).exists() => PathBuf::from(DEFAULT_CONFIG_FILE),
+        None => match profile {
+            Some(name) => bail!("perfil {:?} pedido, mas não há arquivo de configuração ({} ou --config)", name, DEFAULT_CONFIG_FILE),
+            None => return Ok(Profile::default()),
+        },
+    };
+    let bytes = std::fs::read(&path).with_context(|| format!("não foi possível ler {}", path.display()))?;
+    let mut file: ConfigFile = serde_json::from_slice(&bytes).with_context(|| format!("configuração inválida em {}", path.display()))?;
+
+    let name = match profile.or(file.default_profile.as_deref()) {
+        Some(name) => name.to_string(),
+        None if file.profiles.contains_key(DEFAULT_PROFILE) => DEFAULT_PROFILE.to_string(),
+        None => return Ok(Profile::default()),
+    };
+    let Some(mut chosen) = file.profiles.remove(&name) else {
+        let available = file.profiles.keys().cloned().collect::<Vec<_>>().join(", ");
+        bail!("perfil {:?} não existe em {} (perfis: {})", name, path.display(), if available.is_empty() { "nenhum" } else { &available });
+    };
+    if let Some(bits) = chosen.key_bits {
+        if !(MIN_KEY_BITS..=MAX_KEY_BITS).contains(&bits) {
+            bail!("key-bits do perfil {:?} deve estar entre {} e {}", name, MIN_KEY_BITS, MAX_KEY_BITS);
+        }
+    }
+    if let Some(base) = path.parent() {
+        chosen.resolve_paths(base);
+    }
+    Ok(chosen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Arquivo de configuração temporário, apagado ao fim do teste
+    struct TempConfig(PathBuf);
+
+    impl TempConfig {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("chat_rsa_config_{}_{}.json", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            TempConfig(path)
+        }
+    }
+
+    impl Drop for TempConfig {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    const PROFILES: &str = r#"{
+        "default-profile": "casa",
+        "profiles": {
+            "casa": { "host": "::1", "port": 9000, "identity": "alice.json", "ciphers": ["rsa-block"] },
+            "trabalho": { "host": "chat.exemplo.org", "known-hosts": "/etc/conhecidos" }
+        }
+    }"#;
+
+    #[test]
+    fn loads_the_chosen_profile() {
+        let config = TempConfig::new("perfis", PROFILES);
+        let casa = load(Some(&config.0), None).unwrap();
+        assert_eq!((casa.host.as_deref(), casa.port), (Some("::1"), Some(9000)));
+        assert_eq!(casa.ciphers, Some(vec![CipherAlgorithm::RsaBlock]));
+        // Caminhos relativos ficam relativos ao arquivo; absolutos não mudam.
+        assert_eq!(casa.identity, Some(config.0.parent().unwrap().join("alice.json")));
+
+        let trabalho = load(Some(&config.0), Some("trabalho")).unwrap();
+        assert_eq!(trabalho.host.as_deref(), Some("chat.exemplo.org"));
+        assert_eq!(trabalho.known_hosts, Some(PathBuf::from("/etc/conhecidos")));
+    }
+
+    #[test]
+    fn missing_profile_is_an_error() {
+        let config = TempConfig::new("sem_perfil", PROFILES);
+        let error = load(Some(&config.0), Some("praia")).unwrap_err().to_string();
+        assert!(error.contains("casa, trabalho"), "{}", error);
+    }
+
+    #[test]
+    fn no_profile_chosen_means_defaults() {
+        let config = TempConfig::new("vazio", r#"{ "profiles": { "outro": { "port": 1 } } }"#);
+        assert!(load(Some(&config.0), None).unwrap().port.is_none());
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let unknown = TempConfig::new("desconhecida", r#"{ "profiles": { "default": { "porta": 1 } } }"#);
+        assert!(load(Some(&unknown.0), None).is_err());
+        let bits = TempConfig::new("bits", r#"{ "profiles": { "default": { "key-bits": 64 } } }"#);
+        assert!(load(Some(&bits.0), None).is_err());
+    }
+}
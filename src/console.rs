@@ -0,0 +1,127 @@
+use std::sync::Arc;
+use tokio::sync::Notify;
+use crate::commands::{self, Input, ServerAction, SERVER_COMMANDS};
+use crate::dashboard::{format_bytes, format_idle};
+use crate::hub::{ClientId, Delivery, Hub};
+use crate::mailbox;
+use crate::ui::{InputSource, ServerUi, Tone};
+
+/// Console do operador: a única tarefa que lê o teclado do servidor.
+///
+/// Texto comum vai para o destino atual (um cliente escolhido com `/to`, ou
+/// todos); os comandos listam, desconectam e desligam. As mensagens seguem
+/// pelo `hub`, e cada conexão as cifra com a própria sessão. `/shutdown` (ou o
+/// Ctrl-C) avisa todos os clientes e dispara `shutdown`. Se o teclado acabar
+/// (stdin fechado, como em um serviço ou contêiner), o servidor segue atendendo
+/// até o Ctrl-C.
+///
+/// As linhas chegam por `lines` (o teclado ou o painel) e as respostas saem por `ui`.
+pub async fn run(hub: Arc<Hub>, shutdown: Arc<Notify>, ui: ServerUi, mut lines: InputSource) {
+    // Destino do texto comum; `None` é todo mundo.
+    let mut target: Option<(ClientId, String)> = None;
+
+    ui.prompt();
+    loop {
+        let Some(line) = lines.next_line().await else {
+            if !lines.is_interrupted() {
+                ui.log(Tone::Info, "Console sem entrada; o servidor continua atendendo até o Ctrl-C.");
+                lines.interrupted().await;
+            }
+            hub.send_to_all(Delivery::Kicked { reason: "servidor encerrou a conversa".to_string() });
+            break;
+        };
+        let input = line.trim();
+        if input.is_empty() {
+            ui.prompt();
+            continue;
+        }
+
+        match commands::parse(SERVER_COMMANDS, input) {
+            Input::Text(text) => send(&hub, &ui, &mut target, text, false),
+            Input::Command(command, args) => match command.action {
+                ServerAction::Help => ui.log(Tone::Info, commands::help_text(SERVER_COMMANDS)),
+                ServerAction::List => list(&hub, &ui),
+                ServerAction::Inspect => ui.toggle_inspector(),
+                ServerAction::To if args.is_empty() => {
+                    target = None;
+                    ui.log(Tone::Info, "Mensagens agora vão para todos os clientes.");
+                }
+                ServerAction::To => match hub.id_of(args) {
+                    Some(id) => {
+                        ui.log(Tone::Info, format!("Mensagens agora vão só para {}.", args));
+                        target = Some((id, args.to_string()));
+                    }
+                    None => ui.log(Tone::Info, format!("Nenhum cliente com o apelido {}.", args)),
+                },
+                ServerAction::All if !args.is_empty() => send(&hub, &ui, &mut None, args, false),
+                // --- TESTE DE ALTERAÇÃO (Simulação de adulteração) ---
+                // Altera intencionalmente a mensagem criptografada depois de calculado
+                // o hash/MAC, para simular adulteração no caminho.
+                ServerAction::Tamper => send(&hub, &ui, &mut target, if args.is_empty() { "testar" } else { args }, true),
+                ServerAction::Kick if !args.is_empty() => {
+                    let (nickname, reason) = args.split_once(char::is_whitespace).unwrap_or((args, "desconectado pelo operador"));
+                    match hub.id_of(nickname) {
+                        Some(id) => {
+                            hub.send_to(id, Delivery::Kicked { reason: reason.trim().to_string() });
+                            ui.client(id, Tone::Notice, format!("{} foi desconectado.", nickname));
+                        }
+                        None => ui.log(Tone::Info, format!("Nenhum cliente com o apelido {}.", nickname)),
+                    }
+                }
+                ServerAction::Shutdown => {
+                    let reason = if args.is_empty() { "servidor desligado pelo operador" } else { args };
+                    hub.send_to_all(Delivery::Kicked { reason: reason.to_string() });
+                    break;
+                }
+                ServerAction::All | ServerAction::Kick => ui.log(Tone::Info, format!("Uso: {}", command.usage)),
+            },
+            Input::Unknown(name) => ui.log(Tone::Info, format!("Comando desconhecido: /{}\n{}", name, commands::help_text(SERVER_COMMANDS))),
+        }
+        ui.prompt();
+    }
+    ui.log(Tone::Notice, "Desligando o servidor...");
+    shutdown.notify_one();
+}
+
+// Envia uma mensagem do operador ao destino; se o cliente escolhido saiu, volta para todos
+fn send(hub: &Hub, ui: &ServerUi, target: &mut Option<(ClientId, String)>, text: &str, tamper: bool) {
+    let delivery = Delivery::Operator { text: text.to_string(), tamper };
+    match target {
+        Some((id, nickname)) => {
+            if hub.send_to(*id, delivery) {
+                ui.operator(Some(*id), text);
+            } else {
+                ui.log(Tone::Warning, format!("{} não está mais conectado; mensagens agora vão para todos.", nickname));
+                *target = None;
+            }
+        }
+        None => {
+            hub.send_to_all(delivery);
+            ui.operator(None, text);
+        }
+    }
+}
+
+fn list(hub: &Hub, ui: &ServerUi) {
+    let clients = hub.clients();
+    if clients.is_empty() {
+        ui.log(Tone::Info, "Nenhum cliente conectado.");
+        return;
+    }
+    let now = mailbox::now();
+    let mut table = format!("{:<4} {:<20} {:<22} {:<12} {:<24} {:<10} {:<10} atividade", "id", "apelido", "endereço", "sala", "chave", "entrada", "saída");
+    for client in clients {
+        table.push_str(&format!(
+            "\n{:<4} {:<20} {:<22} {:<12} {:<24} {:<10} {:<10} {}",
+            client.id,
+            client.name,
+            client.addr,
+            client.room,
+            client.fingerprint,
+            format_bytes(client.bytes_in),
+            format_bytes(client.bytes_out),
+            format_idle(now.saturating_sub(client.last_activity)),
+        ));
+    }
+    ui.log(Tone::Info, table);
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState, Tabs};
+use ratatui::Frame;
+use tokio::sync::mpsc;
+use crate::hub::{ClientId, ClientInfo, Hub};
+use crate::mailbox;
+use crate::tui::{self, InputLine, MessagePane, Screen};
+use crate::ui::{DashboardEvent, Theme, Tone, SERVER_PROMPT};
+
+/// Linhas da tabela de clientes visíveis de uma vez.
+const MAX_TABLE_ROWS: usize = 8;
+
+/// Roda o painel do servidor até receber `DashboardEvent::Close`.
+///
+/// Mostra a tabela dos clientes conectados (consultada no `hub` a cada
+/// redesenho), uma aba com o log geral e uma aba por cliente com o que passou
+/// pela conexão dele. As linhas digitadas vão para o console do operador por `lines`.
+pub fn run(hub: Arc<Hub>, events: mpsc::UnboundedReceiver<DashboardEvent>, lines: mpsc::UnboundedSender<String>, theme: Theme) {
+    let mut dashboard = Dashboard::new(hub, lines, theme);
+    if let Err(e) = tui::setup().and_then(|mut terminal| {
+        let result = dashboard.event_loop(&mut terminal, events);
+        tui::restore();
+        result
+    }) {
+        tui::restore();
+        eprintln!("Erro no painel: {}", e);
+    }
+    tui::print_last(&dashboard.log);
+}
+
+struct Dashboard {
+    hub: Arc<Hub>,
+    theme: Theme,
+    log: Vec<(Tone, String)>,
+    conversations: HashMap<ClientId, Vec<(Tone, String)>>,
+    // Passos criptográficos de cada cliente, mostrados ao lado da aba dele
+    inspections: HashMap<ClientId, Vec<(Tone, String)>>,
+    inspector: MessagePane,
+    inspector_open: bool,
+    // Aba aberta: `None` é o log geral
+    tab: Option<ClientId>,
+    pane: MessagePane,
+    input: InputLine,
+    // Fechado com Ctrl-C: o console vê o fim da entrada e desliga o servidor
+    lines: Option<mpsc::UnboundedSender<String>>,
+}
+
+impl Dashboard {
+    fn new(hub: Arc<Hub>, lines: mpsc::UnboundedSender<String>, theme: Theme) -> Self {
+        Dashboard {
+            hub,
+            theme,
+            log: Vec::new(),
+            conversations: HashMap::new(),
+            inspections: HashMap::new(),
+            inspector: MessagePane::new(theme),
+            inspector_open: false,
+            tab: None,
+            pane: MessagePane::new(theme),
+            input: InputLine::default(),
+            lines: Some(lines),
+        }
+    }
+
+    fn event_loop(&mut self, terminal: &mut Screen, mut events: mpsc::UnboundedReceiver<DashboardEvent>) -> io::Result<()> {
+        loop {
+            loop {
+                match events.try_recv() {
+                    Ok(DashboardEvent::Log { client, tone, text }) => {
+                        if let Some(id) = client {
+                            self.conversations.entry(id).or_default().push((tone, text.clone()));
+                        }
+                        self.log.push((tone, text));
+                    }
+                    Ok(DashboardEvent::Operator { to, text }) => {
+                        let line = (Tone::Info, format!("Operador: {}", text));
+                        match to {
+                            Some(id) => self.conversations.entry(id).or_default().push(line),
+                            None => {
+                                for client in self.hub.clients() {
+                                    self.conversations.entry(client.id).or_default().push(line.clone());
+                                }
+                            }
+                        }
+                    }
+                    Ok(DashboardEvent::Inspect { client, lines }) => {
+                        self.inspections.entry(client).or_default().push((Tone::Info, lines.join("\n")));
+                    }
+                    Ok(DashboardEvent::ToggleInspector) => self.inspector_open = !self.inspector_open,
+                    Ok(DashboardEvent::Close) | Err(mpsc::error::TryRecvError::Disconnected) => return Ok(()),
+                    Err(mpsc::error::TryRecvError::Empty) => break,
+                }
+            }
+            let clients = self.hub.clients();
+            // A aba de quem saiu fecha; o que ele fez continua no log geral.
+            self.conversations.retain(|id, _| clients.iter().any(|client| client.id == *id));
+            self.inspections.retain(|id, _| clients.iter().any(|client| client.id == *id));
+            if self.tab.is_some_and(|id| !clients.iter().any(|client| client.id == id)) {
+                self.tab = None;
+            }
+            terminal.draw(|frame| self.draw(frame, &clients))?;
+            if let Some(key) = tui::next_key()? {
+                self.key(key, &clients);
+            }
+        }
+    }
+
+    fn key(&mut self, key: KeyEvent, clients: &[ClientInfo]) {
+        if tui::is_quit(&key) {
+            self.lines = None;
+            return;
+        }
+        match key.code {
+            KeyCode::Tab => self.switch_tab(clients, 1),
+            KeyCode::BackTab => self.switch_tab(clients, clients.len()),
+            _ if tui::is_inspector_toggle(&key) => self.inspector_open = !self.inspector_open,
+            _ if self.inspector_open && tui::is_inspector_scroll(&key) => {
+                self.inspector.key(&key);
+            }
+            _ if self.pane.key(&key) => {}
+            _ => {
+                if let Some(line) = self.input.key(key, false) {
+                    self.log.push((Tone::Info, format!("{}{}", SERVER_PROMPT, line)));
+                    if let Some(lines) = &self.lines {
+                        let _ = lines.send(line);
+                    }
+                }
+            }
+        }
+    }
+
+    // Anda `step` abas para a direita, contando o log como a primeira
+    fn switch_tab(&mut self, clients: &[ClientInfo], step: usize) {
+        let current = self.tab.and_then(|id| clients.iter().position(|client| client.id == id)).map_or(0, |i| i + 1);
+        let next = (current + step) % (clients.len() + 1);
+        self.tab = next.checked_sub(1).map(|i| clients[i].id);
+        self.pane.follow();
+        self.inspector.follow();
+    }
+
+    fn draw(&mut self, frame: &mut Frame, clients: &[ClientInfo]) {
+        let table_height = clients.len().clamp(1, MAX_TABLE_ROWS) as u16 + 3;
+        let areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(table_height),
+                Constraint::Length(1),
+                Constraint::Min(3),
+                Constraint::Length(3),
+                Constraint::Length(1),
+            ])
+            .split(frame.size());
+
+        self.draw_clients(frame, areas[0], clients);
+
+        let selected = self.tab.and_then(|id| clients.iter().position(|client| client.id == id));
+        let mut titles = vec!["Log".to_string()];
+        titles.extend(clients.iter().map(|client| client.name.clone()));
+        let tabs = Tabs::new(titles)
+            .select(selected.map_or(0, |i| i + 1))
+            .highlight_style(tui::tab_style(self.theme));
+        frame.render_widget(tabs, areas[1]);
+
+        let (conversation, inspector) = tui::split_inspector(areas[2], self.inspector_open);
+        let empty = Vec::new();
+        match selected {
+            Some(i) => {
+                let title = format!("Conversa com {} ({})", clients[i].name, clients[i].addr);
+                let messages = self.conversations.get(&clients[i].id).unwrap_or(&empty);
+                self.pane.draw(frame, conversation, &title, messages);
+            }
+            None => self.pane.draw(frame, conversation, "Log do servidor", &self.log),
+        }
+        if let Some(area) = inspector {
+            // O inspetor acompanha a aba aberta; no log geral, só explica como usá-lo.
+            let hint = vec![(Tone::Info, "Abra a aba de um cliente (Tab) para ver os passos criptográficos da conexão dele.".to_string())];
+            let inspections = match selected {
+                Some(i) => self.inspections.get(&clients[i].id).unwrap_or(&empty),
+                None => &hint,
+            };
+            self.inspector.draw(frame, area, tui::INSPECTOR_TITLE, inspections);
+        }
+
+        self.input.draw(frame, areas[3], SERVER_PROMPT, false);
+        let help = " Tab/Shift-Tab troca de aba │ PgUp/PgDn rola │ F2 inspetor │ /help lista os comandos │ Ctrl-C desliga o servidor";
+        frame.render_widget(Paragraph::new(help).style(tui::bar_style(self.theme)), areas[4]);
+    }
+
+    fn draw_clients(&self, frame: &mut Frame, area: Rect, clients: &[ClientInfo]) {
+        let now = mailbox::now();
+        let header = Row::new(["id", "apelido", "endereço", "sala", "chave", "entrada", "saída", "atividade"])
+            .style(Style::default().add_modifier(Modifier::BOLD));
+        let rows = clients.iter().map(|client| {
+            Row::new([
+                Cell::from(client.id.to_string()),
+                Cell::from(client.name.clone()),
+                Cell::from(client.addr.to_string()),
+                Cell::from(client.room.clone()),
+                Cell::from(client.fingerprint.clone()),
+                Cell::from(format_bytes(client.bytes_in)),
+                Cell::from(format_bytes(client.bytes_out)),
+                Cell::from(format_idle(now.saturating_sub(client.last_activity))),
+            ])
+        });
+        let widths = [
+            Constraint::Length(4),
+            Constraint::Length(20),
+            Constraint::Length(22),
+            Constraint::Length(12),
+            Constraint::Length(24),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+        ];
+        let title = format!(" Clientes conectados ({}) ", clients.len());
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(tui::selected_style(self.theme));
+        let mut state = TableState::default();
+        state.select(self.tab.and_then(|id| clients.iter().position(|client| client.id == id)));
+        frame.render_stateful_widget(table, area, &mut state);
+    }
+}
+
+/// Tamanho legível: bytes, KiB ou MiB.
+pub fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// Tempo desde a última atividade, como "há 5s" ou "há 3min".
+pub fn format_idle(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("há {}s", secs),
+        60..=3599 => format!("há {}min", secs / 60),
+        _ => format!("há {}h", secs / 3600),
+    }
+}
@@ -0,0 +1,81 @@
+use rand::Rng;
+
+// Parâmetros do grupo Diffie-Hellman: o maior primo de 64 bits (2^64 - 59) e gerador 5.
+// Assim como o RSA deste projeto, é didático: um grupo desse tamanho não resiste a um atacante real.
+pub const P: u64 = 0xFFFF_FFFF_FFFF_FFC5;
+pub const G: u64 = 5;
+
+/// Par de chaves Diffie-Hellman efêmero.
+pub struct DhKeypair {
+    secret: u64,
+    pub public: u64,
+}
+
+impl DhKeypair {
+    /// Sorteia um expoente secreto e calcula `G^secret mod P`.
+    pub fn generate() -> Self {
+        let secret = rand::thread_rng().gen_range(2..P - 1);
+        DhKeypair { secret, public: pow_mod(G, secret, P) }
+    }
+
+    /// Calcula o segredo compartilhado a partir da chave pública do outro lado.
+    pub fn shared_secret(&self, peer_public: u64) -> u64 {
+        pow_mod(peer_public, self.secret, P)
+    }
+}
+
+/// Verifica se um valor público recebido está no intervalo válido do grupo.
+pub fn is_valid_public(value: u64) -> bool {
+    value > 1 && value < P - 1
+}
+
+// Multiplicação modular sem overflow, passando por u128
+fn mul_mod(a: u64, b: u64, modulo: u64) -> u64 {
+    ((a as u128 * b as u128) % modulo as u128) as u64
+}
+
+// Exponenciação modular rápida para módulos de 64 bits
+fn pow_mod(mut base: u64, mut exp: u64, modulo: u64) -> u64 {
+    let mut result = 1;
+    base %= modulo;
+
+    while exp > 0 {
+        if exp % 2 == 1 {
+            result = mul_mod(result, base, modulo);
+        }
+        base = mul_mod(base, base, modulo);
+        exp /= 2;
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_sides_derive_the_same_secret() {
+        let alice = DhKeypair::generate();
+        let bob = DhKeypair::generate();
+        assert!(is_valid_public(alice.public) && is_valid_public(bob.public));
+        assert_eq!(alice.shared_secret(bob.public), bob.shared_secret(alice.public));
+    }
+
+    #[test]
+    fn degenerate_public_values_are_rejected() {
+        for value in [0, 1, P - 1, P, u64::MAX] {
+            assert!(!is_valid_public(value), "{value}");
+        }
+        assert!(is_valid_public(2) && is_valid_public(P - 2));
+    }
+
+    #[test]
+    fn pow_mod_matches_small_cases() {
+        assert_eq!(pow_mod(5, 3, 13), 8);
+        assert_eq!(pow_mod(G, 0, P), 1);
+        // Pequeno teorema de Fermat: a^(P-1) = 1 (mod P), pois P é primo.
+        assert_eq!(pow_mod(G, P - 1, P), 1);
+        assert_eq!(pow_mod(u64::MAX, 2, P), mul_mod(u64::MAX % P, u64::MAX % P, P));
+    }
+}
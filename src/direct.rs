@@ -0,0 +1,136 @@
+use serde::{Serialize, Deserialize};
+use crate::cipher;
+use crate::rsa::{self, PrivateKey, PublicKey};
+use crate::session::Verdict;
+use crate::sha::sha256;
+
+/// Mensagem privada cifrada de ponta a ponta entre dois clientes.
+///
+/// O texto é cifrado com uma chave de uso único, encapsulada num bloco RSA
+/// sorteado para a chave pública do destinatário. O remetente assina o
+/// conjunto com a sua chave privada. O servidor só repassa os bytes: não tem
+/// como ler o texto nem forjar a assinatura.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirectMessage {
+    wrapped_key: u64,
+    nonce: u64,
+    ciphertext: Vec<u8>,
+    signature: u64,
+}
+
+impl DirectMessage {
+    /// Cifra o texto para `recipient` e o assina com a chave privada do remetente.
+    ///
+    /// Os dados associados `associated` (o contexto da mensagem, como a sala)
+    /// seguem às claras, mas entram na assinatura. Devolve `None` se a chave do
+    /// destinatário, vinda do diretório do servidor, for inválida.
+    pub fn seal(text: &str, associated: &[u8], recipient: &PublicKey, sender_priv_key: &PrivateKey) -> Option<Self> {
+        Self::seal_bytes(text.as_bytes(), associated, recipient, sender_priv_key)
+    }
+
+    /// Como `seal`, mas para bytes arbitrários (por exemplo, uma chave de grupo).
+    pub fn seal_bytes(data: &[u8], associated: &[u8], recipient: &PublicKey, sender_priv_key: &PrivateKey) -> Option<Self> {
+        if !recipient.is_valid() {
+            return None;
+        }
+        // r vem de [2, n), então a chave pode se repetir com chaves RSA pequenas;
+        // o nonce sorteado evita reaproveitar o fluxo mesmo nesse caso.
+        let (wrapped_key, key) = rsa::encapsulate(recipient);
+        let nonce: u64 = rand::random();
+        let mut ciphertext = data.to_vec();
+        cipher::apply_keystream(&key, nonce, &mut ciphertext);
+        let signature = rsa::sign(&signed_hash(recipient, associated, wrapped_key, nonce, &ciphertext), sender_priv_key);
+        Some(DirectMessage { wrapped_key, nonce, ciphertext, signature })
+    }
+
+    /// Confere a assinatura do remetente e decifra a mensagem.
+    ///
+    /// A assinatura cobre também a chave pública do destinatário, então uma
+    /// mensagem desviada para outro cliente é recusada como inválida.
+    pub fn open(&self, associated: &[u8], my_pub_key: &PublicKey, my_priv_key: &PrivateKey, sender_key: &PublicKey) -> (String, Verdict) {
+        match self.open_bytes(associated, my_pub_key, my_priv_key, sender_key) {
+            Some(plain) => match String::from_utf8(plain) {
+                Ok(text) => (text, Verdict::Valid),
+                Err(e) => (e.to_string(), Verdict::InvalidUtf8),
+            },
+            None => (String::new(), Verdict::Invalid),
+        }
+    }
+
+    /// Como `open`, mas devolve os bytes decifrados; `None` se a assinatura não confere.
+    pub fn open_bytes(&self, associated: &[u8], my_pub_key: &PublicKey, my_priv_key: &PrivateKey, sender_key: &PublicKey) -> Option<Vec<u8>> {
+        let hash = signed_hash(my_pub_key, associated, self.wrapped_key, self.nonce, &self.ciphertext);
+        if !rsa::verify(&hash, self.signature, sender_key) {
+            return None;
+        }
+        let key = rsa::decapsulate(self.wrapped_key, my_priv_key);
+        let mut plain = self.ciphertext.clone();
+        cipher::apply_keystream(&key, self.nonce, &mut plain);
+        Some(plain)
+    }
+
+    /// Tamanho do texto cifrado, para os registros do servidor.
+    pub fn ciphertext_len(&self) -> usize {
+        self.ciphertext.len()
+    }
+}
+
+// Hash assinado pelo remetente: destinatário, dados associados, chave encapsulada, nonce e texto cifrado
+fn signed_hash(recipient: &PublicKey, associated: &[u8], wrapped_key: u64, nonce: u64, ciphertext: &[u8]) -> [u8; 32] {
+    let bytes = bincode::serialize(&(recipient, associated, wrapped_key, nonce, ciphertext)).expect("mensagem serializável");
+    sha256(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recipient_opens_signed_message() {
+        let (ana_pub, ana_priv) = rsa::new_keypair(None);
+        let (bia_pub, bia_priv) = rsa::new_keypair(None);
+        let message = DirectMessage::seal("olá, Bia ✓", b"sala", &bia_pub, &ana_priv).unwrap();
+        assert_eq!(message.open(b"sala", &bia_pub, &bia_priv, &ana_pub), ("olá, Bia ✓".to_string(), Verdict::Valid));
+    }
+
+    #[test]
+    fn wrong_recipient_sender_or_context_is_invalid() {
+        let (ana_pub, ana_priv) = rsa::new_keypair(None);
+        let (bia_pub, bia_priv) = rsa::new_keypair(None);
+        let (caio_pub, caio_priv) = rsa::new_keypair(None);
+        let message = DirectMessage::seal("oi", b"sala", &bia_pub, &ana_priv).unwrap();
+        assert_eq!(message.open(b"sala", &caio_pub, &caio_priv, &ana_pub).1, Verdict::Invalid);
+        assert_eq!(message.open(b"sala", &bia_pub, &bia_priv, &caio_pub).1, Verdict::Invalid);
+        assert_eq!(message.open(b"outra", &bia_pub, &bia_priv, &ana_pub).1, Verdict::Invalid);
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_invalid() {
+        let (ana_pub, ana_priv) = rsa::new_keypair(None);
+        let (bia_pub, bia_priv) = rsa::new_keypair(None);
+        let mut message = DirectMessage::seal("oi", &[], &bia_pub, &ana_priv).unwrap();
+        message.ciphertext[0] ^= 1;
+        assert_eq!(message.open(&[], &bia_pub, &bia_priv, &ana_pub).1, Verdict::Invalid);
+    }
+
+    #[test]
+    fn each_message_gets_a_fresh_wrapped_key() {
+        let (ana_pub, ana_priv) = rsa::new_keypair(None);
+        let (bia_pub, bia_priv) = rsa::new_keypair(Some(rsa::MAX_KEY_BITS));
+        let first = DirectMessage::seal("oi", &[], &bia_pub, &ana_priv).unwrap();
+        let second = DirectMessage::seal("oi", &[], &bia_pub, &ana_priv).unwrap();
+        assert_ne!(first.wrapped_key, second.wrapped_key);
+        assert_ne!(first.ciphertext, second.ciphertext);
+        for message in [first, second] {
+            assert_eq!(message.open(&[], &bia_pub, &bia_priv, &ana_pub), ("oi".to_string(), Verdict::Valid));
+        }
+    }
+
+    #[test]
+    fn invalid_recipient_keys_are_refused() {
+        let (_, ana_priv) = rsa::new_keypair(None);
+        for recipient in [PublicKey { e: 3, n: 200 }, PublicKey { e: 1, n: 3233 }, PublicKey { e: 3, n: u64::MAX }] {
+            assert!(DirectMessage::seal("oi", &[], &recipient, &ana_priv).is_none());
+        }
+    }
+}
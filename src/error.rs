@@ -0,0 +1,80 @@
+use std::fmt;
+use std::io;
+use crate::protocol::{ErrorCode, ReadError};
+
+/// Falha que encerra uma conexão, ou que impede o servidor ou o cliente de começar.
+///
+/// Nenhuma delas derruba o processo: o servidor registra a falha da conexão e
+/// continua atendendo as outras, e o cliente mostra a mensagem ao sair.
+#[derive(Debug)]
+pub enum ChatError {
+    /// Falha de rede ou de arquivo, inclusive o outro lado fechar a conexão.
+    Io(io::Error),
+    /// O outro lado não seguiu o protocolo: pacote inesperado, recusado ou fora de ordem.
+    Protocol(String),
+    /// Falha criptográfica: nenhuma suíte em comum ou valores inválidos na troca de chaves.
+    Crypto(String),
+    /// O outro lado não provou quem é: assinatura inválida ou chave diferente da conhecida.
+    Auth(String),
+}
+
+impl ChatError {
+    /// Erro de E/S com o que se tentava fazer, como "não foi possível escutar em ...".
+    pub fn io(context: impl fmt::Display, error: io::Error) -> Self {
+        ChatError::Io(io::Error::new(error.kind(), format!("{}: {}", context, error)))
+    }
+
+    /// Recusa enviada pelo outro lado em um `Packet::Error` durante o handshake.
+    pub fn refused(code: ErrorCode, message: String) -> Self {
+        let reason = format!("recusado pelo outro lado ({}): {}", code, message);
+        match code {
+            ErrorCode::NoCommonSuite => ChatError::Crypto(reason),
+            ErrorCode::AuthenticationFailed | ErrorCode::InvalidKey => ChatError::Auth(reason),
+            _ => ChatError::Protocol(reason),
+        }
+    }
+
+    /// Se a conexão só foi fechada ou derrubada, sem outra falha.
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            ChatError::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::BrokenPipe
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            _ if self.is_disconnect() => write!(f, "conexão fechada pelo outro lado"),
+            ChatError::Io(e) => write!(f, "{}", e),
+            ChatError::Protocol(message) => write!(f, "erro de protocolo: {}", message),
+            ChatError::Crypto(message) => write!(f, "erro criptográfico: {}", message),
+            ChatError::Auth(message) => write!(f, "falha de autenticação: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ChatError {}
+
+impl From<io::Error> for ChatError {
+    fn from(error: io::Error) -> Self {
+        // Dados inválidos na conexão são frames malformados, não falhas de rede.
+        match error.kind() {
+            io::ErrorKind::InvalidData => ChatError::Protocol(format!("frame inválido: {}", error)),
+            _ => ChatError::Io(error),
+        }
+    }
+}
+
+impl From<ReadError> for ChatError {
+    fn from(error: ReadError) -> Self {
+        match error {
+            ReadError::Io(e) => ChatError::from(e),
+            ReadError::Rejected { id, code } => ChatError::Protocol(format!("pacote #{} recusado: {}", id, code)),
+        }
+    }
+}
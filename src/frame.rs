@@ -0,0 +1,136 @@
+use std::io::{Error, ErrorKind};
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Tamanho máximo padrão de um frame (1 MiB).
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Codec de frames com prefixo de tamanho usado pelo servidor e pelo cliente.
+///
+/// Cada frame é composto por um cabeçalho de 4 bytes (big-endian) com o tamanho do
+/// payload, seguido do payload serializado com bincode. Assim uma mensagem nunca
+/// depende de como o TCP agrupou ou quebrou os segmentos.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameCodec {
+    max_frame_size: usize,
+}
+
+impl FrameCodec {
+    /// Cria um codec que rejeita frames maiores que `max_frame_size` bytes.
+    pub fn new(max_frame_size: usize) -> Self {
+        FrameCodec { max_frame_size }
+    }
+
+    /// Lê um frame completo do fluxo e devolve o payload ainda serializado.
+    ///
+    /// Frames acima do tamanho máximo são rejeitados antes de qualquer alocação.
+    /// A desserialização fica com quem chama, que pode inspecionar o payload antes.
+    pub async fn read_frame_bytes<R>(&self, reader: &mut R) -> Result<Vec<u8>, Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        // Lê o cabeçalho com o tamanho do payload.
+        let mut len_buf = [0u8; 4];
+        reader.read_exact(&mut len_buf).await?;
+        let len = u32::from_be_bytes(len_buf) as usize;
+
+        if len > self.max_frame_size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("frame de {} bytes excede o máximo de {} bytes", len, self.max_frame_size),
+            ));
+        }
+
+        // Lê exatamente `len` bytes, mesmo que cheguem em vários segmentos.
+        let mut payload = vec![0u8; len];
+        reader.read_exact(&mut payload).await?;
+        Ok(payload)
+    }
+
+    /// Serializa `value` e escreve o frame (cabeçalho + payload) no fluxo.
+    pub async fn write_frame<W, T>(&self, writer: &mut W, value: &T) -> Result<(), Error>
+    where
+        W: AsyncWrite + Unpin,
+        T: Serialize,
+    {
+        let payload = bincode::serialize(value).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        if payload.len() > self.max_frame_size {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("frame de {} bytes excede o máximo de {} bytes", payload.len(), self.max_frame_size),
+            ));
+        }
+
+        writer.write_all(&(payload.len() as u32).to_be_bytes()).await?;
+        writer.write_all(&payload).await?;
+        writer.flush().await
+    }
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        FrameCodec::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn frame_roundtrip() {
+        let codec = FrameCodec::default();
+        let mut wire = Vec::new();
+        codec.write_frame(&mut wire, &"olá, mundo".to_string()).await.unwrap();
+        let payload = codec.read_frame_bytes(&mut wire.as_slice()).await.unwrap();
+        assert_eq!(bincode::deserialize::<String>(&payload).unwrap(), "olá, mundo");
+    }
+
+    #[tokio::test]
+    async fn frame_split_across_reads() {
+        let codec = FrameCodec::default();
+        // Um canal de 1 byte entrega o frame aos pedaços.
+        let (mut writer, mut reader) = tokio::io::duplex(1);
+        let message = vec![7u8; 300];
+        let sent = message.clone();
+        tokio::spawn(async move { codec.write_frame(&mut writer, &sent).await.unwrap() });
+        let payload = codec.read_frame_bytes(&mut reader).await.unwrap();
+        assert_eq!(bincode::deserialize::<Vec<u8>>(&payload).unwrap(), message);
+    }
+
+    #[tokio::test]
+    async fn frame_at_limit_accepted() {
+        let codec = FrameCodec::new(16);
+        let mut wire = 16u32.to_be_bytes().to_vec();
+        wire.extend_from_slice(&[1; 16]);
+        assert_eq!(codec.read_frame_bytes(&mut wire.as_slice()).await.unwrap(), [1; 16]);
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_rejected_on_read() {
+        let codec = FrameCodec::new(16);
+        // Só o cabeçalho: o payload nem chega a ser lido.
+        let wire = 17u32.to_be_bytes();
+        let error = codec.read_frame_bytes(&mut wire.as_slice()).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_rejected_on_write() {
+        let codec = FrameCodec::new(16);
+        let mut wire = Vec::new();
+        let error = codec.write_frame(&mut wire, &vec![0u8; 32]).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(wire.is_empty());
+    }
+
+    #[tokio::test]
+    async fn truncated_frame_is_an_error() {
+        let codec = FrameCodec::default();
+        let mut wire = 10u32.to_be_bytes().to_vec();
+        wire.extend_from_slice(&[0; 4]);
+        let error = codec.read_frame_bytes(&mut wire.as_slice()).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+}
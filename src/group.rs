@@ -0,0 +1,235 @@
+use std::collections::{HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+use crate::cipher;
+use crate::rsa::{self, PrivateKey, PublicKey};
+use crate::session::Verdict;
+use crate::sha::sha256;
+
+/// Quantas mensagens de um remetente podem ser puladas de uma vez antes de a
+/// mensagem ser considerada fora da janela.
+const MAX_SKIP: u32 = 1000;
+
+/// Mensagem para a sala cifrada uma única vez com a chave de remetente de quem
+/// envia, e assinada com a chave RSA dele.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupMessage {
+    key_id: u64,
+    iteration: u32,
+    ciphertext: Vec<u8>,
+    signature: u64,
+}
+
+impl GroupMessage {
+    /// Tamanho do texto cifrado, para os registros do servidor.
+    pub fn ciphertext_len(&self) -> usize {
+        self.ciphertext.len()
+    }
+}
+
+/// Estado de uma cadeia de chaves: cada mensagem usa uma chave derivada da
+/// cadeia, que então avança. Quem tem a cadeia na iteração `i` consegue ler as
+/// mensagens de `i` em diante, mas não as anteriores.
+#[derive(Serialize, Deserialize, Clone)]
+struct Chain {
+    key_id: u64,
+    chain_key: [u8; 32],
+    iteration: u32,
+}
+
+impl Chain {
+    fn generate() -> Self {
+        Chain { key_id: rand::random(), chain_key: rand::random(), iteration: 0 }
+    }
+
+    // Chave da mensagem atual; a cadeia avança para a próxima iteração
+    fn next_message_key(&mut self) -> [u8; 32] {
+        let message_key = cipher::derive_key(&self.chain_key, "mensagem");
+        self.chain_key = cipher::derive_key(&self.chain_key, "corrente");
+        self.iteration += 1;
+        message_key
+    }
+}
+
+// Cadeia recebida de outro participante, junto com a impressão digital da chave que a enviou
+struct ReceivedChain {
+    owner: String,
+    chain: Chain,
+}
+
+/// Chaves de remetente de uma sala, do ponto de vista de um participante.
+///
+/// A própria cadeia é entregue a cada outro participante uma vez, por mensagem
+/// privada de ponta a ponta; daí em diante cada mensagem para a sala é cifrada
+/// uma vez só. Quando alguém sai, a cadeia é trocada por uma nova, que só os
+/// que ficaram recebem.
+pub struct GroupSession {
+    room: String,
+    own: Chain,
+    distributed: HashSet<String>,
+    received: HashMap<u64, ReceivedChain>,
+}
+
+impl GroupSession {
+    pub fn new(room: &str) -> Self {
+        GroupSession { room: room.to_string(), own: Chain::generate(), distributed: HashSet::new(), received: HashMap::new() }
+    }
+
+    pub fn room(&self) -> &str {
+        &self.room
+    }
+
+    /// Troca a própria cadeia por uma nova, que terá de ser entregue de novo a todos.
+    pub fn rotate(&mut self) {
+        self.own = Chain::generate();
+        self.distributed.clear();
+    }
+
+    /// Cópia serializada da própria cadeia para o participante com a chave de
+    /// impressão digital `fingerprint`, ou `None` se ele já a recebeu.
+    pub fn distribution_for(&mut self, fingerprint: &str) -> Option<Vec<u8>> {
+        if !self.distributed.insert(fingerprint.to_string()) {
+            return None;
+        }
+        Some(bincode::serialize(&self.own).expect("cadeia serializável"))
+    }
+
+    /// Guarda a cadeia recebida de um participante, descartando as anteriores dele.
+    pub fn accept_distribution(&mut self, owner: &str, data: &[u8]) -> bool {
+        let Ok(chain) = bincode::deserialize::<Chain>(data) else { return false };
+        self.received.retain(|_, received| received.owner != owner);
+        self.received.insert(chain.key_id, ReceivedChain { owner: owner.to_string(), chain });
+        true
+    }
+
+    /// Cifra o texto com a próxima chave da própria cadeia e o assina.
+    pub fn encrypt(&mut self, text: &str, my_priv_key: &PrivateKey) -> GroupMessage {
+        let iteration = self.own.iteration;
+        let message_key = self.own.next_message_key();
+        let mut ciphertext = text.as_bytes().to_vec();
+        cipher::apply_keystream(&message_key, iteration as u64, &mut ciphertext);
+        let hash = signed_hash(&self.room, self.own.key_id, iteration, &ciphertext);
+        let signature = rsa::sign(&hash, my_priv_key);
+        GroupMessage { key_id: self.own.key_id, iteration, ciphertext, signature }
+    }
+
+    /// Confere a assinatura e decifra uma mensagem da sala.
+    ///
+    /// A cadeia usada precisa ter sido entregue pela mesma chave que assinou a
+    /// mensagem. Só mensagens autênticas avançam a cadeia; iterações já usadas
+    /// são tratadas como repetição.
+    pub fn decrypt(&mut self, message: &GroupMessage, sender_key: &PublicKey) -> (String, Verdict) {
+        let hash = signed_hash(&self.room, message.key_id, message.iteration, &message.ciphertext);
+        let Some(received) = self.received.get_mut(&message.key_id) else {
+            return ("<chave de remetente desconhecida>".to_string(), Verdict::Invalid);
+        };
+        if received.owner != sender_key.fingerprint() || !rsa::verify(&hash, message.signature, sender_key) {
+            return (String::new(), Verdict::Invalid);
+        }
+        if message.iteration < received.chain.iteration {
+            return (String::new(), Verdict::Replayed);
+        }
+        if message.iteration - received.chain.iteration > MAX_SKIP {
+            return (String::new(), Verdict::OutOfWindow);
+        }
+
+        while received.chain.iteration < message.iteration {
+            received.chain.next_message_key();
+        }
+        let message_key = received.chain.next_message_key();
+        let mut plain = message.ciphertext.clone();
+        cipher::apply_keystream(&message_key, message.iteration as u64, &mut plain);
+        match String::from_utf8(plain) {
+            Ok(text) => (text, Verdict::Valid),
+            Err(e) => (e.to_string(), Verdict::InvalidUtf8),
+        }
+    }
+}
+
+// Hash assinado pelo remetente: sala, identificador da cadeia, iteração e texto cifrado
+fn signed_hash(room: &str, key_id: u64, iteration: u32, ciphertext: &[u8]) -> [u8; 32] {
+    let bytes = bincode::serialize(&(room, key_id, iteration, ciphertext)).expect("mensagem serializável");
+    sha256(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sessões da sala para quem envia e para quem recebe, com a cadeia de quem envia já entregue
+    fn sessions(sender_key: &PublicKey) -> (GroupSession, GroupSession) {
+        let mut sender = GroupSession::new("geral");
+        let mut receiver = GroupSession::new("geral");
+        let chain = sender.distribution_for("receptor").unwrap();
+        assert!(receiver.accept_distribution(&sender_key.fingerprint(), &chain));
+        (sender, receiver)
+    }
+
+    #[test]
+    fn chain_is_distributed_once_per_member() {
+        let mut session = GroupSession::new("geral");
+        assert!(session.distribution_for("ana").is_some());
+        assert!(session.distribution_for("ana").is_none());
+        session.rotate();
+        assert!(session.distribution_for("ana").is_some());
+    }
+
+    #[test]
+    fn messages_decrypt_once_in_any_order() {
+        let (ana_pub, ana_priv) = rsa::new_keypair(None);
+        let (mut ana, mut bia) = sessions(&ana_pub);
+        let first = ana.encrypt("primeira", &ana_priv);
+        let second = ana.encrypt("segunda", &ana_priv);
+        assert_eq!(bia.decrypt(&second, &ana_pub), ("segunda".to_string(), Verdict::Valid));
+        assert_eq!(bia.decrypt(&second, &ana_pub).1, Verdict::Replayed);
+        // A cadeia já passou da primeira: ela não pode mais ser lida.
+        assert_eq!(bia.decrypt(&first, &ana_pub).1, Verdict::Replayed);
+    }
+
+    #[test]
+    fn too_large_a_skip_is_out_of_window() {
+        let (ana_pub, ana_priv) = rsa::new_keypair(None);
+        let (mut ana, mut bia) = sessions(&ana_pub);
+        for _ in 0..=MAX_SKIP {
+            ana.encrypt("pulada", &ana_priv);
+        }
+        let late = ana.encrypt("tarde", &ana_priv);
+        assert_eq!(bia.decrypt(&late, &ana_pub).1, Verdict::OutOfWindow);
+    }
+
+    #[test]
+    fn only_the_chain_owner_can_sign() {
+        let (ana_pub, ana_priv) = rsa::new_keypair(None);
+        let (caio_pub, caio_priv) = rsa::new_keypair(None);
+        let (mut ana, mut bia) = sessions(&ana_pub);
+        let message = ana.encrypt("oi", &ana_priv);
+        assert_eq!(bia.decrypt(&message, &caio_pub).1, Verdict::Invalid);
+
+        // Assinada por outra chave, a mensagem não confere nem com a chave certa.
+        let hash = signed_hash("geral", message.key_id, message.iteration, &message.ciphertext);
+        let forged = GroupMessage { signature: rsa::sign(&hash, &caio_priv), ..message.clone() };
+        assert_eq!(bia.decrypt(&forged, &ana_pub).1, Verdict::Invalid);
+        assert_eq!(bia.decrypt(&message, &ana_pub).1, Verdict::Valid);
+    }
+
+    #[test]
+    fn messages_are_bound_to_the_room() {
+        let (ana_pub, ana_priv) = rsa::new_keypair(None);
+        let mut ana = GroupSession::new("geral");
+        let mut bia = GroupSession::new("outra");
+        bia.accept_distribution(&ana_pub.fingerprint(), &ana.distribution_for("bia").unwrap());
+        assert_eq!(bia.decrypt(&ana.encrypt("oi", &ana_priv), &ana_pub).1, Verdict::Invalid);
+    }
+
+    #[test]
+    fn rotated_chain_needs_a_new_distribution() {
+        let (ana_pub, ana_priv) = rsa::new_keypair(None);
+        let (mut ana, mut bia) = sessions(&ana_pub);
+        ana.rotate();
+        let chain = ana.distribution_for("bia").unwrap();
+        let message = ana.encrypt("nova", &ana_priv);
+        assert_eq!(bia.decrypt(&message, &ana_pub).1, Verdict::Invalid);
+        assert!(bia.accept_distribution(&ana_pub.fingerprint(), &chain));
+        assert_eq!(bia.decrypt(&message, &ana_pub), ("nova".to_string(), Verdict::Valid));
+        assert!(!bia.accept_distribution(&ana_pub.fingerprint(), b"lixo"));
+    }
+}
@@ -0,0 +1,750 @@
+use std::fmt;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+use crate::cipher;
+use crate::dh::{self, DhKeypair};
+use crate::error::ChatError;
+use crate::frame::FrameCodec;
+use crate::protocol::{self, Envelope, ErrorCode, Packet, ReadError, PROTOCOL_VERSION, SUPPORTED_VERSIONS};
+use crate::resumption::{Ticket, TicketId, TicketStore};
+use crate::rsa::{self, PrivateKey, PublicKey};
+use crate::session::{Role, Session};
+use crate::sha::sha256;
+
+/// Algoritmos de troca de chaves, do mais forte para o mais fraco.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyExchangeAlgorithm {
+    /// Diffie-Hellman efêmero: cada conexão gera um segredo novo.
+    DiffieHellman,
+    /// Cada lado encapsula metade do segredo num bloco RSA sorteado para a chave do outro.
+    RsaTransport,
+}
+
+/// Algoritmos de assinatura suportados.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureAlgorithm {
+    RsaSha256,
+}
+
+/// Cifras para as mensagens de chat, da mais forte para a mais fraca.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum CipherAlgorithm {
+    /// Cifra de fluxo SHA-256 em modo contador, autenticada com HMAC-SHA-256.
+    Sha256Ctr,
+    /// RSA aplicado byte a byte com a chave pública do outro lado.
+    RsaBlock,
+}
+
+/// Funções de hash suportadas.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+}
+
+/// Algoritmos anunciados por um lado do handshake, cada lista em ordem de preferência.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Offer {
+    pub versions: Vec<u16>,
+    pub key_exchanges: Vec<KeyExchangeAlgorithm>,
+    pub signatures: Vec<SignatureAlgorithm>,
+    pub ciphers: Vec<CipherAlgorithm>,
+    pub hashes: Vec<HashAlgorithm>,
+}
+
+/// Conjunto de algoritmos escolhido pelo servidor para a conexão.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CipherSuite {
+    pub version: u16,
+    pub key_exchange: KeyExchangeAlgorithm,
+    pub signature: SignatureAlgorithm,
+    pub cipher: CipherAlgorithm,
+    pub hash: HashAlgorithm,
+}
+
+impl Offer {
+    /// Tudo o que este binário suporta, do mais forte para o mais fraco.
+    pub fn supported() -> Self {
+        Offer {
+            versions: SUPPORTED_VERSIONS.iter().rev().copied().collect(),
+            key_exchanges: vec![KeyExchangeAlgorithm::DiffieHellman, KeyExchangeAlgorithm::RsaTransport],
+            signatures: vec![SignatureAlgorithm::RsaSha256],
+            ciphers: vec![CipherAlgorithm::Sha256Ctr, CipherAlgorithm::RsaBlock],
+            hashes: vec![HashAlgorithm::Sha256],
+        }
+    }
+
+    /// Tudo o que este binário suporta, mas só com as cifras dadas, na ordem dada.
+    ///
+    /// Uma lista vazia mantém todas as cifras suportadas.
+    pub fn with_ciphers(ciphers: &[CipherAlgorithm]) -> Self {
+        let mut offer = Offer::supported();
+        if !ciphers.is_empty() {
+            offer.ciphers.clear();
+            for cipher in ciphers {
+                if !offer.ciphers.contains(cipher) {
+                    offer.ciphers.push(*cipher);
+                }
+            }
+        }
+        offer
+    }
+
+    /// Escolhe, em cada categoria, o algoritmo mais forte que os dois lados suportam.
+    ///
+    /// `self` é a oferta local, que define a ordem de força. Falha com uma mensagem
+    /// clara na primeira categoria sem nenhum algoritmo em comum.
+    pub fn negotiate(&self, peer: &Offer) -> Result<CipherSuite, String> {
+        Ok(CipherSuite {
+            version: pick("versão de protocolo", &self.versions, &peer.versions)?,
+            key_exchange: pick("troca de chaves", &self.key_exchanges, &peer.key_exchanges)?,
+            signature: pick("assinatura", &self.signatures, &peer.signatures)?,
+            cipher: pick("cifra", &self.ciphers, &peer.ciphers)?,
+            hash: pick("hash", &self.hashes, &peer.hashes)?,
+        })
+    }
+
+    /// Verifica se a suíte escolhida pelo outro lado faz parte desta oferta.
+    fn allows(&self, suite: &CipherSuite) -> bool {
+        self.versions.contains(&suite.version)
+            && self.key_exchanges.contains(&suite.key_exchange)
+            && self.signatures.contains(&suite.signature)
+            && self.ciphers.contains(&suite.cipher)
+            && self.hashes.contains(&suite.hash)
+    }
+}
+
+// Primeiro item da lista local que também aparece na lista do outro lado
+fn pick<T: Copy + PartialEq + fmt::Debug>(category: &str, ours: &[T], theirs: &[T]) -> Result<T, String> {
+    ours.iter()
+        .find(|candidate| theirs.contains(candidate))
+        .copied()
+        .ok_or_else(|| format!("nenhum algoritmo de {} em comum (local: {:?}, remoto: {:?})", category, ours, theirs))
+}
+
+impl fmt::Display for CipherSuite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let key_exchange = match self.key_exchange {
+            KeyExchangeAlgorithm::DiffieHellman => "DH",
+            KeyExchangeAlgorithm::RsaTransport => "RSA",
+        };
+        let cipher = match self.cipher {
+            CipherAlgorithm::Sha256Ctr => "SHA256-CTR+HMAC",
+            CipherAlgorithm::RsaBlock => "RSA-BLOCO",
+        };
+        let signature = match self.signature {
+            SignatureAlgorithm::RsaSha256 => "RSA-SHA256",
+        };
+        let hash = match self.hash {
+            HashAlgorithm::Sha256 => "SHA-256",
+        };
+        write!(f, "v{} / {} / {} / {} / {}", self.version, key_exchange, signature, cipher, hash)
+    }
+}
+
+/// Transcrição do handshake: todos os pacotes trocados, serializados na ordem canônica.
+///
+/// Os dois lados registram exatamente os mesmos bytes, então o hash final só coincide
+/// se nada foi alterado, removido ou reaproveitado de outro handshake no caminho.
+#[derive(Default)]
+struct Transcript {
+    bytes: Vec<u8>,
+}
+
+impl Transcript {
+    fn record(&mut self, packet: &Packet) {
+        // O bincode é determinístico: reserializar um pacote recebido gera os mesmos bytes.
+        self.bytes.extend(bincode::serialize(packet).expect("pacote serializável"));
+    }
+
+    /// Hash que cada lado assina, separado por papel para que uma assinatura do
+    /// cliente não possa ser devolvida como se fosse do servidor.
+    fn hash_for(&self, role: Role) -> [u8; 32] {
+        let label: &[u8] = match role {
+            Role::Client => b"transcricao do cliente",
+            Role::Server => b"transcricao do servidor",
+        };
+        let mut data = label.to_vec();
+        data.extend_from_slice(&sha256(&self.bytes));
+        sha256(&data)
+    }
+}
+
+/// Estado local da troca de chaves enquanto espera a contribuição do outro lado.
+enum KeyShare {
+    Dh(DhKeypair),
+    Rsa([u8; 32]),
+}
+
+impl KeyShare {
+    /// Gera a contribuição local e o material a enviar no pacote `KeyExchange`.
+    fn start(algorithm: KeyExchangeAlgorithm, peer_key: &PublicKey) -> (Self, Vec<u64>) {
+        match algorithm {
+            KeyExchangeAlgorithm::DiffieHellman => {
+                let keypair = DhKeypair::generate();
+                let material = vec![keypair.public];
+                (KeyShare::Dh(keypair), material)
+            }
+            KeyExchangeAlgorithm::RsaTransport => {
+                let (block, part) = rsa::encapsulate(peer_key);
+                (KeyShare::Rsa(part), vec![block])
+            }
+        }
+    }
+
+    /// Combina a contribuição local com o material recebido e devolve o segredo bruto.
+    fn finish(self, material: &[u64], my_priv_key: &PrivateKey, role: Role) -> Result<Vec<u8>, ChatError> {
+        match self {
+            KeyShare::Dh(keypair) => {
+                let peer_public = match material {
+                    [value] if dh::is_valid_public(*value) => *value,
+                    _ => return Err(ChatError::Crypto("valor Diffie-Hellman inválido".to_string())),
+                };
+                Ok(keypair.shared_secret(peer_public).to_be_bytes().to_vec())
+            }
+            KeyShare::Rsa(my_part) => {
+                let peer_part = match material {
+                    [block] if *block < my_priv_key.n => rsa::decapsulate(*block, my_priv_key),
+                    _ => return Err(ChatError::Crypto("bloco RSA da troca de chaves inválido".to_string())),
+                };
+                // A metade do cliente vem sempre primeiro.
+                let mut shared = Vec::with_capacity(64);
+                match role {
+                    Role::Client => { shared.extend_from_slice(&my_part); shared.extend_from_slice(&peer_part); }
+                    Role::Server => { shared.extend_from_slice(&peer_part); shared.extend_from_slice(&my_part); }
+                }
+                Ok(shared)
+            }
+        }
+    }
+}
+
+/// Handshake do lado do cliente.
+///
+/// Envia o `ClientHello` com a oferta local, valida a suíte escolhida no
+/// `ServerHello`, conclui a troca de chaves e verifica a assinatura do servidor
+/// sobre a transcrição.
+pub async fn client_handshake<S>(
+    stream: &mut S,
+    codec: &FrameCodec,
+    offer: &Offer,
+    my_pub_key: &PublicKey,
+    my_priv_key: &PrivateKey,
+) -> Result<Session, ChatError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut transcript = Transcript::default();
+    let client_nonce: [u8; 32] = rand::thread_rng().gen();
+    let hello = Packet::ClientHello { offer: offer.clone(), nonce: client_nonce, public_key: my_pub_key.clone() };
+    transcript.record(&hello);
+    codec.write_frame(stream, &Envelope::new(0, hello)).await?;
+
+    let server_hello = read_reply(stream, codec).await?;
+    transcript.record(&server_hello);
+    let (suite, server_nonce, server_pub_key) = match server_hello {
+        Packet::ServerHello { suite, nonce, public_key } => (suite, nonce, public_key),
+        Packet::Error { code, message, .. } => return Err(ChatError::refused(code, message)),
+        other => return Err(ChatError::Protocol(format!("esperava ServerHello, recebeu {:?}", other))),
+    };
+    if !offer.allows(&suite) {
+        return Err(ChatError::Crypto(format!("servidor escolheu uma suíte que não foi oferecida: {}", suite)));
+    }
+    if !server_pub_key.is_valid() {
+        return Err(invalid_key("servidor", &server_pub_key));
+    }
+
+    let secret = exchange_keys(stream, codec, &mut transcript, &suite, &server_pub_key, my_priv_key, Role::Client, &client_nonce, &server_nonce).await?;
+    authenticate(stream, codec, &transcript, &server_pub_key, my_priv_key, Role::Client).await?;
+    Ok(Session::new(suite, Role::Client, secret, server_pub_key, my_pub_key.clone(), my_priv_key.clone()))
+}
+
+/// Retomada do lado do cliente, no lugar do handshake completo.
+///
+/// Apresenta o ticket recebido na conexão anterior, provando conhecer o segredo
+/// daquela sessão, e confere a prova do servidor na resposta. Devolve `None` se
+/// o servidor recusar o ticket: a conexão continua aberta e o cliente segue com
+/// `client_handshake`.
+pub async fn client_resume<S>(stream: &mut S, codec: &FrameCodec, ticket: Ticket) -> Result<Option<Session>, ChatError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let client_nonce: [u8; 32] = rand::thread_rng().gen();
+    let binder = ticket.state.proof(Role::Client, &resumption_data(&ticket.id, &client_nonce, None));
+    let resume = Packet::Resume { ticket: ticket.id, nonce: client_nonce, binder };
+    codec.write_frame(stream, &Envelope::new(0, resume)).await?;
+
+    let (server_nonce, proof) = match read_reply(stream, codec).await? {
+        Packet::Resumed { nonce, proof } => (nonce, proof),
+        Packet::Error { code: ErrorCode::ResumptionRejected, .. } => return Ok(None),
+        Packet::Error { code, message, .. } => return Err(ChatError::refused(code, message)),
+        other => return Err(ChatError::Protocol(format!("esperava Resumed, recebeu {:?}", other))),
+    };
+    let expected = ticket.state.proof(Role::Server, &resumption_data(&ticket.id, &client_nonce, Some(&server_nonce)));
+    if !cipher::macs_equal(&proof, &expected) {
+        return Err(ChatError::Auth("o servidor não provou conhecer o segredo da sessão retomada".to_string()));
+    }
+    Ok(Some(ticket.state.resume(Role::Client, &client_nonce, &server_nonce)))
+}
+
+/// Handshake do lado do servidor.
+///
+/// Lê o `ClientHello`, escolhe a suíte mais forte em comum com a oferta local e responde com o
+/// `ServerHello`. Sem algoritmos em comum, avisa o cliente e aborta. Só devolve a
+/// sessão depois de verificar a assinatura do cliente sobre a transcrição.
+///
+/// Se o cliente abrir com um `Resume` e o ticket for de `tickets`, a sessão é
+/// retomada sem troca de chaves; um ticket recusado é avisado com um `Error` e o
+/// servidor espera o `ClientHello` na mesma conexão.
+pub async fn server_handshake<S>(
+    stream: &mut S,
+    codec: &FrameCodec,
+    offer: &Offer,
+    tickets: &TicketStore,
+    my_pub_key: &PublicKey,
+    my_priv_key: &PrivateKey,
+) -> Result<Session, ChatError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut transcript = Transcript::default();
+    let mut envelope = read_opening(stream, codec).await?;
+    if let Packet::Resume { ticket, nonce, binder } = envelope.packet {
+        if let Some(session) = resume_session(stream, codec, tickets, &ticket, &nonce, &binder).await? {
+            return Ok(session);
+        }
+        let code = ErrorCode::ResumptionRejected;
+        codec.write_frame(stream, &Envelope::new(0, Packet::Error { id: envelope.id, code, message: code.to_string() })).await?;
+        envelope = read_opening(stream, codec).await?;
+    }
+    transcript.record(&envelope.packet);
+    let (client_offer, client_nonce, client_pub_key) = match envelope.packet {
+        Packet::ClientHello { offer, nonce, public_key } => (offer, nonce, public_key),
+        other => return Err(ChatError::Protocol(format!("esperava ClientHello, recebeu {:?}", other))),
+    };
+
+    // A chave do cliente é conferida antes de qualquer uso: valores fora da faixa derrubariam `mod_exp`.
+    if !client_pub_key.is_valid() {
+        let code = ErrorCode::InvalidKey;
+        let refusal = Packet::Error { id: envelope.id, code, message: code.to_string() };
+        codec.write_frame(stream, &Envelope::new(0, refusal)).await?;
+        return Err(invalid_key("cliente", &client_pub_key));
+    }
+
+    let suite = match offer.negotiate(&client_offer) {
+        Ok(suite) => suite,
+        Err(reason) => {
+            let code = ErrorCode::NoCommonSuite;
+            let refusal = Packet::Error { id: envelope.id, code, message: reason.clone() };
+            codec.write_frame(stream, &Envelope::new(0, refusal)).await?;
+            return Err(ChatError::Crypto(reason));
+        }
+    };
+
+    let server_nonce: [u8; 32] = rand::thread_rng().gen();
+    let hello = Packet::ServerHello { suite, nonce: server_nonce, public_key: my_pub_key.clone() };
+    transcript.record(&hello);
+    codec.write_frame(stream, &Envelope::new(0, hello)).await?;
+
+    let secret = exchange_keys(stream, codec, &mut transcript, &suite, &client_pub_key, my_priv_key, Role::Server, &client_nonce, &server_nonce).await?;
+    authenticate(stream, codec, &transcript, &client_pub_key, my_priv_key, Role::Server).await?;
+    Ok(Session::new(suite, Role::Server, secret, client_pub_key, my_pub_key.clone(), my_priv_key.clone()))
+}
+
+// Resposta do servidor ao primeiro pacote do cliente
+async fn read_reply<S>(stream: &mut S, codec: &FrameCodec) -> Result<Packet, ChatError>
+where
+    S: AsyncRead + Unpin,
+{
+    match protocol::read_envelope(codec, stream).await {
+        Err(ReadError::Rejected { code: ErrorCode::UnsupportedVersion, .. }) => Err(version_mismatch("servidor")),
+        other => Ok(other?.packet),
+    }
+}
+
+// Pacote de abertura do cliente. Se ele falar outra versão do protocolo, recebe um
+// `Error` na versão do servidor, para que também ele perceba a diferença.
+async fn read_opening<S>(stream: &mut S, codec: &FrameCodec) -> Result<Envelope, ChatError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match protocol::read_envelope(codec, stream).await {
+        Err(ReadError::Rejected { id, code: ErrorCode::UnsupportedVersion }) => {
+            let code = ErrorCode::UnsupportedVersion;
+            let message = format!("este servidor fala a versão {} do protocolo", PROTOCOL_VERSION);
+            codec.write_frame(stream, &Envelope::new(0, Packet::Error { id, code, message })).await?;
+            Err(version_mismatch("cliente"))
+        }
+        other => Ok(other?),
+    }
+}
+
+// O outro lado mandou um envelope de uma versão do protocolo que este binário não fala
+fn version_mismatch(peer: &str) -> ChatError {
+    ChatError::Protocol(format!("o {} fala outra versão do protocolo; este binário fala a versão {}", peer, PROTOCOL_VERSION))
+}
+
+// Chave pública recebida fora da faixa aceita (ver `PublicKey::is_valid`)
+fn invalid_key(owner: &str, key: &PublicKey) -> ChatError {
+    ChatError::Auth(format!("chave pública do {} inválida (n = {}, e = {}): é preciso 256 <= n < 2^32 e 1 < e < n", owner, key.n, key.e))
+}
+
+// Confere o ticket e a prova do cliente; se valerem, responde com o `Resumed` e a sessão nova
+async fn resume_session<S>(
+    stream: &mut S,
+    codec: &FrameCodec,
+    tickets: &TicketStore,
+    id: &TicketId,
+    client_nonce: &[u8; 32],
+    binder: &[u8; 32],
+) -> Result<Option<Session>, ChatError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Só quem conhece o segredo da sessão anterior gasta o ticket.
+    let proves = |ticket: &Ticket| cipher::macs_equal(binder, &ticket.state.proof(Role::Client, &resumption_data(id, client_nonce, None)));
+    let Some(ticket) = tickets.redeem(id, proves) else { return Ok(None) };
+    let server_nonce: [u8; 32] = rand::thread_rng().gen();
+    let proof = ticket.state.proof(Role::Server, &resumption_data(id, client_nonce, Some(&server_nonce)));
+    codec.write_frame(stream, &Envelope::new(0, Packet::Resumed { nonce: server_nonce, proof })).await?;
+    Ok(Some(ticket.state.resume(Role::Server, client_nonce, &server_nonce)))
+}
+
+// Dados cobertos pelas provas da retomada: o ticket e os nonces já conhecidos
+fn resumption_data(ticket: &TicketId, client_nonce: &[u8; 32], server_nonce: Option<&[u8; 32]>) -> Vec<u8> {
+    let mut data = ticket.to_vec();
+    data.extend_from_slice(client_nonce);
+    if let Some(server_nonce) = server_nonce {
+        data.extend_from_slice(server_nonce);
+    }
+    data
+}
+
+// Troca os pacotes `KeyExchange` e deriva o segredo da sessão, amarrado aos dois nonces
+#[allow(clippy::too_many_arguments)]
+async fn exchange_keys<S>(
+    stream: &mut S,
+    codec: &FrameCodec,
+    transcript: &mut Transcript,
+    suite: &CipherSuite,
+    peer_key: &PublicKey,
+    my_priv_key: &PrivateKey,
+    role: Role,
+    client_nonce: &[u8; 32],
+    server_nonce: &[u8; 32],
+) -> Result<[u8; 32], ChatError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (share, material) = KeyShare::start(suite.key_exchange, peer_key);
+    let my_packet = Packet::KeyExchange { material };
+    codec.write_frame(stream, &Envelope::new(0, my_packet.clone())).await?;
+
+    let peer_packet = protocol::read_envelope(codec, stream).await?.packet;
+    // Os dois lados enviam ao mesmo tempo; na transcrição entra primeiro o do cliente.
+    match role {
+        Role::Client => { transcript.record(&my_packet); transcript.record(&peer_packet); }
+        Role::Server => { transcript.record(&peer_packet); transcript.record(&my_packet); }
+    }
+    let peer_material = match peer_packet {
+        Packet::KeyExchange { material } => material,
+        Packet::Error { code, message, .. } => return Err(ChatError::refused(code, message)),
+        other => return Err(ChatError::Protocol(format!("esperava KeyExchange, recebeu {:?}", other))),
+    };
+
+    let mut secret_input = share.finish(&peer_material, my_priv_key, role)?;
+    secret_input.extend_from_slice(client_nonce);
+    secret_input.extend_from_slice(server_nonce);
+    Ok(sha256(&secret_input))
+}
+
+// Troca as assinaturas da transcrição e verifica a do outro lado com a chave pública que
+// ele apresentou. Quem trocar a chave no caminho não consegue produzir uma assinatura válida.
+// O cliente assina primeiro; o servidor só responde depois de verificar, de modo que uma
+// falha chega ao cliente como erro em vez de um handshake aparentemente concluído.
+async fn authenticate<S>(
+    stream: &mut S,
+    codec: &FrameCodec,
+    transcript: &Transcript,
+    peer_key: &PublicKey,
+    my_priv_key: &PrivateKey,
+    role: Role,
+) -> Result<(), ChatError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let finished = Packet::Finished { signature: rsa::sign(&transcript.hash_for(role), my_priv_key) };
+    match role {
+        Role::Client => {
+            codec.write_frame(stream, &Envelope::new(0, finished)).await?;
+            verify_peer_finished(stream, codec, transcript, peer_key, Role::Server).await
+        }
+        Role::Server => {
+            verify_peer_finished(stream, codec, transcript, peer_key, Role::Client).await?;
+            codec.write_frame(stream, &Envelope::new(0, finished)).await?;
+            Ok(())
+        }
+    }
+}
+
+// Lê o `Finished` do outro lado e confere a assinatura; se falhar, avisa antes de abortar
+async fn verify_peer_finished<S>(
+    stream: &mut S,
+    codec: &FrameCodec,
+    transcript: &Transcript,
+    peer_key: &PublicKey,
+    peer_role: Role,
+) -> Result<(), ChatError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let envelope = protocol::read_envelope(codec, stream).await?;
+    let peer_signature = match envelope.packet {
+        Packet::Finished { signature } => signature,
+        Packet::Error { code, message, .. } => return Err(ChatError::refused(code, message)),
+        other => return Err(ChatError::Protocol(format!("esperava Finished, recebeu {:?}", other))),
+    };
+
+    if !rsa::verify(&transcript.hash_for(peer_role), peer_signature, peer_key) {
+        let code = ErrorCode::AuthenticationFailed;
+        let message = "assinatura da transcrição inválida".to_string();
+        codec.write_frame(stream, &Envelope::new(0, Packet::Error { id: envelope.id, code, message: message.clone() })).await?;
+        return Err(ChatError::Auth(format!("{}: o outro lado não provou possuir a chave privada apresentada", message)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tokio::io::DuplexStream;
+    use crate::error::ChatError;
+    use crate::session::{Resumable, Verdict};
+
+    // Roda o handshake do cliente e o do servidor nas duas pontas de um canal em memória
+    async fn connect(client_offer: Offer, client_key: (PublicKey, PrivateKey), server_offer: Offer) -> (Result<Session, ChatError>, Result<Session, ChatError>) {
+        let (mut client_end, mut server_end) = tokio::io::duplex(64 * 1024);
+        let codec = FrameCodec::default();
+        let server = tokio::spawn(async move {
+            let (public_key, private_key) = rsa::new_keypair(None);
+            server_handshake(&mut server_end, &codec, &server_offer, &TicketStore::default(), &public_key, &private_key).await
+        });
+        let client = client_handshake(&mut client_end, &codec, &client_offer, &client_key.0, &client_key.1).await;
+        (client, server.await.unwrap())
+    }
+
+    // Lê o próximo pacote enviado pelo outro lado
+    async fn next_packet(stream: &mut DuplexStream) -> Packet {
+        protocol::read_envelope(&FrameCodec::default(), stream).await.unwrap().packet
+    }
+
+    #[test]
+    fn negotiate_picks_the_strongest_common_algorithms() {
+        let local = Offer::supported();
+        let suite = local.negotiate(&Offer::with_ciphers(&[CipherAlgorithm::RsaBlock, CipherAlgorithm::Sha256Ctr])).unwrap();
+        // A ordem de força é a da oferta local.
+        assert_eq!(suite.cipher, CipherAlgorithm::Sha256Ctr);
+        assert_eq!(suite.key_exchange, KeyExchangeAlgorithm::DiffieHellman);
+        assert_eq!(suite.version, PROTOCOL_VERSION);
+        assert!(local.allows(&suite));
+
+        let suite = local.negotiate(&Offer::with_ciphers(&[CipherAlgorithm::RsaBlock])).unwrap();
+        assert_eq!(suite.cipher, CipherAlgorithm::RsaBlock);
+    }
+
+    #[test]
+    fn negotiate_fails_without_a_common_algorithm() {
+        let local = Offer::with_ciphers(&[CipherAlgorithm::Sha256Ctr]);
+        let error = local.negotiate(&Offer::with_ciphers(&[CipherAlgorithm::RsaBlock])).unwrap_err();
+        assert!(error.contains("cifra"), "{}", error);
+
+        let mut old = Offer::supported();
+        old.versions = vec![PROTOCOL_VERSION - 1];
+        let error = local.negotiate(&old).unwrap_err();
+        assert!(error.contains("versão"), "{}", error);
+    }
+
+    #[test]
+    fn with_ciphers_keeps_order_and_drops_repeats() {
+        let offer = Offer::with_ciphers(&[CipherAlgorithm::RsaBlock, CipherAlgorithm::Sha256Ctr, CipherAlgorithm::RsaBlock]);
+        assert_eq!(offer.ciphers, [CipherAlgorithm::RsaBlock, CipherAlgorithm::Sha256Ctr]);
+        assert_eq!(Offer::with_ciphers(&[]).ciphers, Offer::supported().ciphers);
+    }
+
+    #[test]
+    fn allows_rejects_suites_outside_the_offer() {
+        let offer = Offer::with_ciphers(&[CipherAlgorithm::Sha256Ctr]);
+        let suite = Offer::supported().negotiate(&Offer::with_ciphers(&[CipherAlgorithm::RsaBlock])).unwrap();
+        assert!(!offer.allows(&suite));
+    }
+
+    // Transcrição de um handshake com nonce sorteado
+    fn random_transcript() -> Transcript {
+        let mut transcript = Transcript::default();
+        let (public_key, _) = rsa::new_keypair(None);
+        transcript.record(&Packet::ClientHello { offer: Offer::supported(), nonce: rand::random(), public_key });
+        transcript
+    }
+
+    #[test]
+    fn signatures_from_other_transcripts_cannot_be_combined() {
+        let (server_pub, server_priv) = rsa::new_keypair(Some(rsa::MAX_KEY_BITS));
+        let observed: Vec<[u8; 32]> = (0..2).map(|_| random_transcript().hash_for(Role::Server)).collect();
+        let signatures: Vec<u64> = observed.iter().map(|hash| rsa::sign(hash, &server_priv)).collect();
+        for (hash, signature) in observed.iter().zip(&signatures) {
+            assert!(rsa::verify(hash, *signature, &server_pub));
+        }
+
+        // Nenhuma remontagem das assinaturas observadas vale para outra transcrição:
+        // nem cada uma inteira, nem metade de cada, nem o produto das duas.
+        let target = random_transcript().hash_for(Role::Server);
+        let halves = (signatures[0] & !0xFFFF) | (signatures[1] & 0xFFFF);
+        let product = (signatures[0] as u128 * signatures[1] as u128 % server_pub.n as u128) as u64;
+        for candidate in [signatures[0], signatures[1], halves, product] {
+            assert!(!rsa::verify(&target, candidate, &server_pub));
+        }
+    }
+
+    #[tokio::test]
+    async fn handshake_agrees_on_suite_and_keys() {
+        for cipher in [CipherAlgorithm::Sha256Ctr, CipherAlgorithm::RsaBlock] {
+            let client_key = rsa::new_keypair(None);
+            let (client, server) = connect(Offer::with_ciphers(&[cipher]), client_key.clone(), Offer::supported()).await;
+            let (client, server) = (client.unwrap(), server.unwrap());
+            assert_eq!(client.suite, server.suite);
+            assert_eq!(client.suite.cipher, cipher);
+            assert_eq!(server.peer_key, client_key.0);
+            assert_eq!(client.peer_key, server.my_pub_key);
+
+            let (mut sender, _) = client.split();
+            let (_, mut receiver) = server.split();
+            let sealed = sender.seal("oi", &[]);
+            assert_eq!(receiver.open(sealed.seq, &[], &sealed.hash, &sealed.body).1, Verdict::Valid);
+        }
+    }
+
+    #[tokio::test]
+    async fn rsa_transport_agrees_on_the_session_secret() {
+        let mut client_offer = Offer::supported();
+        client_offer.key_exchanges = vec![KeyExchangeAlgorithm::RsaTransport];
+        let (client, server) = connect(client_offer, rsa::new_keypair(None), Offer::supported()).await;
+        let (client, server) = (client.unwrap(), server.unwrap());
+        assert_eq!(client.suite.key_exchange, KeyExchangeAlgorithm::RsaTransport);
+
+        let (mut sender, _) = server.split();
+        let (_, mut receiver) = client.split();
+        let sealed = sender.seal("oi", &[]);
+        assert_eq!(receiver.open(sealed.seq, &[], &sealed.hash, &sealed.body).1, Verdict::Valid);
+    }
+
+    #[tokio::test]
+    async fn handshake_fails_without_a_common_cipher() {
+        let client_offer = Offer::with_ciphers(&[CipherAlgorithm::RsaBlock]);
+        let server_offer = Offer::with_ciphers(&[CipherAlgorithm::Sha256Ctr]);
+        let (client, server) = connect(client_offer, rsa::new_keypair(None), server_offer).await;
+        assert!(matches!(client, Err(ChatError::Crypto(_))));
+        assert!(matches!(server, Err(ChatError::Crypto(_))));
+    }
+
+    #[tokio::test]
+    async fn server_rejects_an_invalid_client_key() {
+        let (_, private_key) = rsa::new_keypair(None);
+        for public_key in [PublicKey { e: 3, n: 255 }, PublicKey { e: 1, n: 3233 }, PublicKey { e: 3233, n: 3233 }, PublicKey { e: 3, n: 1 << 32 }] {
+            let (client, server) = connect(Offer::supported(), (public_key, private_key.clone()), Offer::supported()).await;
+            assert!(matches!(client, Err(ChatError::Auth(reason)) if reason.contains(&format!("({})", ErrorCode::InvalidKey))));
+            assert!(matches!(server, Err(ChatError::Auth(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn server_answers_other_versions_with_an_error() {
+        let (mut client_end, mut server_end) = tokio::io::duplex(64 * 1024);
+        let codec = FrameCodec::default();
+        let server = tokio::spawn(async move {
+            let (public_key, private_key) = rsa::new_keypair(None);
+            server_handshake(&mut server_end, &codec, &Offer::supported(), &TicketStore::default(), &public_key, &private_key).await
+        });
+        let (public_key, _) = rsa::new_keypair(None);
+        let hello = Packet::ClientHello { offer: Offer::supported(), nonce: [0; 32], public_key };
+        codec.write_frame(&mut client_end, &Envelope { version: PROTOCOL_VERSION + 1, id: 7, packet: hello }).await.unwrap();
+
+        assert!(matches!(next_packet(&mut client_end).await, Packet::Error { id: 7, code: ErrorCode::UnsupportedVersion, .. }));
+        assert!(matches!(server.await.unwrap(), Err(ChatError::Protocol(_))));
+    }
+
+    // Estados de retomada do cliente e do servidor de uma sessão anterior
+    fn previous_session() -> (Resumable, Resumable) {
+        let offer = Offer::supported();
+        let suite = offer.negotiate(&offer).unwrap();
+        let (client_pub, client_priv) = rsa::new_keypair(None);
+        let (server_pub, server_priv) = rsa::new_keypair(None);
+        let secret: [u8; 32] = rand::random();
+        let client = Session::new(suite, Role::Client, secret, server_pub.clone(), client_pub.clone(), client_priv);
+        let server = Session::new(suite, Role::Server, secret, client_pub, server_pub, server_priv);
+        (client.resumable(), server.resumable())
+    }
+
+    // Tenta retomar com `ticket` e, se o servidor recusar, refaz o handshake completo na mesma conexão
+    async fn reconnect(tickets: Arc<TicketStore>, ticket: Ticket) -> (Session, Session) {
+        let (mut client_end, mut server_end) = tokio::io::duplex(64 * 1024);
+        let codec = FrameCodec::default();
+        let server = tokio::spawn(async move {
+            let (public_key, private_key) = rsa::new_keypair(None);
+            server_handshake(&mut server_end, &codec, &Offer::supported(), &tickets, &public_key, &private_key).await
+        });
+        let client = match client_resume(&mut client_end, &codec, ticket).await.unwrap() {
+            Some(session) => session,
+            None => {
+                let (public_key, private_key) = rsa::new_keypair(None);
+                client_handshake(&mut client_end, &codec, &Offer::supported(), &public_key, &private_key).await.unwrap()
+            }
+        };
+        (client, server.await.unwrap().unwrap())
+    }
+
+    #[tokio::test]
+    async fn valid_ticket_resumes_without_a_key_exchange() {
+        let (client_state, server_state) = previous_session();
+        let tickets = Arc::new(TicketStore::default());
+        let id = tickets.issue(server_state);
+        let ticket = Ticket::new(id, 60, client_state).unwrap();
+        let (client, server) = reconnect(tickets.clone(), ticket.clone()).await;
+        assert!(client.resumed && server.resumed);
+
+        let (mut sender, _) = client.split();
+        let (_, mut receiver) = server.split();
+        let sealed = sender.seal("de volta", &[]);
+        assert_eq!(receiver.open(sealed.seq, &[], &sealed.hash, &sealed.body).1, Verdict::Valid);
+
+        // O ticket vale uma vez só.
+        let (client, server) = reconnect(tickets, ticket).await;
+        assert!(!client.resumed && !server.resumed);
+    }
+
+    #[tokio::test]
+    async fn unknown_ticket_falls_back_to_the_full_handshake() {
+        let (client_state, _) = previous_session();
+        let ticket = Ticket::new([9; 32], 60, client_state).unwrap();
+        let (client, server) = reconnect(Arc::new(TicketStore::default()), ticket).await;
+        assert!(!client.resumed && !server.resumed);
+        assert_eq!(client.suite, server.suite);
+    }
+
+    #[tokio::test]
+    async fn ticket_from_another_session_is_refused() {
+        let (client_state, server_state) = previous_session();
+        let (other_client_state, _) = previous_session();
+        let tickets = Arc::new(TicketStore::default());
+        let id = tickets.issue(server_state);
+        // Mesmo identificador, mas sem o segredo da sessão a que ele se refere.
+        let forged = Ticket::new(id, 60, other_client_state).unwrap();
+        let (client, server) = reconnect(tickets.clone(), forged).await;
+        assert!(!client.resumed && !server.resumed);
+
+        // Quem copiou o identificador do fio não gasta o ticket: o dono ainda retoma a sessão.
+        let (client, server) = reconnect(tickets, Ticket::new(id, 60, client_state).unwrap()).await;
+        assert!(client.resumed && server.resumed);
+    }
+}
@@ -0,0 +1,279 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use anyhow::{bail, Context};
+use serde::{Serialize, Deserialize};
+use crate::cipher;
+use crate::mailbox;
+
+/// Iterações da derivação da chave a partir da senha.
+const PASSPHRASE_ROUNDS: u32 = 20_000;
+
+/// Quantas mensagens cada página de `/history` mostra.
+const PAGE_SIZE: usize = 20;
+
+/// Uma mensagem da conversa, já decifrada.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    /// Segundos desde 1970 (UTC) em que a mensagem foi enviada.
+    pub at: u64,
+    /// Onde a conversa aconteceu: `#sala` ou `@apelido` para mensagens privadas.
+    pub conversation: String,
+    /// Quem escreveu; `None` para as mensagens do próprio usuário.
+    pub from: Option<String>,
+    pub text: String,
+}
+
+impl Entry {
+    /// Linha mostrada por `/history` e `/search`.
+    pub fn line(&self) -> String {
+        let from = self.from.as_deref().unwrap_or("você");
+        format!("[{}] {} {}: {}", mailbox::format_timestamp(self.at), self.conversation, from, self.text)
+    }
+}
+
+// Início do arquivo: o salt da senha e um valor que confirma que a senha está certa
+#[derive(Serialize, Deserialize)]
+struct Header {
+    salt: [u8; 16],
+    check: [u8; 32],
+}
+
+// Cada mensagem é cifrada com o número do registro como nonce e autenticada com um MAC
+#[derive(Serialize, Deserialize)]
+struct Record {
+    nonce: u64,
+    mac: [u8; 32],
+    ciphertext: Vec<u8>,
+}
+
+/// Histórico local das conversas, cifrado com uma chave derivada da senha do usuário.
+///
+/// O arquivo começa com o salt da senha e depois recebe um registro cifrado por
+/// mensagem, sempre acrescentado ao final. Ao abrir, todos os registros são
+/// conferidos e decifrados para a memória, onde `/history` e `/search` os consultam.
+pub struct History {
+    file: File,
+    encryption_key: [u8; 32],
+    mac_key: [u8; 32],
+    entries: Vec<Entry>,
+}
+
+impl History {
+    /// Abre o histórico em `path` com a senha, ou cria um novo se o arquivo não existe.
+    pub fn open(path: &Path, passphrase: &str) -> anyhow::Result<History> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)
+            .with_context(|| format!("não foi possível abrir {}", path.display()))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let mut frames = Frames { bytes: &bytes };
+
+        let (header, master) = match frames.next_frame()? {
+            Some(data) => {
+                let header: Header = bincode::deserialize(data).context("cabeçalho do histórico inválido")?;
+                let master = cipher::passphrase_key(passphrase, &header.salt, PASSPHRASE_ROUNDS);
+                (header, master)
+            }
+            None => {
+                let salt: [u8; 16] = rand::random();
+                let master = cipher::passphrase_key(passphrase, &salt, PASSPHRASE_ROUNDS);
+                let header = Header { salt, check: cipher::derive_key(&master, "verificação") };
+                write_frame(&mut file, &bincode::serialize(&header)?)?;
+                (header, master)
+            }
+        };
+        if !cipher::macs_equal(&cipher::derive_key(&master, "verificação"), &header.check) {
+            bail!("senha do histórico incorreta");
+        }
+
+        let mut history = History {
+            file,
+            encryption_key: cipher::derive_key(&master, "histórico-cifra"),
+            mac_key: cipher::derive_key(&master, "histórico-mac"),
+            entries: Vec::new(),
+        };
+        while let Some(data) = frames.next_frame()? {
+            let record: Record = bincode::deserialize(data).context("registro do histórico inválido")?;
+            let expected = cipher::mac(&history.mac_key, record.nonce, &[], &record.ciphertext);
+            if record.nonce != history.entries.len() as u64 || !cipher::macs_equal(&expected, &record.mac) {
+                bail!("histórico adulterado no registro {}", history.entries.len() + 1);
+            }
+            let mut plain = record.ciphertext;
+            cipher::apply_keystream(&history.encryption_key, record.nonce, &mut plain);
+            history.entries.push(bincode::deserialize(&plain).context("registro do histórico inválido")?);
+        }
+        Ok(history)
+    }
+
+    /// Cifra a mensagem e a acrescenta ao final do arquivo.
+    pub fn record(&mut self, entry: Entry) -> anyhow::Result<()> {
+        let nonce = self.entries.len() as u64;
+        let mut ciphertext = bincode::serialize(&entry)?;
+        cipher::apply_keystream(&self.encryption_key, nonce, &mut ciphertext);
+        let mac = cipher::mac(&self.mac_key, nonce, &[], &ciphertext);
+        write_frame(&mut self.file, &bincode::serialize(&Record { nonce, mac, ciphertext })?)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Página `page` (a partir de 1, da mais recente para a mais antiga) e o
+    /// total de páginas. As mensagens de cada página vêm em ordem cronológica;
+    /// uma página além da última vem vazia.
+    pub fn page(&self, page: usize) -> (&[Entry], usize) {
+        let pages = self.entries.len().div_ceil(PAGE_SIZE).max(1);
+        let end = self.entries.len().saturating_sub(page.saturating_sub(1).saturating_mul(PAGE_SIZE));
+        (&self.entries[end.saturating_sub(PAGE_SIZE)..end], pages)
+    }
+
+    /// Mensagens de todas as conversas que contêm `term`, sem diferenciar maiúsculas.
+    pub fn search(&self, term: &str) -> Vec<&Entry> {
+        let term = term.to_lowercase();
+        self.entries.iter().filter(|entry| entry.text.to_lowercase().contains(&term)).collect()
+    }
+}
+
+// Percorre os blocos do arquivo, cada um precedido pelo tamanho (u32 big-endian)
+struct Frames<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Frames<'a> {
+    fn next_frame(&mut self) -> anyhow::Result<Option<&'a [u8]>> {
+        if self.bytes.is_empty() {
+            return Ok(None);
+        }
+        let Some((len, rest)) = self.bytes.split_first_chunk::<4>() else { bail!("histórico truncado") };
+        let len = u32::from_be_bytes(*len) as usize;
+        if rest.len() < len {
+            bail!("histórico truncado");
+        }
+        let (data, rest) = rest.split_at(len);
+        self.bytes = rest;
+        Ok(Some(data))
+    }
+}
+
+fn write_frame(file: &mut File, data: &[u8]) -> std::io::Result<()> {
+    let mut frame = (data.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(data);
+    file.write_all(&frame)?;
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // Arquivo temporário apagado ao fim do teste
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("chat_rsa_historico_{}_{}", std::process::id(), name));
+            let _ = std::fs::remove_file(&path);
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn entry(at: u64, text: &str) -> Entry {
+        Entry { at, conversation: "#geral".to_string(), from: Some("ana".to_string()), text: text.to_string() }
+    }
+
+    // Histórico com duas mensagens gravadas com a senha "segredo"
+    fn written(file: &TempFile) {
+        let mut history = History::open(&file.0, "segredo").unwrap();
+        history.record(entry(1, "Bom dia")).unwrap();
+        history.record(entry(2, "boa noite")).unwrap();
+    }
+
+    #[test]
+    fn reopens_with_the_right_passphrase() {
+        let file = TempFile::new("senha_certa");
+        written(&file);
+        let mut history = History::open(&file.0, "segredo").unwrap();
+        let texts: Vec<_> = history.page(1).0.iter().map(|entry| entry.text.as_str()).collect();
+        assert_eq!(texts, ["Bom dia", "boa noite"]);
+        assert_eq!(history.search("BOA").len(), 1);
+
+        // Novos registros continuam a sequência dos anteriores.
+        history.record(entry(3, "de novo")).unwrap();
+        assert_eq!(History::open(&file.0, "segredo").unwrap().entries.len(), 3);
+    }
+
+    #[test]
+    fn rejects_a_wrong_passphrase() {
+        let file = TempFile::new("senha_errada");
+        written(&file);
+        let error = History::open(&file.0, "outra").err().unwrap();
+        assert!(error.to_string().contains("senha do histórico incorreta"), "{}", error);
+    }
+
+    #[test]
+    fn detects_a_tampered_record() {
+        let file = TempFile::new("adulterado");
+        written(&file);
+        let mut bytes = std::fs::read(&file.0).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        std::fs::write(&file.0, &bytes).unwrap();
+        let error = History::open(&file.0, "segredo").err().unwrap();
+        assert!(error.to_string().contains("adulterado no registro 2"), "{}", error);
+    }
+
+    #[test]
+    fn detects_a_removed_record() {
+        let file = TempFile::new("removido");
+        written(&file);
+        // Tira o primeiro registro, logo depois do cabeçalho.
+        let bytes = std::fs::read(&file.0).unwrap();
+        let mut frames = Frames { bytes: &bytes };
+        let header = frames.next_frame().unwrap().unwrap().len() + 4;
+        let first = frames.next_frame().unwrap().unwrap().len() + 4;
+        let mut shortened = bytes[..header].to_vec();
+        shortened.extend_from_slice(&bytes[header + first..]);
+        std::fs::write(&file.0, &shortened).unwrap();
+        let error = History::open(&file.0, "segredo").err().unwrap();
+        assert!(error.to_string().contains("adulterado no registro 1"), "{}", error);
+    }
+
+    #[test]
+    fn detects_a_truncated_file() {
+        let file = TempFile::new("truncado");
+        written(&file);
+        let bytes = std::fs::read(&file.0).unwrap();
+        std::fs::write(&file.0, &bytes[..bytes.len() - 3]).unwrap();
+        let error = History::open(&file.0, "segredo").err().unwrap();
+        assert!(error.to_string().contains("truncado"), "{}", error);
+    }
+
+    #[test]
+    fn pages_go_from_newest_to_oldest() {
+        let file = TempFile::new("paginas");
+        let mut history = History::open(&file.0, "segredo").unwrap();
+        for at in 0..(PAGE_SIZE as u64 + 5) {
+            history.record(entry(at, &at.to_string())).unwrap();
+        }
+        let (newest, pages) = history.page(1);
+        assert_eq!((newest.len(), pages), (PAGE_SIZE, 2));
+        assert_eq!(newest.last().unwrap().at, PAGE_SIZE as u64 + 4);
+        let (oldest, _) = history.page(2);
+        assert_eq!(oldest.iter().map(|entry| entry.at).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn pages_past_the_end_are_empty() {
+        let file = TempFile::new("alem");
+        let mut history = History::open(&file.0, "segredo").unwrap();
+        history.record(entry(1, "oi")).unwrap();
+        assert_eq!(history.page(3).0.len(), 0);
+        let (entries, pages) = history.page(usize::MAX);
+        assert_eq!((entries.len(), pages), (0, 1));
+    }
+}
@@ -0,0 +1,611 @@
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use crate::direct::DirectMessage;
+use crate::group::GroupMessage;
+use crate::mailbox::{self, Mailbox, MailboxConfig, StoredMessage};
+use crate::protocol::ErrorCode;
+use crate::rsa::PublicKey;
+use crate::traffic::Traffic;
+use crate::transfer::{FileTransfer, FILE_QUEUE};
+
+/// Sala em que todo cliente entra ao se conectar.
+pub const DEFAULT_ROOM: &str = "geral";
+
+/// Identificador interno de uma conexão no hub.
+pub type ClientId = u64;
+
+/// Quantas entregas podem esperar na fila de cada cliente. Quem deixa a fila
+/// encher não está acompanhando a conversa e é desconectado.
+pub const DELIVERY_QUEUE: usize = 1024;
+
+/// Eventos que o hub entrega à sessão de um cliente, para serem cifrados e enviados a ele.
+#[derive(Debug, Clone)]
+pub enum Delivery {
+    /// Mensagem de outro participante da sala.
+    Chat { from: String, text: String },
+    /// Alguém entrou ou saiu da sala do cliente.
+    Presence { room: String, who: String, joined: bool },
+    /// O próprio cliente entrou em uma sala; lista quem já está nela.
+    Joined { room: String, members: Vec<String> },
+    /// Um participante da sala trocou de apelido.
+    Renamed { old: String, new: String },
+    /// Mensagem de ponta a ponta de outro cliente (privada ou da sala), opaca para o servidor.
+    Direct { from: String, room: Option<String>, sender_key: PublicKey, message: DirectMessage },
+    /// Mensagem de grupo de outro participante da sala, opaca para o servidor.
+    Group { from: String, room: String, sender_key: PublicKey, message: GroupMessage },
+    /// Mensagem privada guardada enquanto o cliente estava desconectado.
+    Stored(StoredMessage),
+    /// Mensagem do operador do servidor; com `tamper`, vai adulterada de propósito.
+    Operator { text: String, tamper: bool },
+    /// O operador encerrou a conexão do cliente.
+    Kicked { reason: String },
+}
+
+/// Etapa de uma transferência de arquivo de outro cliente, opaca para o servidor.
+#[derive(Debug)]
+pub struct FileDelivery {
+    pub from: String,
+    pub sender_key: PublicKey,
+    pub transfer: FileTransfer,
+}
+
+/// Filas de um cliente registrado: as entregas comuns e as etapas de arquivos.
+pub struct Inbox {
+    pub deliveries: mpsc::Receiver<Delivery>,
+    pub files: mpsc::Receiver<FileDelivery>,
+}
+
+/// Resumo de uma conexão, para o console e o painel do servidor.
+pub struct ClientInfo {
+    pub id: ClientId,
+    pub name: String,
+    pub addr: SocketAddr,
+    pub room: String,
+    pub fingerprint: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Segundos desde 1970 (UTC) da última vez que o cliente mandou algo.
+    pub last_activity: u64,
+}
+
+struct Member {
+    name: String,
+    addr: SocketAddr,
+    public_key: PublicKey,
+    room: String,
+    traffic: Arc<Traffic>,
+    // `None` depois que a fila encheu: a sessão envia o que já estava nela e termina
+    tx: Option<mpsc::Sender<Delivery>>,
+    files: mpsc::Sender<FileDelivery>,
+}
+
+#[derive(Default)]
+struct HubState {
+    next_id: ClientId,
+    members: HashMap<ClientId, Member>,
+    rooms: HashMap<String, BTreeSet<ClientId>>,
+    // Apelidos em uso (em minúsculas, para que "Ana" e "ana" não convivam)
+    nicknames: HashMap<String, ClientId>,
+    // Última chave que usou cada apelido (em minúsculas), para a caixa postal. O dono só
+    // segura o apelido enquanto está conectado; depois, a entrada vale até outra chave
+    // registrar o apelido ou até passar o prazo de retenção da caixa postal.
+    directory: HashMap<String, Listing>,
+    mailbox: Mailbox,
+    retention: Duration,
+}
+
+// Entrada do diretório: a chave e, se o dono já saiu, quando (segundos desde 1970)
+struct Listing {
+    public_key: PublicKey,
+    left_at: Option<u64>,
+}
+
+/// Destino de uma mensagem privada aceita pelo hub.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Routed {
+    /// Entregue ao destinatário conectado.
+    Delivered,
+    /// Destinatário desconectado: guardada na caixa postal dele.
+    Stored,
+}
+
+/// Central de salas do servidor: sabe quem está em cada sala e repassa as
+/// mensagens de um participante para todos os outros da mesma sala.
+///
+/// É compartilhado entre as tarefas de conexão por `Arc`. O lock nunca é mantido
+/// através de um `.await`: as entregas não esperam vaga na fila do cliente, e as
+/// etapas de arquivos são devolvidas por `file` para quem repassa esperar fora do lock.
+///
+/// Também guarda, na caixa postal, as mensagens privadas para quem está desconectado.
+pub struct Hub {
+    state: Mutex<HubState>,
+}
+
+impl Hub {
+    pub fn new(mailbox: MailboxConfig) -> Self {
+        let state = HubState { mailbox: Mailbox::new(mailbox), retention: mailbox.retention, ..HubState::default() };
+        Hub { state: Mutex::new(state) }
+    }
+
+    /// Registra uma nova conexão com o apelido escolhido, vinculado à chave pública
+    /// apresentada no handshake, e a coloca na sala padrão. `traffic` são os
+    /// contadores da conexão, mostrados por `clients`. Devolve o identificador do
+    /// cliente e as filas do que o hub entregar a ele.
+    ///
+    /// Falha com `NicknameTaken` se o apelido já estiver em uso por outra conexão e
+    /// com `InvalidKey` se a chave não puder entrar no diretório: os outros clientes
+    /// cifram para ela. O apelido de quem já saiu fica livre; registrá-lo com outra
+    /// chave faz as mensagens guardadas a partir daí irem para a chave nova.
+    pub fn register(&self, name: String, addr: SocketAddr, public_key: PublicKey, traffic: Arc<Traffic>) -> Result<(ClientId, Inbox), ErrorCode> {
+        if !public_key.is_valid() {
+            return Err(ErrorCode::InvalidKey);
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.nicknames.contains_key(&name.to_lowercase()) {
+            return Err(ErrorCode::NicknameTaken);
+        }
+        state.claim(&name, &public_key);
+        state.next_id += 1;
+        let id = state.next_id;
+        state.nicknames.insert(name.to_lowercase(), id);
+        let (tx, deliveries) = mpsc::channel(DELIVERY_QUEUE);
+        let (files, files_rx) = mpsc::channel(FILE_QUEUE);
+        state.members.insert(id, Member { name, addr, public_key, room: String::new(), traffic, tx: Some(tx), files });
+        state.enter(id, DEFAULT_ROOM);
+        Ok((id, Inbox { deliveries, files: files_rx }))
+    }
+
+    /// Entrega ao cliente as mensagens guardadas para a chave dele enquanto
+    /// estava desconectado. Devolve quantas foram entregues.
+    pub fn deliver_mail(&self, id: ClientId) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let Some(public_key) = state.members.get(&id).map(|member| member.public_key.clone()) else { return 0 };
+        let messages = state.mailbox.take(&public_key);
+        let count = messages.len();
+        if let Some(member) = state.members.get_mut(&id) {
+            for stored in messages {
+                member.deliver(Delivery::Stored(stored));
+            }
+        }
+        count
+    }
+
+    /// Troca o apelido de um cliente já registrado e avisa a sala dele.
+    /// Devolve o apelido antigo, que fica livre para outras chaves.
+    pub fn rename(&self, id: ClientId, new_name: &str) -> Result<String, ErrorCode> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(owner) = state.nicknames.get(&new_name.to_lowercase()) {
+            // Mudar só as maiúsculas do próprio apelido é permitido.
+            if *owner != id {
+                return Err(ErrorCode::NicknameTaken);
+            }
+        }
+        let Some(member) = state.members.get_mut(&id) else { return Err(ErrorCode::Unexpected) };
+        let old = std::mem::replace(&mut member.name, new_name.to_string());
+        let (room, public_key) = (member.room.clone(), member.public_key.clone());
+        state.nicknames.remove(&old.to_lowercase());
+        state.directory.remove(&old.to_lowercase());
+        state.nicknames.insert(new_name.to_lowercase(), id);
+        state.claim(new_name, &public_key);
+
+        let renamed = Delivery::Renamed { old: old.clone(), new: new_name.to_string() };
+        state.send_to_room(&room, Some(id), &renamed);
+        Ok(old)
+    }
+
+    /// Apelido atual do cliente.
+    pub fn name_of(&self, id: ClientId) -> Option<String> {
+        self.state.lock().unwrap().members.get(&id).map(|member| member.name.clone())
+    }
+
+    /// Impressão digital da chave pública vinculada ao apelido do cliente.
+    pub fn fingerprint_of(&self, id: ClientId) -> Option<String> {
+        self.state.lock().unwrap().members.get(&id).map(|member| member.public_key.fingerprint())
+    }
+
+    /// Sala atual do cliente e os participantes dela.
+    pub fn room_of(&self, id: ClientId) -> Option<(String, Vec<String>)> {
+        let state = self.state.lock().unwrap();
+        let room = state.members.get(&id)?.room.clone();
+        let members = state.names_in(&room);
+        Some((room, members))
+    }
+
+    /// Identificador do cliente que usa o apelido, se houver.
+    pub fn id_of(&self, nickname: &str) -> Option<ClientId> {
+        self.state.lock().unwrap().nicknames.get(&nickname.to_lowercase()).copied()
+    }
+
+    /// Lista as conexões registradas, em ordem de chegada.
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        let state = self.state.lock().unwrap();
+        let mut clients: Vec<ClientInfo> = state.members.iter()
+            .map(|(id, member)| ClientInfo {
+                id: *id,
+                name: member.name.clone(),
+                addr: member.addr,
+                room: member.room.clone(),
+                fingerprint: member.public_key.fingerprint(),
+                bytes_in: member.traffic.bytes_in(),
+                bytes_out: member.traffic.bytes_out(),
+                last_activity: member.traffic.last_activity(),
+            })
+            .collect();
+        clients.sort_by_key(|client| client.id);
+        clients
+    }
+
+    /// Entrega um evento a um único cliente. Devolve `false` se ele não está mais conectado.
+    pub fn send_to(&self, id: ClientId, delivery: Delivery) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.members.get_mut(&id).is_some_and(|member| member.deliver(delivery))
+    }
+
+    /// Entrega um evento a todos os clientes conectados.
+    pub fn send_to_all(&self, delivery: Delivery) {
+        let mut state = self.state.lock().unwrap();
+        for member in state.members.values_mut() {
+            member.deliver(delivery.clone());
+        }
+    }
+
+    /// Consulta o diretório: chave pública vinculada ao apelido, se alguém o usa.
+    /// Quem está desconectado é encontrado pela última chave que usou o apelido.
+    pub fn public_key_of(&self, nickname: &str) -> Option<PublicKey> {
+        self.state.lock().unwrap().directory.get(&nickname.to_lowercase()).map(|listing| listing.public_key.clone())
+    }
+
+    /// Entrega uma mensagem de ponta a ponta ao cliente com o apelido `to`, junto com
+    /// o apelido e a chave do remetente.
+    ///
+    /// Mensagens de sala só são entregues se os dois estiverem nela. Mensagens
+    /// privadas para quem está desconectado vão para a caixa postal da última chave
+    /// que usou o apelido. Falha com `UnknownUser` se ninguém conhecido usa
+    /// o apelido (ou se o destinatário não está na sala) e com `MailboxFull` se a
+    /// cota do destinatário acabou.
+    pub fn direct(&self, from: ClientId, to: &str, room: Option<String>, message: DirectMessage) -> Result<Routed, ErrorCode> {
+        let mut state = self.state.lock().unwrap();
+        let Some(sender) = state.members.get(&from) else { return Err(ErrorCode::Unexpected) };
+        let (from_name, sender_key) = (sender.name.clone(), sender.public_key.clone());
+        let sender_room = sender.room.clone();
+        let recipient_id = state.nicknames.get(&to.to_lowercase()).copied();
+        let recipient = recipient_id.and_then(|id| state.members.get_mut(&id));
+
+        match (recipient, room) {
+            (Some(recipient), room) => {
+                if room.as_ref().is_some_and(|room| *room != sender_room || *room != recipient.room) {
+                    return Err(ErrorCode::UnknownUser);
+                }
+                recipient.deliver(Delivery::Direct { from: from_name, room, sender_key, message });
+                Ok(Routed::Delivered)
+            }
+            (None, None) => {
+                let recipient_key = state.directory.get(&to.to_lowercase()).map(|listing| listing.public_key.clone()).ok_or(ErrorCode::UnknownUser)?;
+                let stored = StoredMessage { from: from_name, sender_key, sent_at: mailbox::now(), message };
+                if state.mailbox.store(&recipient_key, stored) {
+                    Ok(Routed::Stored)
+                } else {
+                    Err(ErrorCode::MailboxFull)
+                }
+            }
+            (None, Some(_)) => Err(ErrorCode::UnknownUser),
+        }
+    }
+
+    /// Prepara o repasse de uma etapa de transferência de arquivo ao cliente `to`:
+    /// devolve a fila de arquivos dele e a entrega a colocar nela. A fila é
+    /// limitada (`FILE_QUEUE`), e quem repassa espera vaga fora do lock. Arquivos
+    /// não vão para a caixa postal: falha com `UnknownUser` se ele não está conectado.
+    pub fn file(&self, from: ClientId, to: &str, transfer: FileTransfer) -> Result<(mpsc::Sender<FileDelivery>, FileDelivery), ErrorCode> {
+        let state = self.state.lock().unwrap();
+        let Some(sender) = state.members.get(&from) else { return Err(ErrorCode::Unexpected) };
+        let recipient = state.nicknames.get(&to.to_lowercase()).and_then(|id| state.members.get(id)).ok_or(ErrorCode::UnknownUser)?;
+        let delivery = FileDelivery { from: sender.name.clone(), sender_key: sender.public_key.clone(), transfer };
+        Ok((recipient.files.clone(), delivery))
+    }
+
+    /// Repassa uma mensagem de grupo a todos os outros participantes da sala.
+    /// Falha com `Unexpected` se o remetente não está mais na sala.
+    pub fn group(&self, from: ClientId, room: &str, message: GroupMessage) -> Result<(), ErrorCode> {
+        let mut state = self.state.lock().unwrap();
+        let Some(sender) = state.members.get(&from).filter(|sender| sender.room == room) else {
+            return Err(ErrorCode::Unexpected);
+        };
+        let delivery = Delivery::Group { from: sender.name.clone(), room: room.to_string(), sender_key: sender.public_key.clone(), message };
+        state.send_to_room(room, Some(from), &delivery);
+        Ok(())
+    }
+
+    /// Sala atual do cliente e a chave pública de cada participante dela.
+    pub fn room_keys(&self, id: ClientId) -> Option<(String, Vec<(String, PublicKey)>)> {
+        let state = self.state.lock().unwrap();
+        let room = state.members.get(&id)?.room.clone();
+        let keys = state.rooms.get(&room)
+            .map(|ids| ids.iter().filter_map(|id| state.members.get(id)).map(|m| (m.name.clone(), m.public_key.clone())).collect())
+            .unwrap_or_default();
+        Some((room, keys))
+    }
+
+    /// Move o cliente para outra sala, avisando os participantes das duas.
+    pub fn join(&self, id: ClientId, room: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.members.get(&id).is_some_and(|member| member.room == room) {
+            return;
+        }
+        state.exit(id);
+        state.enter(id, room);
+    }
+
+    /// Remove o cliente do hub quando a conexão termina.
+    pub fn leave(&self, id: ClientId) {
+        let mut state = self.state.lock().unwrap();
+        state.exit(id);
+        if let Some(member) = state.members.remove(&id) {
+            state.nicknames.remove(&member.name.to_lowercase());
+            if let Some(listing) = state.directory.get_mut(&member.name.to_lowercase()) {
+                listing.left_at = Some(mailbox::now());
+            }
+        }
+    }
+
+    /// Repassa a mensagem do cliente para todos os outros participantes da sala dele.
+    pub fn broadcast(&self, from: ClientId, text: &str) {
+        let mut state = self.state.lock().unwrap();
+        let Some(sender) = state.members.get(&from) else { return };
+        let room = sender.room.clone();
+        let delivery = Delivery::Chat { from: sender.name.clone(), text: text.to_string() };
+        state.send_to_room(&room, Some(from), &delivery);
+    }
+}
+
+impl Member {
+    // Coloca a entrega na fila do cliente sem esperar. Se a fila está cheia, o cliente
+    // não acompanha o que chega: o hub larga a fila, e a sessão dele envia o que já
+    // estava nela e termina. Devolve `false` se a entrega não entrou na fila.
+    fn deliver(&mut self, delivery: Delivery) -> bool {
+        let Some(tx) = &self.tx else { return false };
+        match tx.try_send(delivery) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.tx = None;
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+impl HubState {
+    // Vincula o apelido livre à chave no diretório e descarta as entradas de quem saiu
+    // há mais tempo que o prazo da caixa postal
+    fn claim(&mut self, name: &str, public_key: &PublicKey) {
+        let oldest = mailbox::now().saturating_sub(self.retention.as_secs());
+        self.directory.retain(|_, listing| listing.left_at.is_none_or(|left_at| left_at >= oldest));
+        self.directory.insert(name.to_lowercase(), Listing { public_key: public_key.clone(), left_at: None });
+    }
+
+    // Coloca o cliente na sala, avisa os presentes e envia ao cliente a lista de participantes
+    fn enter(&mut self, id: ClientId, room: &str) {
+        let Some(name) = self.members.get(&id).map(|member| member.name.clone()) else { return };
+        let presence = Delivery::Presence { room: room.to_string(), who: name, joined: true };
+        self.send_to_room(room, Some(id), &presence);
+
+        self.rooms.entry(room.to_string()).or_default().insert(id);
+        let members = self.names_in(room);
+        if let Some(member) = self.members.get_mut(&id) {
+            member.room = room.to_string();
+            member.deliver(Delivery::Joined { room: room.to_string(), members });
+        }
+    }
+
+    // Tira o cliente da sala atual e avisa quem ficou; salas vazias são descartadas
+    fn exit(&mut self, id: ClientId) {
+        let Some(member) = self.members.get(&id) else { return };
+        let (room, name) = (member.room.clone(), member.name.clone());
+        if let Some(ids) = self.rooms.get_mut(&room) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.rooms.remove(&room);
+            }
+        }
+        let presence = Delivery::Presence { room: room.clone(), who: name, joined: false };
+        self.send_to_room(&room, None, &presence);
+    }
+
+    fn names_in(&self, room: &str) -> Vec<String> {
+        self.rooms.get(room)
+            .map(|ids| ids.iter().filter_map(|id| self.members.get(id)).map(|m| m.name.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    fn send_to_room(&mut self, room: &str, except: Option<ClientId>, delivery: &Delivery) {
+        let Some(ids) = self.rooms.get(room) else { return };
+        for id in ids.iter().filter(|id| Some(**id) != except) {
+            if let Some(member) = self.members.get_mut(id) {
+                member.deliver(delivery.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rsa::{self, PrivateKey};
+    use crate::session::Verdict;
+
+    struct Client {
+        id: ClientId,
+        inbox: Inbox,
+    }
+
+    fn keypair() -> (PublicKey, PrivateKey) {
+        rsa::new_keypair(None)
+    }
+
+    fn register(hub: &Hub, name: &str, public_key: &PublicKey) -> Result<Client, ErrorCode> {
+        let (id, inbox) = hub.register(name.to_string(), "127.0.0.1:1".parse().unwrap(), public_key.clone(), Arc::new(Traffic::default()))?;
+        Ok(Client { id, inbox })
+    }
+
+    // Tudo o que o hub já entregou ao cliente
+    fn received(client: &mut Client) -> Vec<Delivery> {
+        std::iter::from_fn(|| client.inbox.deliveries.try_recv().ok()).collect()
+    }
+
+    fn message(to: &PublicKey, from: &PrivateKey) -> DirectMessage {
+        DirectMessage::seal("oi", &[], to, from).unwrap()
+    }
+
+    #[test]
+    fn nickname_is_unique_while_in_use() {
+        let hub = Hub::new(MailboxConfig::default());
+        let (key, _) = keypair();
+        let ana = register(&hub, "Ana", &key).unwrap();
+        assert_eq!(register(&hub, "ana", &key).err(), Some(ErrorCode::NicknameTaken));
+        assert_eq!(hub.id_of("ANA"), Some(ana.id));
+
+        // A mesma chave volta com o apelido depois de sair.
+        hub.leave(ana.id);
+        assert!(register(&hub, "ana", &key).is_ok());
+    }
+
+    #[test]
+    fn nickname_is_released_when_its_owner_leaves() {
+        let hub = Hub::new(MailboxConfig::default());
+        let (old_key, _) = keypair();
+        let (new_key, _) = keypair();
+        let ana = register(&hub, "ana", &old_key).unwrap();
+        assert_eq!(register(&hub, "ana", &new_key).err(), Some(ErrorCode::NicknameTaken));
+
+        // Sem `--identity`, o cliente volta com outra chave e recupera o apelido.
+        hub.leave(ana.id);
+        assert_eq!(hub.public_key_of("ana"), Some(old_key));
+        assert!(register(&hub, "Ana", &new_key).is_ok());
+        assert_eq!(hub.public_key_of("ana"), Some(new_key));
+    }
+
+    #[test]
+    fn rename_frees_the_old_nickname() {
+        let hub = Hub::new(MailboxConfig::default());
+        let (ana_key, _) = keypair();
+        let (bia_key, _) = keypair();
+        let ana = register(&hub, "ana", &ana_key).unwrap();
+        let bia = register(&hub, "bia", &bia_key).unwrap();
+        assert_eq!(hub.rename(bia.id, "ANA"), Err(ErrorCode::NicknameTaken));
+        assert_eq!(hub.rename(bia.id, "Bia"), Ok("bia".to_string()));
+        assert_eq!(hub.public_key_of("bia"), Some(bia_key.clone()));
+
+        // Cada conexão segura só o apelido atual.
+        for name in ["b1", "b2", "beatriz"] {
+            hub.rename(bia.id, name).unwrap();
+        }
+        assert_eq!(hub.name_of(bia.id).as_deref(), Some("beatriz"));
+        assert!(["bia", "b1", "b2"].iter().all(|name| hub.public_key_of(name).is_none()));
+        assert_eq!(hub.public_key_of("beatriz"), Some(bia_key));
+
+        hub.leave(ana.id);
+        assert_eq!(hub.rename(bia.id, "ana"), Ok("beatriz".to_string()));
+    }
+
+    #[test]
+    fn departed_owners_expire_with_the_mailbox() {
+        let hub = Hub::new(MailboxConfig::default());
+        let (ana_key, _) = keypair();
+        let (bia_key, _) = keypair();
+        let ana = register(&hub, "ana", &ana_key).unwrap();
+        hub.leave(ana.id);
+        hub.state.lock().unwrap().directory.get_mut("ana").unwrap().left_at = Some(0);
+        register(&hub, "bia", &bia_key).unwrap();
+        assert_eq!(hub.public_key_of("ana"), None);
+        assert_eq!(hub.public_key_of("bia"), Some(bia_key));
+    }
+
+    #[test]
+    fn invalid_keys_cannot_register() {
+        let hub = Hub::new(MailboxConfig::default());
+        assert_eq!(register(&hub, "ana", &PublicKey { e: 3, n: 100 }).err(), Some(ErrorCode::InvalidKey));
+        assert_eq!(hub.public_key_of("ana"), None);
+    }
+
+    #[test]
+    fn offline_messages_wait_for_the_nickname_owner() {
+        let hub = Hub::new(MailboxConfig::default());
+        let (ana_key, ana_priv) = keypair();
+        let (bia_key, bia_priv) = keypair();
+        let ana = register(&hub, "ana", &ana_key).unwrap();
+        let mut bia = register(&hub, "bia", &bia_key).unwrap();
+        assert_eq!(hub.direct(ana.id, "bia", None, message(&bia_key, &ana_priv)), Ok(Routed::Delivered));
+        assert!(received(&mut bia).iter().any(|delivery| matches!(delivery, Delivery::Direct { from, .. } if from == "ana")));
+
+        hub.leave(bia.id);
+        assert_eq!(hub.direct(ana.id, "bia", None, message(&bia_key, &ana_priv)), Ok(Routed::Stored));
+        assert_eq!(hub.direct(ana.id, "caio", None, message(&bia_key, &ana_priv)), Err(ErrorCode::UnknownUser));
+
+        let mut bia = register(&hub, "bia", &bia_key).unwrap();
+        assert_eq!(hub.deliver_mail(bia.id), 1);
+        let stored: Vec<_> = received(&mut bia).into_iter().filter_map(|delivery| match delivery {
+            Delivery::Stored(stored) => Some(stored),
+            _ => None,
+        }).collect();
+        let [stored] = &stored[..] else { panic!("esperava uma mensagem guardada") };
+        assert_eq!(stored.message.open(&[], &bia_key, &bia_priv, &ana_key), ("oi".to_string(), Verdict::Valid));
+    }
+
+    #[test]
+    fn mailbox_quota_is_enforced() {
+        let hub = Hub::new(MailboxConfig { quota: 1, ..MailboxConfig::default() });
+        let (ana_key, ana_priv) = keypair();
+        let (bia_key, _) = keypair();
+        let ana = register(&hub, "ana", &ana_key).unwrap();
+        let bia = register(&hub, "bia", &bia_key).unwrap();
+        hub.leave(bia.id);
+        assert_eq!(hub.direct(ana.id, "bia", None, message(&bia_key, &ana_priv)), Ok(Routed::Stored));
+        assert_eq!(hub.direct(ana.id, "bia", None, message(&bia_key, &ana_priv)), Err(ErrorCode::MailboxFull));
+    }
+
+    #[test]
+    fn client_that_falls_behind_is_dropped() {
+        let hub = Hub::new(MailboxConfig::default());
+        let (ana_key, _) = keypair();
+        let (bia_key, _) = keypair();
+        let ana = register(&hub, "ana", &ana_key).unwrap();
+        let mut bia = register(&hub, "bia", &bia_key).unwrap();
+        received(&mut bia);
+        for _ in 0..DELIVERY_QUEUE {
+            hub.broadcast(ana.id, "oi");
+        }
+        assert!(!hub.send_to(bia.id, Delivery::Operator { text: "oi".to_string(), tamper: false }));
+
+        // O que já estava na fila ainda chega; depois dela, a fila fecha com o cliente no hub.
+        assert_eq!(received(&mut bia).len(), DELIVERY_QUEUE);
+        assert!(matches!(bia.inbox.deliveries.try_recv(), Err(mpsc::error::TryRecvError::Disconnected)));
+        assert!(hub.name_of(bia.id).is_some());
+        assert!(hub.send_to(ana.id, Delivery::Operator { text: "oi".to_string(), tamper: false }));
+    }
+
+    #[test]
+    fn file_relay_queue_is_bounded() {
+        let hub = Hub::new(MailboxConfig::default());
+        let (ana_key, _) = keypair();
+        let (bia_key, _) = keypair();
+        let ana = register(&hub, "ana", &ana_key).unwrap();
+        let mut bia = register(&hub, "bia", &bia_key).unwrap();
+        let chunk = |index| FileTransfer::Chunk { id: 1, index, data: vec![0; 8], proof: Vec::new() };
+        for index in 0..FILE_QUEUE as u64 {
+            let (files, delivery) = hub.file(ana.id, "bia", chunk(index)).unwrap();
+            files.try_send(delivery).unwrap();
+        }
+        let (files, delivery) = hub.file(ana.id, "bia", chunk(FILE_QUEUE as u64)).unwrap();
+        let Err(TrySendError::Full(delivery)) = files.try_send(delivery) else { panic!("a fila de arquivos deveria estar cheia") };
+
+        // Quando o destinatário tira um pedaço, o próximo entra.
+        let first = bia.inbox.files.try_recv().unwrap();
+        assert!(matches!(first.transfer, FileTransfer::Chunk { index: 0, .. }) && first.from == "ana");
+        files.try_send(delivery).unwrap();
+        assert_eq!(hub.file(ana.id, "caio", chunk(0)).err(), Some(ErrorCode::UnknownUser));
+    }
+}
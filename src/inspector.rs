@@ -0,0 +1,135 @@
+use crate::handshake::{CipherAlgorithm, CipherSuite};
+use crate::protocol::Ciphertext;
+use crate::rsa::PublicKey;
+use crate::session::Verdict;
+
+/// Tamanho do bloco do fluxo de chave no modo SHA256-CTR.
+const STREAM_BLOCK: usize = 32;
+
+/// Quantos bytes de cada campo o inspetor mostra antes de abreviar.
+const MAX_SHOWN_BYTES: usize = 96;
+
+/// Sentido da mensagem inspecionada.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Os passos criptográficos de uma mensagem de chat da sessão, para o inspetor.
+///
+/// Guarda o texto claro, o texto cifrado como foi para a rede, o valor de
+/// integridade e a chave RSA envolvida; `lines` monta a explicação mostrada.
+pub struct Inspection {
+    pub direction: Direction,
+    /// Com quem a sessão foi feita: "servidor" ou o apelido do cliente.
+    pub peer: String,
+    pub seq: u64,
+    pub algorithm: CipherAlgorithm,
+    pub plaintext: Vec<u8>,
+    pub ciphertext: Ciphertext,
+    pub integrity: [u8; 32],
+    /// Resultado da verificação; `None` nas mensagens enviadas.
+    pub verdict: Option<Verdict>,
+    /// Chave pública que cifra no modo RSA: a do outro lado ao enviar, a própria ao receber.
+    pub key: PublicKey,
+}
+
+impl Inspection {
+    pub fn lines(&self) -> Vec<String> {
+        let (verb, arrow) = match self.direction {
+            Direction::Sent => ("enviada", "→"),
+            Direction::Received => ("recebida", "←"),
+        };
+        let mut lines = vec![format!("── Mensagem #{} {} {} {} ──", self.seq, verb, arrow, self.peer)];
+        lines.push(format!(
+            "Texto claro ({} bytes): {} {:?}",
+            self.plaintext.len(),
+            spaced_hex(&self.plaintext),
+            String::from_utf8_lossy(&self.plaintext),
+        ));
+
+        match (&self.algorithm, &self.ciphertext) {
+            (CipherAlgorithm::RsaBlock, Ciphertext::RsaBlocks(blocks)) => {
+                lines.push(format!(
+                    "Blocos RSA ({}, sem padding: um byte por bloco, m < n): {}",
+                    self.plaintext.len(),
+                    abbreviate(self.plaintext.iter().map(|byte| byte.to_string()).collect()),
+                ));
+                lines.push(format!(
+                    "Texto cifrado (c = m^e mod n, {} blocos u64): {}",
+                    blocks.len(),
+                    abbreviate(blocks.iter().map(|block| format!("{:016x}", block)).collect()),
+                ));
+                lines.push(format!("Hash SHA-256 (seq + dados associados + texto claro): {}", hex::encode(self.integrity)));
+            }
+            (_, Ciphertext::Stream(bytes)) => {
+                let blocks = self.plaintext.len().div_ceil(STREAM_BLOCK);
+                let last = self.plaintext.len() - blocks.saturating_sub(1) * STREAM_BLOCK;
+                lines.push(format!(
+                    "Blocos do fluxo de chave SHA-256(chave || nonce {} || i): {} de {} bytes, o último com {} bytes usados (sem padding); claro → cifrado:",
+                    self.seq, blocks, STREAM_BLOCK, last,
+                ));
+                for (i, (plain, sealed)) in self.plaintext.chunks(STREAM_BLOCK).zip(bytes.chunks(STREAM_BLOCK)).enumerate().take(3) {
+                    lines.push(format!("  bloco {}: {} → {}", i, hex::encode(plain), hex::encode(sealed)));
+                }
+                if blocks > 3 {
+                    lines.push(format!("  ... mais {} blocos", blocks - 3));
+                }
+                lines.push(format!("Texto cifrado (texto claro XOR fluxo, {} bytes): {}", bytes.len(), spaced_hex(bytes)));
+                lines.push(format!("HMAC-SHA-256 (seq + dados associados + texto cifrado): {}", hex::encode(self.integrity)));
+            }
+            (_, Ciphertext::RsaBlocks(blocks)) => {
+                lines.push(format!("Texto cifrado em blocos RSA ({} blocos), diferente da cifra negociada", blocks.len()));
+            }
+        }
+
+        lines.push(match self.verdict {
+            None => "Verificação: calculada por este lado, conferida pelo outro ao receber".to_string(),
+            Some(Verdict::Valid) => "Verificação: VÁLIDA".to_string(),
+            Some(Verdict::Invalid) => "Verificação: INVÁLIDA (hash/MAC não confere, mensagem adulterada)".to_string(),
+            Some(Verdict::Replayed) => "Verificação: descartada, número de sequência repetido (replay)".to_string(),
+            Some(Verdict::OutOfWindow) => "Verificação: descartada, número de sequência fora da janela".to_string(),
+            Some(Verdict::InvalidUtf8) => "Verificação: íntegra, mas o texto não é UTF-8 válido".to_string(),
+        });
+        let owner = match self.direction {
+            Direction::Sent => "do outro lado",
+            Direction::Received => "própria",
+        };
+        lines.push(format!("Chave RSA {}: {}", owner, key_parameters(&self.key)));
+        lines
+    }
+}
+
+/// Resumo do handshake: a suíte negociada e as duas chaves RSA.
+pub fn handshake_lines(peer: &str, suite: &CipherSuite, own: &PublicKey, peer_key: &PublicKey) -> Vec<String> {
+    vec![
+        format!("── Handshake com {} ──", peer),
+        format!("Suíte negociada: {}", suite),
+        format!("Chave RSA própria ({}): {}", own.fingerprint(), key_parameters(own)),
+        format!("Chave RSA do outro lado ({}): {}", peer_key.fingerprint(), key_parameters(peer_key)),
+    ]
+}
+
+/// n e e da chave, com o tamanho em bits de cada um.
+pub fn key_parameters(key: &PublicKey) -> String {
+    format!("n = {} ({} bits), e = {} ({} bits)", key.n, bits(key.n), key.e, bits(key.e))
+}
+
+fn bits(value: u64) -> u32 {
+    u64::BITS - value.leading_zeros()
+}
+
+fn spaced_hex(bytes: &[u8]) -> String {
+    abbreviate(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Junta os itens com espaços, cortando os que passam do limite
+fn abbreviate(items: Vec<String>) -> String {
+    let total = items.len();
+    let mut shown = items.into_iter().take(MAX_SHOWN_BYTES).collect::<Vec<_>>().join(" ");
+    if total > MAX_SHOWN_BYTES {
+        shown.push_str(&format!(" ... (+{})", total - MAX_SHOWN_BYTES));
+    }
+    shown
+}
@@ -0,0 +1,85 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// O que o arquivo de servidores conhecidos diz sobre a chave recebida.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostCheck {
+    /// A impressão digital é a mesma guardada para o servidor.
+    Known,
+    /// Primeira conexão a este servidor: nada guardado ainda.
+    New,
+    /// O servidor apresentou outra chave; `expected` é a impressão digital guardada.
+    Changed { expected: String },
+}
+
+/// Confere a impressão digital da chave de `server` (`host:porta`) com o arquivo.
+///
+/// Cada linha do arquivo tem o servidor e a impressão digital separados por um
+/// espaço; linhas vazias e começadas por `#` são ignoradas. Um arquivo que não
+/// existe é tratado como vazio.
+pub fn check(path: &Path, server: &str, fingerprint: &str) -> io::Result<HostCheck> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HostCheck::New),
+        Err(e) => return Err(e),
+    };
+    let known = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(host, _)| *host == server)
+        .map(|(_, known)| known.trim());
+    Ok(match known {
+        None => HostCheck::New,
+        Some(known) if known == fingerprint => HostCheck::Known,
+        Some(known) => HostCheck::Changed { expected: known.to_string() },
+    })
+}
+
+/// Acrescenta `server` e a impressão digital ao final do arquivo (criado se não existir).
+pub fn remember(path: &Path, server: &str, fingerprint: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{} {}", server, fingerprint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // Arquivo temporário apagado ao fim do teste
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("chat_rsa_conhecidos_{}_{}", std::process::id(), name));
+            let _ = std::fs::remove_file(&path);
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn first_connection_is_new_then_known() {
+        let file = TempFile::new("primeira");
+        assert_eq!(check(&file.0, "[::1]:8080", "aa:bb").unwrap(), HostCheck::New);
+        remember(&file.0, "[::1]:8080", "aa:bb").unwrap();
+        assert_eq!(check(&file.0, "[::1]:8080", "aa:bb").unwrap(), HostCheck::Known);
+        assert_eq!(check(&file.0, "[::1]:9000", "cc:dd").unwrap(), HostCheck::New);
+    }
+
+    #[test]
+    fn changed_key_reports_the_pinned_one() {
+        let file = TempFile::new("mudou");
+        std::fs::write(&file.0, "# comentário\n\nchat.exemplo.org:9000  aa:bb\n").unwrap();
+        assert_eq!(check(&file.0, "chat.exemplo.org:9000", "aa:bb").unwrap(), HostCheck::Known);
+        assert_eq!(check(&file.0, "chat.exemplo.org:9000", "cc:dd").unwrap(), HostCheck::Changed { expected: "aa:bb".to_string() });
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::direct::DirectMessage;
+use crate::rsa::PublicKey;
+
+/// Limites da caixa postal do servidor.
+#[derive(Debug, Clone, Copy)]
+pub struct MailboxConfig {
+    /// Por quanto tempo uma mensagem guardada espera o destinatário.
+    pub retention: Duration,
+    /// Quantas mensagens podem ficar guardadas para cada destinatário.
+    pub quota: usize,
+}
+
+impl Default for MailboxConfig {
+    fn default() -> Self {
+        MailboxConfig { retention: Duration::from_secs(7 * 24 * 60 * 60), quota: 100 }
+    }
+}
+
+/// Mensagem privada guardada para um destinatário desconectado.
+///
+/// O conteúdo continua cifrado para a chave do destinatário: o servidor guarda
+/// só os bytes opacos, o remetente e a hora em que a mensagem chegou.
+#[derive(Debug, Clone)]
+pub struct StoredMessage {
+    pub from: String,
+    pub sender_key: PublicKey,
+    pub sent_at: u64,
+    pub message: DirectMessage,
+}
+
+/// Caixa postal do servidor: mensagens esperando destinatários desconectados,
+/// indexadas pela impressão digital da chave pública de cada um.
+#[derive(Default)]
+pub struct Mailbox {
+    config: MailboxConfig,
+    boxes: HashMap<String, Vec<StoredMessage>>,
+}
+
+impl Mailbox {
+    pub fn new(config: MailboxConfig) -> Self {
+        Mailbox { config, boxes: HashMap::new() }
+    }
+
+    /// Guarda a mensagem para o dono da chave `recipient`. Devolve `false` se a
+    /// cota dele já está cheia.
+    pub fn store(&mut self, recipient: &PublicKey, stored: StoredMessage) -> bool {
+        self.expire();
+        let messages = self.boxes.entry(recipient.fingerprint()).or_default();
+        if messages.len() >= self.config.quota {
+            return false;
+        }
+        messages.push(stored);
+        true
+    }
+
+    /// Retira todas as mensagens ainda válidas guardadas para a chave, em ordem de chegada.
+    pub fn take(&mut self, recipient: &PublicKey) -> Vec<StoredMessage> {
+        self.expire();
+        self.boxes.remove(&recipient.fingerprint()).unwrap_or_default()
+    }
+
+    // Descarta as mensagens mais velhas que o prazo de retenção
+    fn expire(&mut self) {
+        let oldest = now().saturating_sub(self.config.retention.as_secs());
+        for messages in self.boxes.values_mut() {
+            messages.retain(|stored| stored.sent_at >= oldest);
+        }
+        self.boxes.retain(|_, messages| !messages.is_empty());
+    }
+}
+
+/// Hora atual em segundos desde 1970 (UTC).
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
+
+/// Formata segundos desde 1970 como "AAAA-MM-DD HH:MM UTC".
+pub fn format_timestamp(secs: u64) -> String {
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    // Conversão de dias para data civil (algoritmo de Howard Hinnant).
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, rest / 3_600, rest % 3_600 / 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rsa;
+
+    // Mensagem de `from` para `recipient`, enviada em `sent_at`
+    fn stored(recipient: &PublicKey, from: &str, sent_at: u64) -> StoredMessage {
+        let (sender_key, sender_priv) = rsa::new_keypair(None);
+        let message = DirectMessage::seal(from, &[], recipient, &sender_priv).unwrap();
+        StoredMessage { from: from.to_string(), sender_key, sent_at, message }
+    }
+
+    #[test]
+    fn delivers_in_order_once() {
+        let (ana, _) = rsa::new_keypair(None);
+        let mut mailbox = Mailbox::default();
+        assert!(mailbox.store(&ana, stored(&ana, "bia", now())));
+        assert!(mailbox.store(&ana, stored(&ana, "caio", now())));
+        let from: Vec<_> = mailbox.take(&ana).into_iter().map(|stored| stored.from).collect();
+        assert_eq!(from, ["bia", "caio"]);
+        assert!(mailbox.take(&ana).is_empty());
+    }
+
+    #[test]
+    fn quota_is_per_recipient() {
+        let (ana, _) = rsa::new_keypair(None);
+        let (bia, _) = rsa::new_keypair(None);
+        let mut mailbox = Mailbox::new(MailboxConfig { quota: 2, ..MailboxConfig::default() });
+        assert!(mailbox.store(&ana, stored(&ana, "caio", now())));
+        assert!(mailbox.store(&ana, stored(&ana, "caio", now())));
+        assert!(!mailbox.store(&ana, stored(&ana, "caio", now())));
+        assert!(mailbox.store(&bia, stored(&bia, "caio", now())));
+        assert_eq!(mailbox.take(&ana).len(), 2);
+        // Depois da entrega a cota volta a valer do zero.
+        assert!(mailbox.store(&ana, stored(&ana, "caio", now())));
+    }
+
+    #[test]
+    fn expired_messages_are_dropped_and_free_the_quota() {
+        let (ana, _) = rsa::new_keypair(None);
+        let retention = Duration::from_secs(60 * 60);
+        let mut mailbox = Mailbox::new(MailboxConfig { retention, quota: 1 });
+        assert!(mailbox.store(&ana, stored(&ana, "bia", now() - retention.as_secs() - 1)));
+        assert!(mailbox.store(&ana, stored(&ana, "caio", now() - 60)));
+        let from: Vec<_> = mailbox.take(&ana).into_iter().map(|stored| stored.from).collect();
+        assert_eq!(from, ["caio"]);
+    }
+
+    #[test]
+    fn formats_timestamps_in_utc() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(951_825_600), "2000-02-29 12:00 UTC");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13 UTC");
+    }
+}
@@ -1,19 +1,100 @@
 mod rsa;
 mod network;
 mod sha;
+mod frame;
+mod protocol;
+mod handshake;
+mod session;
+mod cipher;
+mod dh;
+mod hub;
+mod commands;
+mod direct;
+mod console;
+mod group;
+mod mailbox;
+mod history;
+mod transfer;
+mod merkle;
+mod ui;
+mod tui;
+mod dashboard;
+mod traffic;
+mod inspector;
+mod cli;
+mod error;
+mod config;
+mod known_hosts;
+mod resumption;
 
-// Se o codigo for passado com --server como argumento, o terminal servirá
-//como servidor, sem, será cliente
+use std::io::IsTerminal;
+use std::path::Path;
+use clap::Parser;
+use cli::{Cli, Command};
+use frame::FrameCodec;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let args: Vec<String> = std::env::args().collect();
+// Sem subcomando, o programa é o cliente; `server` o transforma no servidor.
+// `chat_rsa --help` lista os subcomandos e as opções de cada um; `--config` e
+// `--profile` escolhem os valores padrão das opções (ver `config`).
 
-    if args.len() > 1 && args[1] == "--server" {
-        network::start_server(8080).await;
-    } else {
-        network::start_client("127.0.0.1:8080").await;
+fn main() -> anyhow::Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(run(Cli::parse()));
+    // Uma leitura do teclado em andamento não pode ser cancelada: sai sem esperar por ela.
+    runtime.shutdown_background();
+    result
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    match cli.command.unwrap_or(Command::Connect(cli.connect)) {
+        Command::Server(args) => {
+            let profile = config::load(cli.config.as_deref(), cli.profile.as_deref())?;
+            network::start_server(args.config(profile), FrameCodec::default()).await?
+        }
+        Command::Connect(args) => {
+            let profile = config::load(cli.config.as_deref(), cli.profile.as_deref())?;
+            network::start_client(args.config(profile), FrameCodec::default()).await?
+        }
+        Command::Keygen { file, bits, force } => keygen(&file, bits, force)?,
+        Command::Hash { file } => hash(file.as_deref())?,
+        Command::Fingerprint { file } => fingerprint(&file)?,
+    }
+
+    Ok(())
+}
+
+// Gera um par de chaves no formato lido por --identity e --key
+fn keygen(file: &Path, bits: Option<u32>, force: bool) -> anyhow::Result<()> {
+    if file.exists() && !force {
+        anyhow::bail!("{} já existe; use --force para substituí-lo", file.display());
     }
+    let keypair = rsa::new_keypair(bits);
+    rsa::save_keypair(file, &keypair)?;
+    println!("Par de chaves salvo em {}", file.display());
+    println!("Impressão digital: {}", keypair.0.fingerprint());
+    println!("{}", inspector::key_parameters(&keypair.0));
+    Ok(())
+}
+
+// SHA-256 do arquivo, ou da entrada padrão sem arquivo
+fn hash(file: Option<&Path>) -> anyhow::Result<()> {
+    let digest = match file {
+        Some(file) => sha::sha256_reader(std::fs::File::open(file)?)?,
+        None => {
+            if std::io::stdin().is_terminal() {
+                eprintln!("Lendo a entrada padrão (Ctrl-D termina)...");
+            }
+            sha::sha256_reader(std::io::stdin().lock())?
+        }
+    };
+    sha::display_hash(&digest);
+    Ok(())
+}
 
+// Impressão digital e parâmetros de um par de chaves salvo
+fn fingerprint(file: &Path) -> anyhow::Result<()> {
+    let (public_key, _) = rsa::load_keypair(file)?;
+    println!("Impressão digital: {}", public_key.fingerprint());
+    println!("{}", inspector::key_parameters(&public_key));
     Ok(())
-}
\ No newline at end of file
+}
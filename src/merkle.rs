@@ -0,0 +1,156 @@
+use crate::sha::sha256;
+
+/// Hash de uma folha (um pedaço do arquivo). O prefixo separa folhas de nós
+/// internos, para que um nó não possa se passar por pedaço.
+pub fn leaf_hash(chunk: &[u8]) -> [u8; 32] {
+    let mut data = Vec::with_capacity(1 + chunk.len());
+    data.push(0);
+    data.extend_from_slice(chunk);
+    sha256(&data)
+}
+
+// Hash de um nó interno a partir dos dois filhos
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut data = [0u8; 65];
+    data[0] = 1;
+    data[1..33].copy_from_slice(left);
+    data[33..].copy_from_slice(right);
+    sha256(&data)
+}
+
+/// Árvore de Merkle sobre os hashes dos pedaços de um arquivo.
+///
+/// Cada nível junta os nós do anterior dois a dois; um nó que sobra sozinho no
+/// fim de um nível sobe sem mudança. Com a raiz e a prova de um pedaço (os
+/// irmãos no caminho até a raiz), dá para conferir esse pedaço sozinho.
+pub struct MerkleTree {
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleTree {
+    /// Monta a árvore a partir dos hashes das folhas (pelo menos uma).
+    pub fn new(leaves: Vec<[u8; 32]>) -> Self {
+        assert!(!leaves.is_empty(), "a árvore precisa de pelo menos uma folha");
+        let mut levels = vec![leaves];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels.last().unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_hash(left, right),
+                    [alone] => *alone,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        MerkleTree { levels }
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.levels.last().unwrap()[0]
+    }
+
+    /// Hash da folha `index`.
+    pub fn leaf(&self, index: usize) -> [u8; 32] {
+        self.levels[0][index]
+    }
+
+    /// Irmãos da folha `index`, de baixo para cima.
+    pub fn proof(&self, index: usize) -> Vec<[u8; 32]> {
+        let mut proof = Vec::new();
+        let mut index = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = index ^ 1;
+            if sibling < level.len() {
+                proof.push(level[sibling]);
+            }
+            index /= 2;
+        }
+        proof
+    }
+}
+
+/// Confere se a folha `leaf` está na posição `index` da árvore de `leaf_count`
+/// folhas com essa `root`.
+pub fn verify(root: &[u8; 32], leaf_count: u64, index: u64, leaf: [u8; 32], proof: &[[u8; 32]]) -> bool {
+    if index >= leaf_count {
+        return false;
+    }
+    let (mut index, mut width, mut hash) = (index, leaf_count, leaf);
+    let mut siblings = proof.iter();
+    while width > 1 {
+        if index % 2 == 1 {
+            let Some(sibling) = siblings.next() else { return false };
+            hash = node_hash(sibling, &hash);
+        } else if index + 1 < width {
+            let Some(sibling) = siblings.next() else { return false };
+            hash = node_hash(&hash, sibling);
+        }
+        index /= 2;
+        width = width.div_ceil(2);
+    }
+    siblings.next().is_none() && hash == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: usize) -> Vec<[u8; 32]> {
+        (0..count).map(|i| leaf_hash(&i.to_be_bytes())).collect()
+    }
+
+    #[test]
+    fn every_leaf_verifies_against_the_root() {
+        for count in 1..=9 {
+            let tree = MerkleTree::new(leaves(count));
+            for (index, leaf) in leaves(count).into_iter().enumerate() {
+                assert_eq!(tree.leaf(index), leaf);
+                assert!(verify(&tree.root(), count as u64, index as u64, leaf, &tree.proof(index)), "{} de {}", index, count);
+            }
+        }
+    }
+
+    #[test]
+    fn single_leaf_is_the_root() {
+        let tree = MerkleTree::new(leaves(1));
+        assert_eq!(tree.root(), leaves(1)[0]);
+        assert!(tree.proof(0).is_empty());
+    }
+
+    #[test]
+    fn wrong_leaf_or_position_is_rejected() {
+        let tree = MerkleTree::new(leaves(5));
+        let proof = tree.proof(2);
+        assert!(!verify(&tree.root(), 5, 2, leaf_hash(b"outro"), &proof));
+        assert!(!verify(&tree.root(), 5, 3, tree.leaf(2), &proof));
+        assert!(!verify(&tree.root(), 5, 5, tree.leaf(2), &proof));
+        // A última folha sobe sem irmão; com outro número de folhas a prova não fecha.
+        assert!(!verify(&tree.root(), 8, 4, tree.leaf(4), &tree.proof(4)));
+        assert!(!verify(&MerkleTree::new(leaves(4)).root(), 5, 2, tree.leaf(2), &proof));
+    }
+
+    #[test]
+    fn altered_proofs_are_rejected() {
+        let tree = MerkleTree::new(leaves(6));
+        let proof = tree.proof(1);
+        assert!(!verify(&tree.root(), 6, 1, tree.leaf(1), &proof[..proof.len() - 1]));
+        let mut longer = proof.clone();
+        longer.push([0; 32]);
+        assert!(!verify(&tree.root(), 6, 1, tree.leaf(1), &longer));
+        let mut changed = proof;
+        changed[0][0] ^= 1;
+        assert!(!verify(&tree.root(), 6, 1, tree.leaf(1), &changed));
+    }
+
+    #[test]
+    fn inner_nodes_cannot_pose_as_leaves() {
+        let tree = MerkleTree::new(leaves(4));
+        let node = node_hash(&tree.leaf(0), &tree.leaf(1));
+        // O nó interno ocupa a posição 0 de uma árvore de 2 folhas com a mesma raiz,
+        // mas como pedaço ele teria o hash de folha, com outro prefixo.
+        let joined: Vec<u8> = tree.leaf(0).iter().chain(tree.leaf(1).iter()).copied().collect();
+        assert_ne!(leaf_hash(&joined), node);
+        assert!(!verify(&tree.root(), 2, 0, leaf_hash(&joined), &[node_hash(&tree.leaf(2), &tree.leaf(3))]));
+    }
+}
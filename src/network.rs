@@ -1,274 +1,1520 @@
 use tokio::net::TcpListener;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, AsyncBufReadExt, BufReader as TokioBufReader};
+use tokio::io::AsyncWrite;
 use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::{JoinHandle, JoinSet};
+use std::collections::HashMap;
+use std::io;
 use std::net::SocketAddr;
-use bincode::{serialize as bincode_serialize, deserialize as bincode_deserialize};
-use crate::rsa::{self, PublicKey, encrypt_string, PrivateKey};
-use crate::sha::sha256;
-use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::direct::DirectMessage;
+use crate::error::ChatError;
+use crate::group::GroupSession;
+use crate::commands::{self, ClientAction, Input, CLIENT_COMMANDS};
+use crate::console;
+use crate::traffic::{Metered, Traffic};
+use crate::frame::FrameCodec;
+use crate::handshake::{self, Offer};
+use crate::inspector::{self, Inspection};
+use crate::history::{Entry, History};
+use crate::known_hosts::{self, HostCheck};
+use crate::hub::{ClientId, Delivery, FileDelivery, Hub, Inbox, Routed, DEFAULT_ROOM};
+use crate::mailbox::{self, MailboxConfig};
+use crate::protocol::{self, Envelope, ErrorCode, Packet, ReadError};
+use crate::resumption::{Ticket, TicketStore, TICKET_LIFETIME};
+use crate::rsa::{self, PrivateKey, PublicKey};
+use crate::session::{SendCipher, Session, Verdict};
+use crate::transfer::{self, FileTransfer, Incoming, Outgoing, Progress, Transfers, FILE_QUEUE};
+use crate::ui::{self, ClientUi, InputSource, ServerUi, StatusUpdate, Theme, Tone, UiMode, Verbosity, PROMPT};
+
+/// Porta padrão do servidor.
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// Arquivo padrão do par de chaves do servidor, no diretório atual.
+pub const DEFAULT_SERVER_KEY_FILE: &str = "chat_rsa_servidor.json";
+
+/// Intervalo entre os pings enviados para verificar se o outro lado continua ativo.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Quanto tempo o servidor espera as conexões enviarem o `Bye` ao desligar.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// Espera antes de aceitar de novo depois de uma falha no `accept`.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// Quanto tempo o cliente espera o servidor aceitar a conexão TCP.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Primeira espera antes de reconectar depois de uma queda; dobra a cada falha.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Espera máxima entre duas tentativas de reconexão.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Tentativas seguidas de reconexão antes de o cliente desistir.
+const MAX_RECONNECT_ATTEMPTS: u32 = 8;
+
+/// Opções do servidor, vindas da linha de comando e do arquivo de configuração.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Endereço e porta em que o servidor escuta.
+    pub bind: SocketAddr,
+    pub relay: bool,
+    pub mailbox: MailboxConfig,
+    /// Par de chaves RSA do servidor, lido desse arquivo (ou criado nele), para
+    /// que a impressão digital guardada pelos clientes continue valendo.
+    pub key_file: PathBuf,
+    /// Tamanho de n, em bits, da chave criada quando `key_file` não existe (`None`: o tamanho padrão).
+    pub key_bits: Option<u32>,
+    /// Algoritmos aceitos no handshake, com as cifras na ordem de preferência.
+    pub offer: Offer,
+    pub ui: UiMode,
+    pub verbosity: Verbosity,
+    pub theme: Theme,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT)),
+            relay: false,
+            mailbox: MailboxConfig::default(),
+            key_file: PathBuf::from(DEFAULT_SERVER_KEY_FILE),
+            key_bits: None,
+            offer: Offer::supported(),
+            ui: UiMode::Plain,
+            verbosity: Verbosity::Normal,
+            theme: Theme::default(),
+        }
+    }
+}
 
 /// Inicia o servidor TCP, escutando por conexões de clientes.
 ///
-/// Ouve em todas as interfaces de rede (0.0.0.0) na porta especificada.
+/// Ouve no endereço `config.bind` (por padrão, todas as interfaces na porta 8080).
 /// Para cada conexão aceita, ele cria uma nova tarefa Tokio para lidar com a comunicação.
-pub async fn start_server(port: u16) {
-    // Vincula o TcpListener à porta especificada em todas as interfaces de rede.
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await.unwrap();
-    println!("Servidor escutando na porta: {}", port);
+/// Todas as mensagens trocadas passam pelo `codec`, que define o tamanho máximo de frame.
+/// As conexões compartilham um `Hub`, que repassa as mensagens entre os participantes de cada sala.
+///
+/// Com `relay`, o servidor funciona como relay cego: não decifra mensagens de chat
+/// dos clientes, só repassa as cópias cifradas de ponta a ponta que eles trocam
+/// entre si, vendo apenas os metadados de roteamento (remetente, destinatário e sala).
+///
+/// O teclado do servidor é lido só pelo console do operador (ver `console::run`),
+/// que endereça cada mensagem à sessão certa pelo hub. Com `UiMode::Tui`, o
+/// console e o log ficam no painel (ver `dashboard`), junto da lista de clientes.
+///
+/// Só falhas ao começar (a chave do servidor ou o endereço de escuta) encerram o
+/// servidor com erro; a falha de uma conexão é registrada e afeta só aquele cliente.
+pub async fn start_server(config: ServerConfig, codec: FrameCodec) -> Result<(), ChatError> {
+    let hub = Arc::new(Hub::new(config.mailbox));
+    let (ui, input, handle) = ui::start_server(config.ui, config.verbosity, config.theme, hub.clone());
+    let result = serve(config, codec, hub, ui, input).await;
+    handle.close().await;
+    result
+}
+
+async fn serve(config: ServerConfig, codec: FrameCodec, hub: Arc<Hub>, ui: ServerUi, input: InputSource) -> Result<(), ChatError> {
+    let relay = config.relay;
+    // O servidor mantém a mesma chave (e a mesma impressão digital) entre execuções.
+    let path = &config.key_file;
+    let server_keys = rsa::load_or_create_keypair(path, config.key_bits)
+        .map_err(|e| ChatError::io(format!("não foi possível usar a chave {}", path.display()), e))?;
+    ui.detail(format!("Chave do servidor: {} ({})", server_keys.0.fingerprint(), path.display()));
+    // Vincula o TcpListener ao endereço escolhido.
+    let listener = TcpListener::bind(config.bind).await
+        .map_err(|e| ChatError::io(format!("não foi possível escutar em {}", config.bind), e))?;
+    ui.detail(format!("Servidor escutando em: {}", config.bind));
+    if relay {
+        ui.log(Tone::Notice, "Modo relay cego: o servidor só repassa mensagens cifradas de ponta a ponta.");
+    }
+    // Tickets de retomada emitidos para as sessões desta execução.
+    let tickets = Arc::new(TicketStore::default());
+    let shutdown = Arc::new(Notify::new());
+    tokio::spawn(console::run(hub.clone(), shutdown.clone(), ui.clone(), input));
+    let mut connections = JoinSet::new();
 
-    // Loop infinito para aceitar novas conexões.
+    // Aceita novas conexões até o operador desligar o servidor.
     loop {
         // Aceita uma nova conexão de entrada. Isso bloqueará até que um cliente se conecte.
-        let (socket, addr) = listener.accept().await.unwrap();
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.notified() => break,
+        };
+        // Uma falha ao aceitar (limite de arquivos abertos, conexão desfeita na fila)
+        // não derruba o servidor: registra e tenta de novo logo depois.
+        let (socket, addr) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                ui.log(Tone::Warning, format!("Falha ao aceitar conexão: {}", e));
+                tokio::time::sleep(ACCEPT_RETRY).await;
+                continue;
+            }
+        };
         // Cria uma nova tarefa assíncrona para lidar com a conexão do cliente.
         // Isso permite que o servidor lide com múltiplos clientes simultaneamente.
-        tokio::spawn(async move {
-            let mut mutable_socket = socket; // Cria uma cópia mutável do socket
-            // Gera o par de chaves RSA para o servidor. Essas chaves serão usadas para criptografia/descriptografia
-            // e para o processo de handshake.
-            let (local_server_pub, local_server_priv) = rsa::generate_keypair(); // Chaves do servidor
-            // Realiza um handshake com o cliente para trocar chaves públicas.
-            let client_pub_key = perform_handshake(&mut mutable_socket, &local_server_pub).await;
-            // Lida com a comunicação contínua com o cliente após o handshake.
-            handle_connection(mutable_socket, addr, local_server_priv, client_pub_key).await;
+        let hub = hub.clone();
+        let ui = ui.clone();
+        let server_keys = server_keys.clone();
+        let offer = config.offer.clone();
+        let tickets = tickets.clone();
+        connections.spawn(async move {
+            // Os bytes da conexão são contados desde o handshake, para o painel e o /list.
+            let traffic = Arc::new(Traffic::default());
+            let mut mutable_socket = Metered::new(socket, traffic.clone());
+            // Par de chaves RSA do servidor, usado no handshake para a troca de chaves e a assinatura.
+            let (local_server_pub, local_server_priv) = server_keys; // Chaves do servidor
+            // Realiza o handshake com o cliente: negocia a suíte e deriva o segredo da sessão,
+            // ou retoma uma sessão anterior se o cliente apresentar um ticket válido.
+            let session = match handshake::server_handshake(&mut mutable_socket, &codec, &offer, &tickets, &local_server_pub, &local_server_priv).await {
+                Ok(session) => session,
+                Err(e) => {
+                    ui.log(error_tone(&e), format!("[CLIENTE {}] Handshake falhou: {}", addr, e));
+                    return;
+                }
+            };
+            if session.resumed {
+                ui.detail(format!("[CLIENTE {}] Sessão retomada com ticket. Suíte: {}", addr, session.suite));
+            } else {
+                ui.detail(format!("[CLIENTE {}] Handshake OK. Suíte: {}", addr, session.suite));
+            }
+            // O cliente escolhe um apelido, vinculado à chave pública que ele provou ter no handshake.
+            let (client_id, inbox) = match register_client(&mut mutable_socket, addr, &codec, &hub, &tickets, &session, traffic, relay, &ui).await {
+                Ok(Some(registered)) => registered,
+                Ok(None) => {
+                    ui.log(Tone::Warning, format!("[CLIENTE {}] Desistiu antes de se registrar", addr));
+                    return;
+                }
+                Err(e) => {
+                    ui.log(error_tone(&e), format!("[CLIENTE {}] Desconectou antes de se registrar: {}", addr, e));
+                    return;
+                }
+            };
+            // Lida com a comunicação contínua com o cliente após o registro.
+            if let Err(e) = handle_connection(mutable_socket, client_id, inbox, session, codec, hub.clone(), relay, ui.clone()).await {
+                let nickname = hub.name_of(client_id).unwrap_or_default();
+                ui.client(client_id, error_tone(&e), format!("[CLIENTE {}] Conexão encerrada: {}", nickname, e));
+            }
+            // Tira o cliente da sala; isso também encerra a tarefa de entregas.
+            hub.leave(client_id);
         });
     }
+
+    // Dá às conexões um tempo para entregar o `Bye` antes de sair.
+    let _ = tokio::time::timeout(SHUTDOWN_GRACE, async {
+        while connections.join_next().await.is_some() {}
+    }).await;
+    Ok(())
+}
+
+// Quedas e erros de protocolo são avisos; falhas criptográficas e de autenticação, alertas
+fn error_tone(error: &ChatError) -> Tone {
+    match error {
+        ChatError::Crypto(_) | ChatError::Auth(_) => Tone::Invalid,
+        ChatError::Io(_) | ChatError::Protocol(_) => Tone::Warning,
+    }
+}
+
+/// Etapa de registro, logo após o handshake: espera um `Register` com um apelido
+/// válido e livre e o vincula à chave pública do cliente no hub.
+///
+/// Apelidos recusados são respondidos com um `Error` e o cliente pode tentar de
+/// novo. Aceito o apelido, o cliente recebe também um ticket para retomar a
+/// sessão se a conexão cair. Devolve o identificador do cliente no hub e as filas das entregas para
+/// ele, ou `None` se o cliente desistir com um `Bye`.
+#[allow(clippy::too_many_arguments)]
+async fn register_client(
+    socket: &mut Metered<TcpStream>,
+    addr: SocketAddr,
+    codec: &FrameCodec,
+    hub: &Hub,
+    tickets: &TicketStore,
+    session: &Session,
+    traffic: Arc<Traffic>,
+    relay: bool,
+    ui: &ServerUi,
+) -> Result<Option<(ClientId, Inbox)>, ChatError> {
+    loop {
+        let envelope = match protocol::read_envelope(codec, socket).await {
+            Ok(envelope) => envelope,
+            Err(ReadError::Rejected { id, code }) => {
+                let refusal = Packet::Error { id, code, message: code.to_string() };
+                codec.write_frame(socket, &Envelope::new(0, refusal)).await?;
+                continue;
+            }
+            Err(ReadError::Io(e)) => return Err(e.into()),
+        };
+
+        let code = match envelope.packet {
+            Packet::Register { nickname } if protocol::is_valid_nickname(&nickname) => {
+                match hub.register(nickname.clone(), addr, session.peer_key.clone(), traffic.clone()) {
+                    Ok((client_id, inbox)) => {
+                        ui.client(client_id, Tone::Notice, format!("[CLIENTE {}] Registrado como {} (chave {})", addr, nickname, session.peer_key.fingerprint()));
+                        let ticket = Packet::Ticket { ticket: tickets.issue(session.resumable()), lifetime: TICKET_LIFETIME.as_secs() };
+                        let sent = async {
+                            codec.write_frame(socket, &Envelope::new(0, Packet::Registered { nickname, relay })).await?;
+                            codec.write_frame(socket, &Envelope::new(0, ticket)).await
+                        };
+                        if let Err(e) = sent.await {
+                            // Sem a confirmação, o apelido não pode ficar preso a uma conexão que caiu.
+                            hub.leave(client_id);
+                            return Err(e.into());
+                        }
+                        // Mensagens privadas que chegaram enquanto a chave estava desconectada.
+                        let delivered = hub.deliver_mail(client_id);
+                        if delivered > 0 {
+                            ui.client(client_id, Tone::Notice, format!("[CLIENTE {}] {} mensagens guardadas entregues", addr, delivered));
+                        }
+                        return Ok(Some((client_id, inbox)));
+                    }
+                    Err(code) => code,
+                }
+            }
+            Packet::Register { .. } => ErrorCode::InvalidNickname,
+            Packet::Bye { .. } => return Ok(None),
+            _ => ErrorCode::Unexpected,
+        };
+        let refusal = Packet::Error { id: envelope.id, code, message: code.to_string() };
+        codec.write_frame(socket, &Envelope::new(0, refusal)).await?;
+    }
 }
 
 /// Lida com a comunicação com um único cliente conectado.
 ///
 /// Esta função gerencia tanto o envio quanto o recebimento de mensagens, incluindo
-/// a descriptografia usando a chave privada do servidor e a verificação da integridade da mensagem
-/// usando hash SHA-256.
+/// a decifração com a cifra negociada no handshake e a verificação da integridade
+/// de cada mensagem. Mensagens válidas são repassadas pelo `hub` aos outros
+/// participantes da sala, e o que o hub entrega a este cliente é recifrado com a
+/// sessão dele antes de seguir.
+///
+/// Termina sem erro quando o cliente sai com um `Bye` ou quando o servidor o
+/// desconecta; uma queda ou falha de E/S é devolvida para quem registra a conexão.
+#[allow(clippy::too_many_arguments)]
 async fn handle_connection(
-    socket: TcpStream,
-    addr: SocketAddr,
-    my_server_priv_key: rsa::PrivateKey,
-    peer_client_pub_key: rsa::PublicKey,
-) {
+    socket: Metered<TcpStream>,
+    client_id: ClientId,
+    inbox: Inbox,
+    session: Session,
+    codec: FrameCodec,
+    hub: Arc<Hub>,
+    relay: bool,
+    ui: ServerUi,
+) -> Result<(), ChatError> {
     // Divide o fluxo TCP em uma metade legível e uma metade gravável.
-    let (mut reader_half, mut writer_half) = tokio::io::split(socket);
-    // Clona a chave privada do servidor para ser usada na tarefa de leitura.
-    let server_priv_key_clone_for_read = my_server_priv_key.clone();
+    let (mut reader_half, writer_half) = tokio::io::split(socket);
+    // A escrita fica com uma tarefa dedicada; as demais enviam pacotes pelo canal.
+    let (packet_tx, file_tx, mut writer_task) = spawn_writer(writer_half, codec);
+    spawn_keepalive(packet_tx.clone());
+    let reader_packet_tx = packet_tx.clone();
+    let Inbox { deliveries: mut delivery_rx, files: mut files_rx } = inbox;
+    // O apelido pode mudar durante a conversa, por isso é sempre consultado no hub.
+    let reader_hub = hub.clone();
+    let nick_hub = hub.clone();
+    let reader_nick = move || nick_hub.name_of(client_id).unwrap_or_default();
 
-    // Cria uma nova tarefa assíncrona para lidar com a leitura de mensagens do cliente.
+    ui.inspect(client_id, inspector::handshake_lines(&reader_nick(), &session.suite, &session.my_pub_key, &session.peer_key));
+    // Separa a sessão: a tarefa de leitura decifra; a de entregas cifra.
+    let (mut send_cipher, mut recv_cipher) = session.split();
+
+    // Tarefa que cifra e envia ao cliente o que o hub entrega para ele: mensagens
+    // da sala, do operador e avisos. Por ser a única que cifra, a ordem de envio
+    // segue a dos números de sequência.
+    let delivery_ui = ui.clone();
+    let delivery_nick = reader_nick.clone();
+    let delivery_hub = hub.clone();
     tokio::spawn(async move {
-        let mut client_msg_payload_buf = [0; 4096]; // Buffer para o payload da mensagem criptografada
-        let mut received_hash_buf = [0u8; 32]; // Buffer para o hash SHA-256 recebido (32 bytes)
+        // Cifra uma mensagem para o cliente e manda os passos para o inspetor.
+        let mut seal = |text: &str, from: Option<String>, tamper: bool| {
+            let (packet, inspection) = seal_chat(&mut send_cipher, &delivery_nick(), text, from, tamper);
+            delivery_ui.inspect(client_id, inspection.lines());
+            packet
+        };
+        while let Some(delivery) = delivery_rx.recv().await {
+            let packet = match delivery {
+                Delivery::Chat { from, text } => seal(&text, Some(from), false),
+                Delivery::Presence { room, who, joined } => Packet::Presence { room, who, joined },
+                Delivery::Joined { room, members } => Packet::Joined { room, members },
+                Delivery::Renamed { old, new } => Packet::Renamed { old, new },
+                Delivery::Direct { from, room, sender_key, message } => Packet::Forwarded { from, room, sender_key, message },
+                Delivery::Group { from, room, sender_key, message } => Packet::GroupForwarded { from, room, sender_key, message },
+                Delivery::Stored(stored) => Packet::Stored { from: stored.from, sender_key: stored.sender_key, sent_at: stored.sent_at, message: stored.message },
+                Delivery::Operator { text, tamper } => seal(&text, None, tamper),
+                Delivery::Kicked { reason } => Packet::Bye { reason },
+            };
+            if packet_tx.send(packet).is_err() {
+                return;
+            }
+        }
+        // A fila fecha quando o cliente sai do hub, ou antes, se ela encheu: o cliente
+        // não acompanhou a conversa e é desconectado.
+        if let Some(nickname) = delivery_hub.name_of(client_id) {
+            delivery_ui.client(client_id, Tone::Warning, format!("[CLIENTE {}] Fila de entregas cheia; desconectando", nickname));
+            let _ = packet_tx.send(Packet::Bye { reason: "o servidor desconectou você: mensagens acumuladas sem leitura".to_string() });
+        }
+    });
 
+    // Tarefa que repassa as etapas de arquivos de outros clientes. A fila da escrita
+    // é limitada: enquanto ela está cheia, a fila deste cliente no hub também enche,
+    // e quem envia espera em vez de o arquivo se acumular no servidor.
+    tokio::spawn(async move {
+        while let Some(FileDelivery { from, sender_key, transfer }) = files_rx.recv().await {
+            if file_tx.send(Packet::FileForwarded { from, sender_key, transfer }).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Cria uma nova tarefa assíncrona para lidar com a leitura de mensagens do cliente.
+    let mut reader_task = tokio::spawn(async move {
         loop {
-            // Primeiro, lê o hash SHA-256 de 32 bytes.
-            reader_half.read_exact(&mut received_hash_buf).await.unwrap();
-            // Lê o payload da mensagem criptografada. `read` retorna o número de bytes lidos.
-            let bytes_read = reader_half.read(&mut client_msg_payload_buf).await.unwrap();
-
-            // Desserializa os bytes recebidos em um vetor de u64, que representa a
-            // mensagem criptografada RSA.
-            let encrypted_msg: Vec<u64> =
-                bincode_deserialize(&client_msg_payload_buf[..bytes_read]).unwrap();
-
-            // NOVO: Imprime a mensagem criptografada recebida no servidor
-            println!("\n[Servidor - Recebido] Mensagem criptografada (Vec<u64>): {:?}", encrypted_msg);
-
-            // Descriptografa a mensagem usando a chave privada do servidor.
-            let decrypted_text = rsa::decrypt_string(
-                &encrypted_msg,
-                server_priv_key_clone_for_read.d,
-                server_priv_key_clone_for_read.n,
-            );
-
-            // Calcula o hash SHA-256 da mensagem descriptografada.
-            let calculated_hash = sha256(decrypted_text.as_bytes());
-
-            // Compara o hash calculado com o hash recebido para verificar a integridade da mensagem.
-            if calculated_hash == received_hash_buf {
-                println!("[CLIENTE {} Assinatura VÁLIDA]: {}", addr, decrypted_text);
-            } else {
-                println!("[CLIENTE {} Assinatura INVÁLIDA!]: {}", addr, decrypted_text);
+            // Lê o próximo envelope. Pacotes recusados são respondidos com um erro
+            // e a conexão continua; falhas de E/S encerram a leitura.
+            let envelope = match protocol::read_envelope(&codec, &mut reader_half).await {
+                Ok(envelope) => envelope,
+                Err(ReadError::Rejected { id, code }) => {
+                    ui.client(client_id, Tone::Warning, format!("[CLIENTE {}] Pacote #{} rejeitado: {}", reader_nick(), id, code));
+                    let _ = reader_packet_tx.send(Packet::Error { id, code, message: code.to_string() });
+                    continue;
+                }
+                Err(ReadError::Io(e)) => return Err(ChatError::from(e)),
+            };
+
+            let (seq, from, received_hash, encrypted_msg) = match envelope.packet {
+                // O relay cego não decifra mensagens de chat: elas devem ir de ponta a ponta.
+                Packet::Chat { .. } if relay => {
+                    let code = ErrorCode::RelayOnly;
+                    let _ = reader_packet_tx.send(Packet::Error { id: envelope.id, code, message: code.to_string() });
+                    continue;
+                }
+                Packet::Chat { seq, from, hash, body } => (seq, from, hash, body),
+                Packet::Ping { nonce } => {
+                    let _ = reader_packet_tx.send(Packet::Pong { nonce });
+                    continue;
+                }
+                Packet::Pong { .. } | Packet::Ack { .. } => continue,
+                Packet::Error { id, code, message } => {
+                    ui.client(client_id, Tone::Warning, format!("[CLIENTE {}] Recusou o pacote #{} ({}): {}", reader_nick(), id, code, message));
+                    continue;
+                }
+                Packet::Bye { reason } => {
+                    ui.client(client_id, Tone::Warning, format!("[CLIENTE {}] Saiu: {}", reader_nick(), reason));
+                    return Ok(());
+                }
+                Packet::Join { room } => {
+                    if protocol::is_valid_room_name(&room) {
+                        ui.client(client_id, Tone::Notice, format!("[CLIENTE {}] Entrou na sala {}", reader_nick(), room));
+                        reader_hub.join(client_id, &room);
+                    } else {
+                        let code = ErrorCode::Unexpected;
+                        let _ = reader_packet_tx.send(Packet::Error { id: envelope.id, code, message: format!("nome de sala inválido: {:?}", room) });
+                    }
+                    continue;
+                }
+                Packet::KeyRequest { nickname } => {
+                    let public_key = reader_hub.public_key_of(&nickname);
+                    let _ = reader_packet_tx.send(Packet::KeyResponse { nickname, public_key });
+                    continue;
+                }
+                Packet::Direct { to, room, message } => {
+                    // O conteúdo é cifrado para o destinatário: o servidor só o repassa.
+                    let context = room.as_ref().map_or("Mensagem privada".to_string(), |room| format!("Chave de remetente da sala {}", room));
+                    ui.client(client_id, Tone::Info, format!("[CLIENTE {}] {} para {} ({} bytes cifrados, ilegíveis para o servidor)", reader_nick(), context, to, message.ciphertext_len()));
+                    match reader_hub.direct(client_id, &to, room, message) {
+                        Ok(Routed::Delivered) => {}
+                        Ok(Routed::Stored) => {
+                            ui.client(client_id, Tone::Notice, format!("[CLIENTE {}] Guardada para {} (desconectado)", reader_nick(), to));
+                            let _ = reader_packet_tx.send(Packet::Queued { to });
+                        }
+                        Err(code) => {
+                            let _ = reader_packet_tx.send(Packet::Error { id: envelope.id, code, message: format!("{}: {}", code, to) });
+                        }
+                    }
+                    continue;
+                }
+                Packet::File { to, transfer } => {
+                    // Oferta e pedaços vão cifrados para o destinatário: o servidor só os repassa.
+                    match &transfer {
+                        FileTransfer::Offer { offer, .. } => ui.client(client_id, Tone::Info, format!("[CLIENTE {}] Oferta de arquivo para {} ({} bytes cifrados, ilegíveis para o servidor)", reader_nick(), to, offer.ciphertext_len())),
+                        FileTransfer::Answer { accepted, .. } => ui.client(client_id, Tone::Notice, format!("[CLIENTE {}] {} o arquivo de {}", reader_nick(), if *accepted { "Aceitou" } else { "Recusou" }, to)),
+                        FileTransfer::Chunk { .. } => {}
+                    }
+                    match reader_hub.file(client_id, &to, transfer) {
+                        // Espera vaga na fila do destinatário: enquanto isso, este cliente não é
+                        // lido, e o envio dele para por falta de espaço no TCP.
+                        Ok((files, delivery)) => {
+                            let _ = files.send(delivery).await;
+                        }
+                        Err(code) => {
+                            let _ = reader_packet_tx.send(Packet::Error { id: envelope.id, code, message: format!("{}: {}", code, to) });
+                        }
+                    }
+                    continue;
+                }
+                Packet::GroupChat { room, message } => {
+                    // Cifrada uma vez só com a chave de remetente: o servidor só a repassa à sala.
+                    ui.client(client_id, Tone::Info, format!("[CLIENTE {}] Mensagem de grupo na sala {} ({} bytes cifrados, ilegíveis para o servidor)", reader_nick(), room, message.ciphertext_len()));
+                    if let Err(code) = reader_hub.group(client_id, &room, message) {
+                        let _ = reader_packet_tx.send(Packet::Error { id: envelope.id, code, message: format!("você não está na sala {}", room) });
+                    }
+                    continue;
+                }
+                Packet::Who => {
+                    if let Some((room, members)) = reader_hub.room_of(client_id) {
+                        let _ = reader_packet_tx.send(Packet::Joined { room, members });
+                    }
+                    continue;
+                }
+                Packet::RoomKeysRequest => {
+                    if let Some((room, keys)) = reader_hub.room_keys(client_id) {
+                        let _ = reader_packet_tx.send(Packet::RoomKeys { room, keys });
+                    }
+                    continue;
+                }
+                Packet::Register { nickname } => {
+                    // Troca de apelido durante a conversa (`/nick`).
+                    let result = if protocol::is_valid_nickname(&nickname) {
+                        reader_hub.rename(client_id, &nickname)
+                    } else {
+                        Err(ErrorCode::InvalidNickname)
+                    };
+                    let reply = match result {
+                        Ok(old) => {
+                            let fingerprint = reader_hub.fingerprint_of(client_id).unwrap_or_default();
+                            ui.client(client_id, Tone::Notice, format!("[CLIENTE {}] Agora se chama {} (chave {})", old, nickname, fingerprint));
+                            Packet::Registered { nickname, relay }
+                        }
+                        Err(code) => Packet::Error { id: envelope.id, code, message: code.to_string() },
+                    };
+                    let _ = reader_packet_tx.send(reply);
+                    continue;
+                }
+                Packet::ClientHello { .. }
+                | Packet::ServerHello { .. }
+                | Packet::KeyExchange { .. }
+                | Packet::Finished { .. }
+                | Packet::Joined { .. }
+                | Packet::Presence { .. }
+                | Packet::Registered { .. }
+                | Packet::Renamed { .. }
+                | Packet::KeyResponse { .. }
+                | Packet::Forwarded { .. }
+                | Packet::RoomKeys { .. }
+                | Packet::GroupForwarded { .. }
+                | Packet::Queued { .. }
+                | Packet::Stored { .. }
+                | Packet::FileForwarded { .. }
+                | Packet::Ticket { .. }
+                | Packet::Resume { .. }
+                | Packet::Resumed { .. } => {
+                    let code = ErrorCode::Unexpected;
+                    let _ = reader_packet_tx.send(Packet::Error { id: envelope.id, code, message: code.to_string() });
+                    continue;
+                }
+            };
+
+            // Confere a sequência, decifra a mensagem e verifica o hash/MAC recebido.
+            let associated = protocol::chat_associated_data(&from);
+            let (decrypted_text, verdict) = recv_cipher.open(seq, &associated, &received_hash, &encrypted_msg);
+            // Os passos criptográficos vão para o inspetor, fora do log da conversa.
+            let inspection = recv_cipher.inspect(&reader_nick(), seq, &received_hash, &encrypted_msg, &decrypted_text, verdict);
+            ui.inspect(client_id, inspection.lines());
+
+            // Informa o resultado da verificação. Repetições são descartadas sem confirmação.
+            // Só mensagens íntegras são repassadas para a sala.
+            match verdict {
+                Verdict::Valid => {
+                    ui.client(client_id, Tone::Valid, format!("[CLIENTE {} Assinatura VÁLIDA]: {}", reader_nick(), decrypted_text));
+                    reader_hub.broadcast(client_id, &decrypted_text);
+                }
+                Verdict::Invalid => ui.client(client_id, Tone::Invalid, format!("[CLIENTE {} Assinatura INVÁLIDA!]: {}", reader_nick(), decrypted_text)),
+                Verdict::Replayed => ui.client(client_id, Tone::Warning, format!("[CLIENTE {} AVISO] Mensagem #{} repetida descartada (possível ataque de replay)", reader_nick(), seq)),
+                Verdict::OutOfWindow => ui.client(client_id, Tone::Warning, format!("[CLIENTE {} AVISO] Mensagem #{} fora da janela descartada (atrasada ou reenviada)", reader_nick(), seq)),
+                Verdict::InvalidUtf8 => ui.client(client_id, Tone::Invalid, format!("[CLIENTE {} ERRO] Mensagem #{} não é UTF-8 válido: {}", reader_nick(), seq, decrypted_text)),
+            }
+            if matches!(verdict, Verdict::Valid | Verdict::Invalid | Verdict::InvalidUtf8) {
+                // Confirma o recebimento da mensagem.
+                let _ = reader_packet_tx.send(Packet::Ack { id: envelope.id });
             }
-            // Solicita uma resposta do servidor.
-            print!("Servidor, sua resposta para {}: ", addr);
-            let _ = std::io::stdout().flush(); // Libera o stdout para garantir que o prompt seja exibido imediatamente.
         }
     });
 
-    // Lida com o envio de mensagens do servidor para o cliente.
-    let mut server_stdin_reader = TokioBufReader::new(tokio::io::stdin()); // Cria um leitor bufferizado para stdin.
-    let mut server_response_line = String::new(); // Buffer para a entrada do servidor.
+    // A conexão acaba quando a leitura termina (o cliente saiu ou caiu) ou quando
+    // a escrita termina (o `Bye` de um /kick ou /shutdown foi enviado, ou a escrita falhou).
+    let finished = tokio::select! {
+        read = &mut reader_task => {
+            writer_task.abort();
+            read
+        }
+        written = &mut writer_task => {
+            reader_task.abort();
+            written
+        }
+    };
+    finished.map_err(io::Error::from)?
+}
 
-    // Prompt inicial para o servidor.
-    print!("Servidor, sua resposta para {}: ", addr);
-    let _ = std::io::stdout().flush();
+/// Cifra um texto e monta o pacote `Chat`, com o remetente original (se for um
+/// repasse) amarrado ao hash/MAC. Com `tamper`, altera o texto cifrado depois de
+/// calculado o valor de integridade, para simular adulteração no caminho.
+///
+/// Devolve também a descrição da mensagem para o inspetor, com o texto cifrado
+/// como ele vai para `peer` (já adulterado, se for o caso).
+fn seal_chat(cipher: &mut SendCipher, peer: &str, text: &str, from: Option<String>, tamper: bool) -> (Packet, Inspection) {
+    let sealed = cipher.seal(text, &protocol::chat_associated_data(&from));
+    let mut body = sealed.body;
+    if tamper {
+        body.tamper();
+    }
+    let inspection = cipher.inspect(peer, text, sealed.seq, &sealed.hash, &body);
+    (Packet::Chat { seq: sealed.seq, from, hash: sealed.hash, body }, inspection)
+}
 
-    loop {
-        server_response_line.clear(); // Limpa o buffer para a próxima entrada.
+/// Cria a tarefa responsável por escrever pacotes no fluxo.
+///
+/// Cada pacote recebe um ID sequencial e segue dentro de um `Envelope`. Devolve o
+/// canal pelo qual as outras tarefas enfileiram pacotes, a fila dos pedaços de
+/// arquivo, limitada a `FILE_QUEUE` (quem envia espera vaga nela), e o handle da
+/// tarefa, que termina depois de enviar um `Bye` ou quando todos os remetentes do
+/// canal são descartados, ou com o erro da escrita que falhou.
+fn spawn_writer<W>(mut writer: W, codec: FrameCodec) -> (mpsc::UnboundedSender<Packet>, mpsc::Sender<Packet>, JoinHandle<Result<(), ChatError>>)
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (packet_tx, mut packet_rx) = mpsc::unbounded_channel::<Packet>();
+    let (file_tx, mut file_rx) = mpsc::channel::<Packet>(FILE_QUEUE);
+    let handle = tokio::spawn(async move {
+        let mut next_id: u64 = 1;
+        loop {
+            // Mensagens e avisos passam na frente dos pedaços de arquivo.
+            let packet = tokio::select! {
+                biased;
+                packet = packet_rx.recv() => match packet {
+                    Some(packet) => packet,
+                    None => break,
+                },
+                Some(packet) = file_rx.recv() => packet,
+            };
+            let is_bye = matches!(packet, Packet::Bye { .. });
+            let envelope = Envelope::new(next_id, packet);
+            next_id += 1;
+            codec.write_frame(&mut writer, &envelope).await?;
+            if is_bye {
+                break;
+            }
+        }
+        Ok(())
+    });
+    (packet_tx, file_tx, handle)
+}
 
-        // Lê uma linha do stdin. Se 0 bytes forem lidos, significa que o stdin foi fechado.
-        if server_stdin_reader.read_line(&mut server_response_line).await.unwrap() == 0 {
-            break; // Sai do loop se o stdin for fechado.
+/// Envia um `Ping` periódico enquanto a conexão estiver aberta.
+fn spawn_keepalive(packet_tx: mpsc::UnboundedSender<Packet>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PING_INTERVAL);
+        interval.tick().await; // O primeiro tick é imediato.
+        loop {
+            interval.tick().await;
+            if packet_tx.send(Packet::Ping { nonce: rand::random() }).is_err() {
+                break;
+            }
         }
+    });
+}
 
-        let response_text_from_server = server_response_line.trim(); // Remove espaços em branco da entrada.
+/// Opções do cliente, vindas da linha de comando e do arquivo de configuração.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Endereço do servidor, como `host:porta`; o host pode ser um nome.
+    pub server: String,
+    pub identity: Option<PathBuf>,
+    pub history: Option<PathBuf>,
+    /// Tamanho de n, em bits, das chaves geradas (`None`: o tamanho padrão).
+    pub key_bits: Option<u32>,
+    /// Impressões digitais dos servidores já vistos (ver `known_hosts`).
+    pub known_hosts: Option<PathBuf>,
+    /// Apelido tentado no registro antes de perguntar ao usuário.
+    pub nickname: Option<String>,
+    /// Algoritmos aceitos no handshake, com as cifras na ordem de preferência.
+    pub offer: Offer,
+    pub ui: UiMode,
+    pub verbosity: Verbosity,
+    pub theme: Theme,
+}
 
-        // Calcula o hash SHA-256 da resposta do servidor.
-        let message_hash = sha256(response_text_from_server.as_bytes());
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            server: format!("127.0.0.1:{}", DEFAULT_PORT),
+            identity: None,
+            history: None,
+            key_bits: None,
+            known_hosts: None,
+            nickname: None,
+            offer: Offer::supported(),
+            ui: UiMode::Plain,
+            verbosity: Verbosity::Normal,
+            theme: Theme::default(),
+        }
+    }
+}
 
-        // Criptografa a resposta do servidor usando a chave pública do cliente.
-        let mut encrypted_response_to_client = rsa::encrypt_string(
-            response_text_from_server,
-            peer_client_pub_key.e,
-            peer_client_pub_key.n,
-        );
+/// Inicia o cliente TCP e conecta-se ao endereço do servidor especificado.
+///
+/// Após a conexão, ele realiza o handshake (negociação da suíte e troca de chaves) e
+/// então lida com o envio e recebimento de mensagens criptografadas e assinadas.
+///
+/// Com `config.identity`, o par de chaves é lido desse arquivo (ou criado nele), e o
+/// cliente mantém a mesma chave entre execuções; assim recebe as mensagens
+/// privadas guardadas pelo servidor enquanto estava desconectado.
+///
+/// Com `config.history`, as mensagens decifradas (recebidas e enviadas) também são
+/// salvas nesse arquivo, cifradas com uma chave derivada de uma senha pedida ao
+/// usuário, e podem ser consultadas com `/history` e `/search`.
+///
+/// Com `config.known_hosts`, a impressão digital do servidor é conferida com a
+/// guardada na primeira conexão, e a conexão é abandonada se ela mudar.
+///
+/// Se a conexão cair, o cliente reconecta com espera crescente e retoma a sessão
+/// com o ticket recebido do servidor, ou refaz o handshake se ele for recusado;
+/// uma saída do usuário ou um `Bye` do servidor encerram o cliente.
+///
+/// Com `UiMode::Tui`, a conversa acontece em uma interface de tela cheia (ver
+/// `tui`) em vez de linhas impressas no terminal.
+///
+/// Falhas ao conectar, no handshake ou na conferência da chave do servidor são
+/// devolvidas depois de a interface ser fechada, para que a mensagem fique visível.
+pub async fn start_client(config: ClientConfig, codec: FrameCodec) -> Result<(), ChatError> {
+    let (ui, mut input, handle) = ui::start(config.ui, config.verbosity, config.theme);
+    let result = run_client(&config, codec, &ui, &mut input).await;
+    if result.is_err() {
+        ui.status(StatusUpdate::Connection("desconectado".to_string()));
+    }
+    handle.close().await;
+    result
+}
 
-        // --- TESTE DE ALTERAÇÃO (Simulação de adulteração) ---
-        // Se o servidor digitar "testar", altera intencionalmente o primeiro bloco
-        // da mensagem criptografada para simular adulteração.
-        if response_text_from_server == "testar" {
-            if let Some(first) = encrypted_response_to_client.get_mut(0) {
-                *first = first.wrapping_add(1); // Incrementa o primeiro valor u64.
+async fn run_client(config: &ClientConfig, codec: FrameCodec, ui: &ClientUi, input: &mut InputSource) -> Result<(), ChatError> {
+    let server_addr = config.server.as_str();
+    let history_path = config.history.as_deref();
+    ui.status(StatusUpdate::Server { addr: server_addr.to_string(), fingerprint: String::new() });
+    // Carrega a identidade salva ou gera um par de chaves RSA novo para esta execução.
+    let (my_pub_key, my_priv_key) = match &config.identity {
+        Some(path) => rsa::load_or_create_keypair(path, config.key_bits)
+            .map_err(|e| ChatError::io(format!("não foi possível usar a identidade {}", path.display()), e))?,
+        None => rsa::new_keypair(config.key_bits),
+    };
+    let carried = Arc::new(Mutex::new(Carried { nickname: config.nickname.clone(), ..Carried::default() }));
+    let mut history: Option<Arc<Mutex<History>>> = None;
+    // Depois da primeira queda, falhas de rede ao conectar levam a outra tentativa.
+    let mut reconnecting = false;
+    let mut failures: u32 = 0;
+
+    loop {
+        if reconnecting {
+            // Espera 1, 2, 4... segundos entre as tentativas, até o limite.
+            let delay = RECONNECT_DELAY.saturating_mul(1 << failures.min(5)).min(MAX_RECONNECT_DELAY);
+            ui.status(StatusUpdate::Connection("reconectando".to_string()));
+            ui.warning(format!("Reconectando em {}s (tentativa {} de {})...", delay.as_secs(), failures + 1, MAX_RECONNECT_ATTEMPTS));
+            if !wait_to_reconnect(delay, input, ui).await {
+                return Ok(());
             }
         }
+        // O ticket fica guardado até uma conexão dar certo: só então é gasto.
+        let ticket = carried.lock().unwrap().ticket.clone().filter(|ticket| !ticket.is_expired());
+        let (mut stream, session) = match connect(config, &codec, ui, ticket, &my_pub_key, &my_priv_key).await {
+            Ok(connected) => {
+                carried.lock().unwrap().ticket = None;
+                connected
+            }
+            Err(e @ ChatError::Io(_)) if reconnecting => {
+                failures += 1;
+                if failures == MAX_RECONNECT_ATTEMPTS {
+                    ui.warning(format!("Desistindo de reconectar depois de {} tentativas.", MAX_RECONNECT_ATTEMPTS));
+                    return Err(e);
+                }
+                ui.warning(format!("Reconexão falhou: {}", e));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        ui.status(StatusUpdate::Connection("registro".to_string()));
 
-        // Serializa a mensagem criptografada em bytes usando bincode.
-        let serialized_response = bincode_serialize(&encrypted_response_to_client).unwrap();
+        // Abre o histórico local com a senha do usuário, só na primeira conexão.
+        if let (Some(path), false) = (history_path, reconnecting) {
+            ui.prompt_secret("Senha do histórico: ");
+            let Some(passphrase) = input.next_secret().await else { return Ok(()) };
+            let opened = History::open(path, &passphrase)
+                .map_err(|e| ChatError::Io(io::Error::other(format!("não foi possível abrir o histórico {}: {:#}", path.display(), e))))?;
+            history = Some(Arc::new(Mutex::new(opened)));
+        }
 
-        writer_half.write_all(&message_hash).await.unwrap();
-        writer_half.write_all(&serialized_response).await.unwrap();
-        writer_half.flush().await.unwrap(); // Garante que todos os dados em buffer sejam enviados.
+        // Registra um apelido antes de entrar na sala; ao reconectar, o mesmo de antes.
+        let preferred = carried.lock().unwrap().nickname.clone();
+        let Some((nickname, relay)) = register_nickname(&mut stream, &codec, preferred.as_deref(), input, ui).await? else {
+            ui.info("Registro de apelido cancelado.");
+            return Ok(());
+        };
+        carried.lock().unwrap().nickname = Some(nickname.clone());
+        if reconnecting {
+            let how = if session.resumed { "sessão retomada, sem nova troca de chaves" } else { "novo handshake" };
+            ui.notice(format!("*** Reconectado como {} ({})", nickname, how));
+            // Volta para a sala em que estava antes da queda; se a escrita falhar, a leitura percebe.
+            let room = carried.lock().unwrap().room.clone();
+            if !room.is_empty() && room != DEFAULT_ROOM {
+                let _ = codec.write_frame(&mut stream, &Envelope::new(0, Packet::Join { room })).await;
+            }
+        } else {
+            ui.info(format!("Registrado como {} (sua chave: {})", nickname, my_pub_key.fingerprint()));
+            if relay {
+                ui.info("Servidor em modo relay cego: suas mensagens para a sala são cifradas de ponta a ponta com chaves de remetente.");
+            }
+            ui.info("Digite /help para ver os comandos.");
+        }
+        ui.status(StatusUpdate::Identity { nickname: nickname.clone(), fingerprint: my_pub_key.fingerprint() });
+        ui.status(StatusUpdate::Connection("conectado".to_string()));
+        failures = 0;
 
-        // Solicita novamente a próxima resposta.
-        print!("Servidor, sua resposta para {}: ", addr);
-        let _ = std::io::stdout().flush();
+        match converse(stream, session, codec, ui, input, history.clone(), (&my_pub_key, &my_priv_key), relay, &carried).await {
+            Ending::Closed => return Ok(()),
+            Ending::Dropped => reconnecting = true,
+        }
     }
 }
 
-/// Realiza um handshake para trocar chaves públicas entre o servidor e um cliente.
-///
-/// O servidor envia sua chave pública e, em seguida, recebe a chave pública do cliente.
-async fn perform_handshake(stream: &mut TcpStream, my_pub_key: &PublicKey) -> PublicKey {
-    // Serializa a chave pública do servidor em bytes.
-    let pub_key_bytes = my_pub_key.to_bytes();
-    // Envia a chave pública do servidor para o cliente.
-    stream.write_all(&pub_key_bytes).await.unwrap();
-    stream.flush().await.unwrap(); // Garante que a chave seja enviada.
-
-    let mut buf = [0; 512]; // Buffer para receber a chave pública do cliente.
-    // Lê a chave pública do cliente do fluxo.
-    let bytes_read = stream.read(&mut buf).await.unwrap();
-
-    // Desserializa os bytes recebidos de volta em uma estrutura PublicKey.
-    let peer_pub_key = PublicKey::from_bytes(&buf[..bytes_read]);
-    peer_pub_key
+// Conecta ao servidor e estabelece a sessão: retoma a anterior com `ticket`, se o
+// servidor o aceitar, ou faz o handshake completo e confere a chave do servidor
+async fn connect(
+    config: &ClientConfig,
+    codec: &FrameCodec,
+    ui: &ClientUi,
+    ticket: Option<Ticket>,
+    my_pub_key: &PublicKey,
+    my_priv_key: &PrivateKey,
+) -> Result<(TcpStream, Session), ChatError> {
+    let server_addr = config.server.as_str();
+    // Conecta-se ao servidor (resolvendo o nome, se for o caso), sem esperar indefinidamente.
+    let connected = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(server_addr)).await
+        .unwrap_or_else(|_| Err(io::Error::from(io::ErrorKind::TimedOut)));
+    let mut stream = connected.map_err(|e| ChatError::io(format!("não foi possível conectar ao servidor {}", server_addr), e))?;
+    ui.detail(format!("Conectado ao servidor {}", server_addr));
+    ui.status(StatusUpdate::Connection("handshake".to_string()));
+
+    if let Some(ticket) = ticket {
+        match handshake::client_resume(&mut stream, codec, ticket).await? {
+            Some(session) => {
+                ui.detail(format!("Sessão retomada com o ticket do servidor. Suíte: {}", session.suite));
+                ui.inspect(inspector::handshake_lines("servidor", &session.suite, &session.my_pub_key, &session.peer_key));
+                return Ok((stream, session));
+            }
+            None => ui.detail("Servidor recusou o ticket de retomada; refazendo o handshake."),
+        }
+    }
+
+    // Realiza o handshake com o servidor para trocar chaves públicas.
+    let session = handshake::client_handshake(&mut stream, codec, &config.offer, my_pub_key, my_priv_key).await?;
+    ui.detail(format!("Handshake com servidor OK. Suíte: {}", session.suite));
+    ui.inspect(inspector::handshake_lines("servidor", &session.suite, &session.my_pub_key, &session.peer_key));
+    ui.status(StatusUpdate::Server { addr: server_addr.to_string(), fingerprint: session.peer_key.fingerprint() });
+    if let Some(path) = &config.known_hosts {
+        if let Err(e) = check_known_host(path, server_addr, &session.peer_key.fingerprint(), ui) {
+            let bye = Packet::Bye { reason: "chave do servidor não confere".to_string() };
+            let _ = codec.write_frame(&mut stream, &Envelope::new(0, bye)).await;
+            return Err(e);
+        }
+    }
+    Ok((stream, session))
 }
 
-/// Inicia o cliente TCP e conecta-se ao endereço do servidor especificado.
+// Espera antes de reconectar; o que for digitado nesse meio-tempo não é enviado.
+// Devolve `false` se o usuário fechar a entrada (Ctrl-C, Ctrl-D) para desistir.
+async fn wait_to_reconnect(delay: Duration, input: &mut InputSource, ui: &ClientUi) -> bool {
+    let deadline = tokio::time::Instant::now() + delay;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => return true,
+            line = input.next_line() => match line {
+                Some(_) => ui.info("Sem conexão com o servidor; a mensagem não foi enviada."),
+                None => return false,
+            },
+        }
+    }
+}
+
+// Como terminou a conversa em uma conexão
+enum Ending {
+    // O usuário saiu ou o servidor encerrou a conversa com um `Bye`.
+    Closed,
+    // A conexão caiu; o cliente tenta reconectar.
+    Dropped,
+}
+
+// O que o cliente leva de uma conexão para a próxima quando ela cai
+#[derive(Default)]
+struct Carried {
+    // Ticket para retomar a sessão, recebido depois do registro.
+    ticket: Option<Ticket>,
+    nickname: Option<String>,
+    // Sala atual, anotada pela tarefa de leitura (vazia até o primeiro `Joined`).
+    room: String,
+}
+
+/// Conversa em uma conexão já registrada: a tarefa de leitura mostra o que chega
+/// do servidor e o laço de escrita envia o que o usuário digita.
 ///
-/// Após a conexão, ele realiza um handshake para trocar chaves públicas e
-/// então lida com o envio e recebimento de mensagens criptografadas e assinadas.
-pub async fn start_client(server_addr: &str) {
-    // Analisa a string do endereço do servidor em um SocketAddr.
-    let server_socket_addr = server_addr
-        .parse::<SocketAddr>()
-        .expect("Endereço do servidor inválido");
-    // Conecta-se ao servidor. Isso bloqueará até que uma conexão seja estabelecida.
-    let mut stream = TcpStream::connect(server_socket_addr).await.unwrap();
-    println!("Conectado ao servidor {}", server_addr);
-
-    // Gera o par de chaves RSA para o cliente.
-    let (my_pub_key, my_priv_key) = rsa::generate_keypair();
-    // Realiza o handshake com o servidor para trocar chaves públicas.
-    let server_pub_key = perform_handshake(&mut stream, &my_pub_key).await;
-    println!("Handshake com servidor OK.\n");
+/// Termina com `Ending::Dropped` se a conexão cair, para que `run_client` tente
+/// reconectar, e com `Ending::Closed` se o usuário sair ou o servidor se despedir.
+#[allow(clippy::too_many_arguments)]
+async fn converse(
+    stream: TcpStream,
+    session: Session,
+    codec: FrameCodec,
+    ui: &ClientUi,
+    input: &mut InputSource,
+    history: Option<Arc<Mutex<History>>>,
+    (my_pub_key, my_priv_key): (&PublicKey, &PrivateKey),
+    relay: bool,
+    carried: &Arc<Mutex<Carried>>,
+) -> Ending {
+    let reader_history = history.clone();
 
     // Divide o fluxo TCP para leitura e escrita concorrentes.
-    let (mut reader_half, mut writer_half) = tokio::io::split(stream);
-    // Clona a chave privada do cliente para a tarefa de leitura.
-    let priv_key_for_read_task = my_priv_key.clone();
+    let (mut reader_half, writer_half) = tokio::io::split(stream);
+    // A escrita fica com uma tarefa dedicada; as demais enviam pacotes pelo canal.
+    let (packet_tx, file_tx, writer_task) = spawn_writer(writer_half, codec);
+    spawn_keepalive(packet_tx.clone());
+    let reader_packet_tx = packet_tx.clone();
+    // Separa a sessão: a tarefa de leitura decifra, o laço de escrita cifra.
+    let server_fingerprint = session.peer_key.fingerprint();
+    // Guardado antes de separar a sessão, para montar o ticket quando ele chegar.
+    let resumable = session.resumable();
+    let (mut send_cipher, mut recv_cipher) = session.split();
 
-    // Cria uma nova tarefa assíncrona para lidar com a leitura de mensagens do servidor.
-    tokio::spawn(async move {
-        let mut server_msg_payload_buf = [0; 4096]; // Buffer para o payload da mensagem criptografada.
-        let mut received_hash_buf = [0u8; 32]; // Buffer para o hash SHA-256 recebido.
+    // Mensagens privadas esperando a chave do destinatário chegar do diretório do servidor.
+    let pending_direct: Arc<Mutex<HashMap<String, Vec<PendingDirect>>>> = Arc::default();
+    let reader_pending_direct = pending_direct.clone();
+    // Arquivos oferecidos e recebidos.
+    let transfers: Arc<Mutex<Transfers>> = Arc::default();
+    let reader_transfers = transfers.clone();
+    // No modo relay, mensagens para a sala esperam as chaves dos participantes.
+    let pending_room: Arc<Mutex<Vec<String>>> = Arc::default();
+    let reader_pending_room = pending_room.clone();
+    let (reader_pub_key, reader_priv_key) = (my_pub_key.clone(), my_priv_key.clone());
+    // No modo relay, chaves de remetente da sala atual (só a tarefa de leitura as usa).
+    let mut group: Option<GroupSession> = None;
+    // A sala atual, o apelido e o ticket são anotados pela tarefa de leitura.
+    let reader_carried = carried.clone();
+    let reader_ui = ui.clone();
 
-        loop {
-            // Primeiro, lê o hash SHA-256 de 32 bytes.
-            reader_half.read_exact(&mut received_hash_buf).await.unwrap();
-            // Lê o payload da mensagem criptografada.
-            let bytes_read = reader_half.read(&mut server_msg_payload_buf).await.unwrap();
-
-            // Desserializa os bytes recebidos no formato de mensagem criptografada (Vec<u64>).
-            let encrypted_msg =
-                bincode_deserialize::<Vec<u64>>(&server_msg_payload_buf[..bytes_read]).unwrap();
-
-            // NOVO: Imprime a mensagem criptografada recebida no cliente
-            println!("\n[Cliente - Recebido] Mensagem criptografada (Vec<u64>): {:?}", encrypted_msg);
-
-            // Descriptografa a mensagem usando a chave privada do cliente.
-            let decrypted_text = rsa::decrypt_string(
-                &encrypted_msg,
-                priv_key_for_read_task.d,
-                priv_key_for_read_task.n,
-            );
-
-            // Calcula o hash SHA-256 da mensagem descriptografada.
-            let calculated_hash = sha256(decrypted_text.as_bytes());
-
-            // Verifica a integridade da mensagem comparando os hashes.
-            if calculated_hash == received_hash_buf {
-                println!("[SERVIDOR Assinatura VÁLIDA]: {}", decrypted_text);
-            } else {
-                println!("[SERVIDOR Assinatura INVÁLIDA!]: {}", decrypted_text);
+    // Cria uma nova tarefa assíncrona para lidar com a leitura de mensagens do servidor
+    // e dos outros participantes da sala, repassadas por ele. Ela termina quando o
+    // servidor se despede ou a conexão cai, dizendo qual dos dois aconteceu.
+    let mut reader_task = tokio::spawn(async move {
+        let ending = loop {
+            // Lê o próximo envelope. Pacotes recusados são respondidos com um erro
+            // e a conexão continua; falhas de E/S encerram a leitura.
+            let envelope = match protocol::read_envelope(&codec, &mut reader_half).await {
+                Ok(envelope) => envelope,
+                Err(ReadError::Rejected { id, code }) => {
+                    reader_ui.warning(format!("[SERVIDOR] Pacote #{} rejeitado: {}", id, code));
+                    let _ = reader_packet_tx.send(Packet::Error { id, code, message: code.to_string() });
+                    continue;
+                }
+                Err(ReadError::Io(e)) => {
+                    let error = ChatError::from(e);
+                    reader_ui.warning(format!("[SERVIDOR] Conexão encerrada: {}", error));
+                    // Frames inválidos não se resolvem reconectando; só quedas de rede.
+                    break if matches!(error, ChatError::Io(_)) { Ending::Dropped } else { Ending::Closed };
+                }
+            };
+
+            let (seq, from, received_hash, encrypted_msg) = match envelope.packet {
+                Packet::Chat { seq, from, hash, body } => (seq, from, hash, body),
+                Packet::Ping { nonce } => {
+                    let _ = reader_packet_tx.send(Packet::Pong { nonce });
+                    continue;
+                }
+                Packet::Pong { .. } | Packet::Ack { .. } => continue,
+                Packet::Error { id, code, message } => {
+                    reader_ui.warning(format!("[SERVIDOR] Recusou o pacote #{} ({}): {}", id, code, message));
+                    continue;
+                }
+                Packet::Bye { reason } => {
+                    reader_ui.warning(format!("[SERVIDOR] Encerrou a conversa: {}", reason));
+                    break Ending::Closed;
+                }
+                Packet::Joined { room, members } => {
+                    // Ao mudar de sala, começa uma cadeia nova: a antiga não vale para a nova sala.
+                    if relay && group.as_ref().is_none_or(|group| group.room() != room) {
+                        group = Some(GroupSession::new(&room));
+                    }
+                    reader_carried.lock().unwrap().room = room.clone();
+                    reader_ui.status(StatusUpdate::Room(room.clone()));
+                    reader_ui.notice(format!("*** Você está na sala {}. Participantes: {}", room, members.join(", ")));
+                    continue;
+                }
+                Packet::Presence { room, who, joined } => {
+                    let action = if joined { "entrou na" } else { "saiu da" };
+                    reader_ui.notice(format!("*** {} {} sala {}", who, action, room));
+                    // Quem saiu não deve ler as próximas mensagens: troca a cadeia, que será
+                    // entregue de novo só a quem ficou.
+                    if let Some(group) = group.as_mut().filter(|group| !joined && group.room() == room) {
+                        group.rotate();
+                        reader_ui.notice(format!("*** Chave de remetente da sala {} renovada", room));
+                    }
+                    continue;
+                }
+                Packet::Registered { nickname, .. } => {
+                    reader_carried.lock().unwrap().nickname = Some(nickname.clone());
+                    reader_ui.status(StatusUpdate::Identity { nickname: nickname.clone(), fingerprint: reader_pub_key.fingerprint() });
+                    reader_ui.notice(format!("*** Agora você é {}", nickname));
+                    continue;
+                }
+                Packet::Renamed { old, new } => {
+                    reader_ui.notice(format!("*** {} agora é {}", old, new));
+                    continue;
+                }
+                Packet::Ticket { ticket, lifetime } => {
+                    // Se a conexão cair, a próxima retoma esta sessão sem nova troca de chaves.
+                    match Ticket::new(ticket, lifetime, resumable.clone()) {
+                        Some(ticket) => reader_carried.lock().unwrap().ticket = Some(ticket),
+                        None => reader_ui.warning(format!("[SERVIDOR] Ticket de retomada descartado: validade de {}s fora da faixa aceita", lifetime)),
+                    }
+                    continue;
+                }
+                Packet::KeyResponse { nickname, public_key } => {
+                    // Cifra para o destinatário as mensagens privadas e ofertas de arquivo que esperavam esta chave.
+                    let pending = reader_pending_direct.lock().unwrap().remove(&nickname.to_lowercase()).unwrap_or_default();
+                    match public_key {
+                        Some(key) => {
+                            for item in pending {
+                                match item {
+                                    PendingDirect::Text(text) => match DirectMessage::seal(&text, &protocol::direct_associated_data(&None), &key, &reader_priv_key) {
+                                        Some(message) => {
+                                            let _ = reader_packet_tx.send(Packet::Direct { to: nickname.clone(), room: None, message });
+                                            remember(&reader_ui, &reader_history, mailbox::now(), format!("@{}", nickname), None, text);
+                                        }
+                                        None => reader_ui.invalid(format!("*** Chave de {} no diretório é inválida; mensagem privada não enviada", nickname)),
+                                    },
+                                    PendingDirect::File(outgoing) => match outgoing.offer(&key, &reader_priv_key) {
+                                        Some(transfer) => {
+                                            let _ = reader_packet_tx.send(Packet::File { to: nickname.clone(), transfer });
+                                            reader_ui.notice(format!("*** Arquivo {} ({} bytes) oferecido a {}; aguardando resposta", outgoing.name, outgoing.size(), nickname));
+                                            reader_transfers.lock().unwrap().offered(outgoing);
+                                        }
+                                        None => reader_ui.invalid(format!("*** Chave de {} no diretório é inválida; arquivo {} não oferecido", nickname, outgoing.name)),
+                                    },
+                                }
+                            }
+                        }
+                        None => {
+                            reader_ui.notice(format!("*** Ninguém usa o apelido {}; mensagem privada ou arquivo não enviado", nickname));
+                        }
+                    }
+                    continue;
+                }
+                Packet::RoomKeys { room, keys } => {
+                    // Entrega a chave de remetente a quem ainda não a tem e então cifra
+                    // cada mensagem pendente uma vez só, para a sala inteira.
+                    let texts = std::mem::take(&mut *reader_pending_room.lock().unwrap());
+                    let my_fingerprint = reader_pub_key.fingerprint();
+                    let others: Vec<_> = keys.iter().filter(|(_, key)| key.fingerprint() != my_fingerprint).collect();
+                    if others.is_empty() && !texts.is_empty() {
+                        reader_ui.notice(format!("*** Ninguém mais na sala {}; mensagem não enviada", room));
+                        continue;
+                    }
+                    let group = match &mut group {
+                        Some(group) if group.room() == room => group,
+                        // Mudou de sala enquanto esperava: as mensagens pendentes são descartadas.
+                        _ => continue,
+                    };
+                    let associated = protocol::direct_associated_data(&Some(room.clone()));
+                    for (nickname, key) in &others {
+                        if let Some(distribution) = group.distribution_for(&key.fingerprint()) {
+                            match DirectMessage::seal_bytes(&distribution, &associated, key, &reader_priv_key) {
+                                Some(message) => {
+                                    let _ = reader_packet_tx.send(Packet::Direct { to: nickname.clone(), room: Some(room.clone()), message });
+                                }
+                                None => reader_ui.invalid(format!("*** Chave de {} no diretório é inválida; chave de remetente não entregue", nickname)),
+                            }
+                        }
+                    }
+                    for text in texts {
+                        let message = group.encrypt(&text, &reader_priv_key);
+                        let _ = reader_packet_tx.send(Packet::GroupChat { room: room.clone(), message });
+                        remember(&reader_ui, &reader_history, mailbox::now(), format!("#{}", room), None, text);
+                    }
+                    continue;
+                }
+                Packet::Forwarded { from, room: Some(room), sender_key, message } => {
+                    // Chave de remetente de outro participante da sala.
+                    let associated = protocol::direct_associated_data(&Some(room.clone()));
+                    let accepted = match (message.open_bytes(&associated, &reader_pub_key, &reader_priv_key, &sender_key), &mut group) {
+                        (Some(distribution), Some(group)) if group.room() == room => group.accept_distribution(&sender_key.fingerprint(), &distribution),
+                        _ => false,
+                    };
+                    if !accepted {
+                        reader_ui.invalid(format!("[{} em {} ERRO] Chave de remetente inválida descartada", from, room));
+                    }
+                    continue;
+                }
+                Packet::GroupForwarded { from, room, sender_key, message } => {
+                    let (text, verdict) = match &mut group {
+                        Some(group) if group.room() == room => group.decrypt(&message, &sender_key),
+                        _ => ("<mensagem de outra sala>".to_string(), Verdict::Invalid),
+                    };
+                    let context = format!("{} em {}", from, room);
+                    match verdict {
+                        Verdict::Valid => {
+                            reader_ui.valid(format!("[{} Assinatura VÁLIDA, chave {}]: {}", context, sender_key.fingerprint(), text));
+                            remember(&reader_ui, &reader_history, mailbox::now(), format!("#{}", room), Some(from), text);
+                        }
+                        Verdict::Invalid => reader_ui.invalid(format!("[{} Assinatura INVÁLIDA!] Mensagem descartada {}", context, text)),
+                        Verdict::Replayed => reader_ui.warning(format!("[{} AVISO] Mensagem de grupo repetida descartada (possível ataque de replay)", context)),
+                        Verdict::OutOfWindow => reader_ui.warning(format!("[{} AVISO] Mensagem de grupo fora da janela descartada", context)),
+                        Verdict::InvalidUtf8 => reader_ui.invalid(format!("[{} ERRO] Mensagem não é UTF-8 válido: {}", context, text)),
+                    }
+                    continue;
+                }
+                Packet::Forwarded { from, room: None, sender_key, message } => {
+                    let associated = protocol::direct_associated_data(&None);
+                    let (text, verdict) = message.open(&associated, &reader_pub_key, &reader_priv_key, &sender_key);
+                    let fingerprint = sender_key.fingerprint();
+                    let context = format!("privado de {}", from);
+                    match verdict {
+                        Verdict::Valid => {
+                            reader_ui.valid(format!("[{} Assinatura VÁLIDA, chave {}]: {}", context, fingerprint, text));
+                            remember(&reader_ui, &reader_history, mailbox::now(), format!("@{}", from), Some(from), text);
+                        }
+                        Verdict::InvalidUtf8 => reader_ui.invalid(format!("[{} ERRO] Mensagem não é UTF-8 válido: {}", context, text)),
+                        _ => reader_ui.invalid(format!("[{} Assinatura INVÁLIDA!] Mensagem descartada", context)),
+                    }
+                    continue;
+                }
+                Packet::FileForwarded { from, sender_key, transfer } => {
+                    match transfer {
+                        FileTransfer::Offer { id, offer } => match Incoming::open(id, &from, &sender_key, &offer, &reader_pub_key, &reader_priv_key) {
+                            Some(incoming) => {
+                                let (name, size, digest) = (incoming.name.clone(), incoming.size, incoming.digest_hex());
+                                let resumed = incoming.received_len();
+                                let number = reader_transfers.lock().unwrap().add_incoming(incoming);
+                                reader_ui.notice(format!("*** {} quer enviar o arquivo {} ({} bytes, SHA-256 {})", from, name, size, digest));
+                                if resumed > 0 {
+                                    reader_ui.notice(format!("*** {} bytes já foram recebidos antes; a transferência continua de onde parou", resumed));
+                                }
+                                reader_ui.notice(format!("*** Use /accept {} para receber ou /decline {} para recusar", number, number));
+                            }
+                            None => reader_ui.invalid(format!("[{} Assinatura INVÁLIDA!] Oferta de arquivo descartada", from)),
+                        },
+                        FileTransfer::Answer { id, accepted, resume_from } => match reader_transfers.lock().unwrap().take_outgoing(id, &from) {
+                            Some(outgoing) if accepted && resume_from < outgoing.chunk_count() => {
+                                if resume_from > 0 {
+                                    reader_ui.notice(format!("*** {} aceitou o arquivo {}; retomando do pedaço {} de {}", from, outgoing.name, resume_from + 1, outgoing.chunk_count()));
+                                } else {
+                                    reader_ui.notice(format!("*** {} aceitou o arquivo {}; enviando", from, outgoing.name));
+                                }
+                                spawn_upload(outgoing, resume_from, file_tx.clone(), reader_ui.clone());
+                            }
+                            Some(outgoing) if accepted => reader_ui.invalid(format!("[{} ERRO] Pediu o arquivo {} a partir de um pedaço que não existe", from, outgoing.name)),
+                            Some(outgoing) => reader_ui.warning(format!("*** {} recusou o arquivo {}", from, outgoing.name)),
+                            None => continue,
+                        },
+                        FileTransfer::Chunk { id, index, data, proof } => {
+                            let mut transfers = reader_transfers.lock().unwrap();
+                            // Pedaços de transferências não aceitas (ou já canceladas) são ignorados.
+                            let Some((number, incoming)) = transfers.receiving_mut(id, &sender_key.fingerprint()) else { continue };
+                            let before = transfer::percent(incoming.received_len(), incoming.size as usize);
+                            match incoming.accept_chunk(index, &data, &proof) {
+                                Ok(Progress::Partial) => {
+                                    let now = transfer::percent(incoming.received_len(), incoming.size as usize);
+                                    if now / 10 == before / 10 {
+                                        continue;
+                                    }
+                                    reader_ui.notice(format!("*** Recebendo {} de {}: {}%", incoming.name, from, now));
+                                }
+                                Ok(Progress::Complete) => {
+                                    let mut incoming = transfers.remove_incoming(number).expect("transferência em andamento");
+                                    // Reler o arquivo inteiro para o SHA-256 não pode parar a leitura
+                                    // da conexão nem segurar as outras transferências.
+                                    let ui = reader_ui.clone();
+                                    tokio::task::spawn_blocking(move || match incoming.verify() {
+                                        Ok(()) => match incoming.save() {
+                                            Ok(path) => ui.notice(format!("*** Arquivo {} de {} recebido: SHA-256 {} confere; salvo em {}", incoming.name, from, incoming.digest_hex(), path.display())),
+                                            Err(e) => ui.notice(format!("*** Arquivo {} de {} recebido, mas não foi possível salvá-lo: {}", incoming.name, from, e)),
+                                        },
+                                        Err(reason) => ui.invalid(format!("[{} ERRO] Transferência de {} interrompida: {}", from, incoming.name, reason)),
+                                    });
+                                }
+                                Err(reason) => {
+                                    // Os pedaços conferidos continuam no arquivo parcial para uma nova tentativa.
+                                    let incoming = transfers.remove_incoming(number).expect("transferência em andamento");
+                                    reader_ui.invalid(format!("[{} ERRO] Transferência de {} interrompida: {}", from, incoming.name, reason));
+                                }
+                            }
+                        }
+                    }
+                    continue;
+                }
+                Packet::Queued { to } => {
+                    reader_ui.notice(format!("*** {} está desconectado; mensagem guardada no servidor até ele voltar", to));
+                    continue;
+                }
+                Packet::Stored { from, sender_key, sent_at, message } => {
+                    // Mensagem privada que chegou enquanto esta chave estava desconectada.
+                    let associated = protocol::direct_associated_data(&None);
+                    let (text, verdict) = message.open(&associated, &reader_pub_key, &reader_priv_key, &sender_key);
+                    let context = format!("privado de {}, enviada em {}", from, mailbox::format_timestamp(sent_at));
+                    match verdict {
+                        Verdict::Valid => {
+                            reader_ui.valid(format!("[{} Assinatura VÁLIDA, chave {}]: {}", context, sender_key.fingerprint(), text));
+                            remember(&reader_ui, &reader_history, sent_at, format!("@{}", from), Some(from), text);
+                        }
+                        Verdict::InvalidUtf8 => reader_ui.invalid(format!("[{} ERRO] Mensagem não é UTF-8 válido: {}", context, text)),
+                        _ => reader_ui.invalid(format!("[{} Assinatura INVÁLIDA!] Mensagem descartada", context)),
+                    }
+                    continue;
+                }
+                Packet::ClientHello { .. }
+                | Packet::ServerHello { .. }
+                | Packet::KeyExchange { .. }
+                | Packet::Finished { .. }
+                | Packet::Join { .. }
+                | Packet::Register { .. }
+                | Packet::Who
+                | Packet::KeyRequest { .. }
+                | Packet::Direct { .. }
+                | Packet::RoomKeysRequest
+                | Packet::GroupChat { .. }
+                | Packet::File { .. }
+                | Packet::Resume { .. }
+                | Packet::Resumed { .. } => {
+                    let code = ErrorCode::Unexpected;
+                    let _ = reader_packet_tx.send(Packet::Error { id: envelope.id, code, message: code.to_string() });
+                    continue;
+                }
+            };
+
+            // Confere a sequência, decifra a mensagem e verifica o hash/MAC recebido.
+            let associated = protocol::chat_associated_data(&from);
+            let (decrypted_text, verdict) = recv_cipher.open(seq, &associated, &received_hash, &encrypted_msg);
+            // Os passos criptográficos vão para o inspetor, fora da conversa.
+            let inspection = recv_cipher.inspect("servidor", seq, &received_hash, &encrypted_msg, &decrypted_text, verdict);
+            reader_ui.inspect(inspection.lines());
+
+            // Mensagens repassadas pelo servidor mostram o participante que as enviou.
+            let sender = from.unwrap_or_else(|| "SERVIDOR".to_string());
+
+            // Informa o resultado da verificação. Repetições são descartadas sem confirmação.
+            match verdict {
+                Verdict::Valid => {
+                    reader_ui.valid(format!("[{} Assinatura VÁLIDA]: {}", sender, decrypted_text));
+                    let room = format!("#{}", reader_carried.lock().unwrap().room);
+                    remember(&reader_ui, &reader_history, mailbox::now(), room, Some(sender), decrypted_text);
+                }
+                Verdict::Invalid => reader_ui.invalid(format!("[{} Assinatura INVÁLIDA!]: {}", sender, decrypted_text)),
+                Verdict::Replayed => reader_ui.warning(format!("[{} AVISO] Mensagem #{} repetida descartada (possível ataque de replay)", sender, seq)),
+                Verdict::OutOfWindow => reader_ui.warning(format!("[{} AVISO] Mensagem #{} fora da janela descartada (atrasada ou reenviada)", sender, seq)),
+                Verdict::InvalidUtf8 => reader_ui.invalid(format!("[{} ERRO] Mensagem #{} não é UTF-8 válido: {}", sender, seq, decrypted_text)),
             }
-            // Solicita uma resposta do cliente.
-            print!("Cliente, sua resposta para o servidor: ");
-            let _ = std::io::stdout().flush();
-        }
+            if matches!(verdict, Verdict::Valid | Verdict::Invalid | Verdict::InvalidUtf8) {
+                // Confirma o recebimento da mensagem.
+                let _ = reader_packet_tx.send(Packet::Ack { id: envelope.id });
+            }
+        };
+        reader_ui.status(StatusUpdate::Connection("desconectado".to_string()));
+        ending
     });
 
     // Lida com o envio de mensagens do cliente para o servidor.
-    let stdin = tokio::io::stdin();
-    let mut stdin_reader = TokioBufReader::new(stdin);
-    let mut input_line = String::new();
-
     // Prompt inicial para o cliente.
-    print!("Cliente, sua resposta para o servidor: ");
-    let _ = std::io::stdout().flush();
+    ui.prompt(PROMPT);
 
     loop {
-        input_line.clear(); // Limpa o buffer.
-        // Lê uma linha do stdin.
-        let stdin_bytes_read = stdin_reader.read_line(&mut input_line).await.unwrap();
-        if stdin_bytes_read == 0 {
-            break; // Sai se o stdin for fechado.
-        }
+        // Lê uma linha do teclado (ou da interface de tela cheia), até o servidor sair.
+        let next_line = tokio::select! {
+            line = input.next_line() => line,
+            ending = &mut reader_task => {
+                // Sem conexão, não há `Bye` a enviar; a escrita é abandonada.
+                writer_task.abort();
+                return ending.unwrap_or(Ending::Closed);
+            }
+        };
+        let Some(input_line) = next_line else {
+            // Avisa o servidor antes de sair se a entrada for fechada.
+            let _ = packet_tx.send(Packet::Bye { reason: "cliente encerrou a conversa".to_string() });
+            break;
+        };
 
         let trimmed_input = input_line.trim(); // Remove espaços em branco.
         if trimmed_input.is_empty() {
             // Não envia mensagens vazias.
-            print!("Cliente, sua resposta para o servidor (não pode ser vazia): ");
-            let _ = std::io::stdout().flush();
+            ui.prompt("Cliente, sua resposta para o servidor (não pode ser vazia): ");
             continue;
         }
 
-        // Calcula o hash SHA-256 da mensagem do cliente.
-        let message_hash = sha256(trimmed_input.as_bytes());
-
-        // Criptografa a mensagem do cliente usando a chave pública do servidor.
-        let encrypted_to_server = encrypt_string(
-            trimmed_input,
-            server_pub_key.e,
-            server_pub_key.n,
-        );
-        
-        // Serializa a mensagem criptografada.
-        let serialized_msg = bincode_serialize(&encrypted_to_server).unwrap();
+        // Linhas começando com "/" são comandos; o resto é mensagem para a sala.
+        let packet = match commands::parse(CLIENT_COMMANDS, trimmed_input) {
+            // No modo relay, a mensagem é cifrada para cada participante quando as chaves chegarem.
+            Input::Text(text) if relay => {
+                pending_room.lock().unwrap().push(text.to_string());
+                Some(Packet::RoomKeysRequest)
+            }
+            // Cifra a mensagem do cliente e calcula seu valor de integridade.
+            Input::Text(text) => {
+                let room = format!("#{}", carried.lock().unwrap().room);
+                remember(ui, &history, mailbox::now(), room, None, text.to_string());
+                let (packet, inspection) = seal_chat(&mut send_cipher, "servidor", text, None, false);
+                ui.inspect(inspection.lines());
+                Some(packet)
+            }
+            Input::Command(command, args) => match command.action {
+                ClientAction::Help => {
+                    ui.info(commands::help_text(CLIENT_COMMANDS));
+                    None
+                }
+                ClientAction::Quit => {
+                    let _ = packet_tx.send(Packet::Bye { reason: "cliente encerrou a conversa".to_string() });
+                    break;
+                }
+                ClientAction::Nick if protocol::is_valid_nickname(args) => Some(Packet::Register { nickname: args.to_string() }),
+                ClientAction::Nick => {
+                    ui.info(format!("{}. Uso: {}", ErrorCode::InvalidNickname, command.usage));
+                    None
+                }
+                ClientAction::Join if protocol::is_valid_room_name(args) => Some(Packet::Join { room: args.to_string() }),
+                ClientAction::Join => {
+                    ui.info(format!("Nome de sala inválido (1 a 32 caracteres, sem espaços). Uso: {}", command.usage));
+                    None
+                }
+                ClientAction::Who => Some(Packet::Who),
+                ClientAction::Msg => match args.split_once(char::is_whitespace) {
+                    // A chave do destinatário vem do diretório; a mensagem segue quando ela chegar.
+                    Some((to, text)) if protocol::is_valid_nickname(to) => {
+                        pending_direct.lock().unwrap().entry(to.to_lowercase()).or_default().push(PendingDirect::Text(text.trim().to_string()));
+                        Some(Packet::KeyRequest { nickname: to.to_string() })
+                    }
+                    _ => {
+                        ui.info(format!("Uso: {}", command.usage));
+                        None
+                    }
+                },
+                ClientAction::Send => match args.split_once(char::is_whitespace) {
+                    // O arquivo é conferido agora e oferecido quando a chave do destinatário chegar.
+                    Some((to, path)) if protocol::is_valid_nickname(to) => match Outgoing::read(to, path.trim()) {
+                        Ok(outgoing) => {
+                            pending_direct.lock().unwrap().entry(to.to_lowercase()).or_default().push(PendingDirect::File(outgoing));
+                            Some(Packet::KeyRequest { nickname: to.to_string() })
+                        }
+                        Err(e) => {
+                            ui.invalid(format!("Não foi possível ler {}: {}", path.trim(), e));
+                            None
+                        }
+                    },
+                    _ => {
+                        ui.info(format!("Uso: {}", command.usage));
+                        None
+                    }
+                },
+                ClientAction::Accept | ClientAction::Decline => {
+                    let accepted = command.action == ClientAction::Accept;
+                    let mut transfers = transfers.lock().unwrap();
+                    match args.parse::<u32>().ok().filter(|number| transfers.pending_mut(*number).is_some()) {
+                        Some(number) if accepted => {
+                            let incoming = transfers.pending_mut(number).expect("oferta pendente");
+                            match incoming.start() {
+                                Ok(resume_from) => {
+                                    ui.info(format!("Recebendo {} de {}...", incoming.name, incoming.from));
+                                    Some(Packet::File { to: incoming.from.clone(), transfer: FileTransfer::Answer { id: incoming.id, accepted, resume_from } })
+                                }
+                                Err(e) => {
+                                    ui.invalid(format!("Não foi possível criar {}: {}", incoming.partial_path().display(), e));
+                                    None
+                                }
+                            }
+                        }
+                        Some(number) => {
+                            let incoming = transfers.remove_incoming(number).expect("oferta pendente");
+                            ui.info(format!("Arquivo {} de {} recusado.", incoming.name, incoming.from));
+                            Some(Packet::File { to: incoming.from, transfer: FileTransfer::Answer { id: incoming.id, accepted, resume_from: 0 } })
+                        }
+                        None => {
+                            ui.info(format!("Nenhuma oferta de arquivo pendente com esse número. Uso: {}", command.usage));
+                            None
+                        }
+                    }
+                }
+                ClientAction::Inspect => {
+                    ui.toggle_inspector();
+                    None
+                }
+                ClientAction::Fingerprint => {
+                    ui.info(format!("Sua chave:         {}", my_pub_key.fingerprint()));
+                    ui.info(format!("Chave do servidor: {}", server_fingerprint));
+                    None
+                }
+                ClientAction::History | ClientAction::Search if history.is_none() => {
+                    ui.info("Histórico desativado; inicie o cliente com --history <arquivo>.");
+                    None
+                }
+                ClientAction::History => match if args.is_empty() { Ok(1) } else { args.parse::<usize>() } {
+                    Ok(page) if page > 0 => {
+                        let history = history.as_ref().unwrap().lock().unwrap();
+                        let (entries, pages) = history.page(page);
+                        ui.info(format!("Histórico, página {} de {}:", page, pages));
+                        for entry in entries {
+                            ui.info(entry.line());
+                        }
+                        if entries.is_empty() {
+                            ui.info("(nenhuma mensagem)");
+                        }
+                        None
+                    }
+                    _ => {
+                        ui.info(format!("Uso: {}", command.usage));
+                        None
+                    }
+                },
+                ClientAction::Search if !args.is_empty() => {
+                    let history = history.as_ref().unwrap().lock().unwrap();
+                    let found = history.search(args);
+                    ui.info(format!("{} mensagens com \"{}\":", found.len(), args));
+                    for entry in found {
+                        ui.info(entry.line());
+                    }
+                    None
+                }
+                ClientAction::Search => {
+                    ui.info(format!("Uso: {}", command.usage));
+                    None
+                }
+            },
+            Input::Unknown(name) => {
+                ui.info(format!("Comando desconhecido: /{}\n{}", name, commands::help_text(CLIENT_COMMANDS)));
+                None
+            }
+        };
 
-        writer_half.write_all(&message_hash).await.unwrap();
-        writer_half.write_all(&serialized_msg).await.unwrap();
-        writer_half.flush().await.unwrap(); // Garante que os dados sejam enviados.
+        // Envia o pacote; a escrita roda em outra tarefa.
+        if let Some(packet) = packet {
+            if packet_tx.send(packet).is_err() {
+                // A tarefa de escrita terminou: a conexão caiu.
+                reader_task.abort();
+                return Ending::Dropped;
+            }
+        }
 
         // Solicita a próxima mensagem.
-        print!("Cliente, sua resposta para o servidor: ");
-        let _ = std::io::stdout().flush();
+        ui.prompt(PROMPT);
     }
-}
\ No newline at end of file
+
+    // Espera o `Bye` (se houver) ser escrito antes de encerrar.
+    drop(packet_tx);
+    let _ = writer_task.await;
+    Ending::Closed
+}
+
+// O que espera a chave pública do destinatário chegar do diretório do servidor
+enum PendingDirect {
+    Text(String),
+    File(Outgoing),
+}
+
+// Envia os pedaços cifrados de um arquivo aceito a partir de `first`, mostrando o progresso.
+// Cada pedaço só é lido do disco quando há vaga na fila de arquivos da escrita.
+fn spawn_upload(outgoing: Outgoing, first: u64, file_tx: mpsc::Sender<Packet>, ui: ClientUi) {
+    tokio::spawn(async move {
+        let mut shown = 0;
+        let mut chunks = match outgoing.chunks(first) {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                ui.invalid(format!("*** Não foi possível ler {} para enviar a {}: {}", outgoing.name, outgoing.to, e));
+                return;
+            }
+        };
+        loop {
+            let Ok(slot) = file_tx.reserve().await else { return };
+            let Some(chunk) = chunks.next() else { break };
+            let (chunk, sent) = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    ui.invalid(format!("*** Envio de {} para {} interrompido: {}", outgoing.name, outgoing.to, e));
+                    return;
+                }
+            };
+            slot.send(Packet::File { to: outgoing.to.clone(), transfer: chunk });
+            let now = transfer::percent(sent, outgoing.size());
+            if now / 10 != shown / 10 {
+                ui.notice(format!("*** Enviando {} para {}: {}%", outgoing.name, outgoing.to, now));
+                shown = now;
+            }
+        }
+        ui.notice(format!("*** Arquivo {} enviado a {}", outgoing.name, outgoing.to));
+    });
+}
+
+// Salva a mensagem no histórico local, se ele estiver ativo
+fn remember(ui: &ClientUi, history: &Option<Arc<Mutex<History>>>, at: u64, conversation: String, from: Option<String>, text: String) {
+    let Some(history) = history else { return };
+    if let Err(e) = history.lock().unwrap().record(Entry { at, conversation, from, text }) {
+        ui.invalid(format!("*** Não foi possível salvar no histórico: {}", e));
+    }
+}
+
+/// Pede um apelido ao usuário e o registra no servidor, logo após o handshake.
+///
+/// Com `preferred`, a primeira tentativa usa esse apelido sem perguntar. Repete a
+/// pergunta enquanto o servidor recusar o apelido (já em uso ou inválido).
+/// Devolve o apelido aceito e se o servidor é um relay cego, ou `None` se o
+/// teclado for fechado antes disso.
+async fn register_nickname(stream: &mut TcpStream, codec: &FrameCodec, preferred: Option<&str>, input: &mut InputSource, ui: &ClientUi) -> Result<Option<(String, bool)>, ChatError> {
+    let mut preferred = preferred.map(str::to_string);
+    loop {
+        let line = match preferred.take() {
+            Some(nickname) => nickname,
+            None => {
+                ui.prompt("Escolha seu apelido: ");
+                let Some(line) = input.next_line().await else {
+                    let bye = Packet::Bye { reason: "cliente desistiu do registro".to_string() };
+                    let _ = codec.write_frame(stream, &Envelope::new(0, bye)).await;
+                    return Ok(None);
+                };
+                line
+            }
+        };
+        let nickname = line.trim();
+        if !protocol::is_valid_nickname(nickname) {
+            ui.info(ErrorCode::InvalidNickname.to_string());
+            continue;
+        }
+
+        let register = Packet::Register { nickname: nickname.to_string() };
+        codec.write_frame(stream, &Envelope::new(0, register)).await?;
+        match protocol::read_envelope(codec, stream).await {
+            Ok(Envelope { packet: Packet::Registered { nickname, relay }, .. }) => return Ok(Some((nickname, relay))),
+            Ok(Envelope { packet: Packet::Error { message, .. }, .. }) => ui.warning(format!("Servidor recusou o apelido: {}", message)),
+            Ok(_) | Err(ReadError::Rejected { .. }) => ui.info("Resposta inesperada do servidor ao registro"),
+            Err(ReadError::Io(e)) => return Err(e.into()),
+        }
+    }
+}
+
+// Confere a chave do servidor com o arquivo de servidores conhecidos; falha se ela mudou
+fn check_known_host(path: &Path, server: &str, fingerprint: &str, ui: &ClientUi) -> Result<(), ChatError> {
+    let check = known_hosts::check(path, server, fingerprint)
+        .map_err(|e| ChatError::io(format!("não foi possível conferir a chave do servidor em {}", path.display()), e))?;
+    match check {
+        HostCheck::Known => ui.detail(format!("Chave do servidor confere com {}", path.display())),
+        HostCheck::New => match known_hosts::remember(path, server, fingerprint) {
+            Ok(()) => ui.notice(format!("Primeira conexão a {}: chave {} guardada em {}", server, fingerprint, path.display())),
+            Err(e) => ui.warning(format!("Não foi possível guardar a chave do servidor em {}: {}", path.display(), e)),
+        },
+        HostCheck::Changed { expected } => {
+            return Err(ChatError::Auth(format!(
+                "a chave do servidor {} MUDOU: esperava {}, recebeu {}. Pode ser um ataque man-in-the-middle; conexão abandonada. Se a troca foi legítima, remova a linha do servidor em {}",
+                server, expected, fingerprint, path.display(),
+            )));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use std::fmt;
+use std::io::Error;
+use serde::{Serialize, Deserialize};
+use tokio::io::AsyncRead;
+use crate::direct::DirectMessage;
+use crate::frame::FrameCodec;
+use crate::group::GroupMessage;
+use crate::handshake::{CipherSuite, Offer};
+use crate::rsa::PublicKey;
+use crate::transfer::FileTransfer;
+
+/// Versão do protocolo usada nos envelopes enviados por este binário.
+///
+/// Sobe a cada mudança incompatível no fio, para que peers de versões diferentes
+/// recusem o primeiro pacote um do outro em vez de lerem campos trocados:
+/// 2, negociação da suíte no `ClientHello`/`ServerHello`; 3, transcrição assinada
+/// no `Finished`; 4, número de sequência no `Chat`; 5, remetente no `Chat` das
+/// salas; 6, relay cego (`Registered` com `relay`, sala nas mensagens diretas);
+/// 7, assinaturas RSA num bloco só; chave das mensagens diretas e metades do
+/// segredo na troca de chaves RSA encapsuladas num bloco sorteado.
+pub const PROTOCOL_VERSION: u16 = 7;
+
+/// Versões do protocolo aceitas por este binário, da mais antiga para a mais nova.
+pub const SUPPORTED_VERSIONS: &[u16] = &[PROTOCOL_VERSION];
+
+/// Envelope que acompanha todo pacote enviado no fio.
+///
+/// A versão e o ID vêm antes do pacote, de modo que o cabeçalho pode ser lido
+/// mesmo quando o pacote em si é de um tipo desconhecido.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope {
+    pub version: u16,
+    pub id: u64,
+    pub packet: Packet,
+}
+
+impl Envelope {
+    /// Cria um envelope com a versão atual do protocolo.
+    pub fn new(id: u64, packet: Packet) -> Self {
+        Envelope { version: PROTOCOL_VERSION, id, packet }
+    }
+}
+
+/// Todos os tipos de pacote do protocolo.
+///
+/// Novos tipos devem ser acrescentados sempre no final da lista: o bincode
+/// identifica a variante pela posição, então reordenar quebra peers antigos.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Packet {
+    /// Abre o handshake: algoritmos suportados pelo cliente, nonce e chave pública.
+    ClientHello { offer: Offer, nonce: [u8; 32], public_key: PublicKey },
+    /// Mensagem de chat: número de sequência da direção, remetente original quando
+    /// o servidor repassa a mensagem de outro participante, valor de integridade
+    /// (hash ou MAC, cobrindo também a sequência e o remetente) e o texto cifrado.
+    Chat { seq: u64, from: Option<String>, hash: [u8; 32], body: Ciphertext },
+    /// Confirma o recebimento do pacote com o ID informado.
+    Ack { id: u64 },
+    /// Verifica se o outro lado continua ativo.
+    Ping { nonce: u64 },
+    /// Resposta a um `Ping`, repetindo o mesmo nonce.
+    Pong { nonce: u64 },
+    /// Informa que o pacote com o ID `id` foi rejeitado.
+    Error { id: u64, code: ErrorCode, message: String },
+    /// Encerramento da conversa.
+    Bye { reason: String },
+    /// Resposta ao `ClientHello` com a suíte escolhida, nonce e chave pública do servidor.
+    ServerHello { suite: CipherSuite, nonce: [u8; 32], public_key: PublicKey },
+    /// Contribuição de cada lado para o segredo da sessão.
+    KeyExchange { material: Vec<u64> },
+    /// Fecha o handshake: assinatura RSA do hash da transcrição completa.
+    Finished { signature: u64 },
+    /// Pedido do cliente para mudar de sala.
+    Join { room: String },
+    /// Confirma a entrada do cliente na sala e lista os participantes.
+    Joined { room: String, members: Vec<String> },
+    /// Alguém entrou (`joined`) ou saiu da sala do cliente.
+    Presence { room: String, who: String, joined: bool },
+    /// Pedido do cliente para usar (ou trocar para) um apelido.
+    Register { nickname: String },
+    /// Confirma o apelido registrado para o cliente e informa se o servidor é
+    /// um relay cego (`relay`), que só repassa mensagens de ponta a ponta.
+    Registered { nickname: String, relay: bool },
+    /// Um participante da sala trocou de apelido.
+    Renamed { old: String, new: String },
+    /// Pedido do cliente pela lista de participantes da sala atual; a resposta é um `Joined`.
+    Who,
+    /// Consulta ao diretório do servidor: chave pública vinculada a um apelido.
+    KeyRequest { nickname: String },
+    /// Resposta do diretório; `None` se ninguém usa o apelido.
+    KeyResponse { nickname: String, public_key: Option<PublicKey> },
+    /// Mensagem do cliente para o participante `to`, cifrada de ponta a ponta:
+    /// privada (`room` vazio) ou a entrega da chave de remetente do cliente na sala `room`.
+    Direct { to: String, room: Option<String>, message: DirectMessage },
+    /// Mensagem de ponta a ponta repassada pelo servidor, com a chave do remetente segundo o diretório.
+    Forwarded { from: String, room: Option<String>, sender_key: PublicKey, message: DirectMessage },
+    /// Consulta ao diretório: chaves públicas dos participantes da sala atual.
+    RoomKeysRequest,
+    /// Resposta do diretório com o apelido e a chave de cada participante da sala.
+    RoomKeys { room: String, keys: Vec<(String, PublicKey)> },
+    /// Mensagem para a sala cifrada uma vez com a chave de remetente do cliente.
+    GroupChat { room: String, message: GroupMessage },
+    /// Mensagem de grupo repassada pelo servidor, com a chave do remetente segundo o diretório.
+    GroupForwarded { from: String, room: String, sender_key: PublicKey, message: GroupMessage },
+    /// O destinatário `to` está desconectado: a mensagem privada ficou guardada no servidor.
+    Queued { to: String },
+    /// Mensagem privada guardada enquanto o cliente estava desconectado, com a hora
+    /// (segundos desde 1970, UTC) em que chegou ao servidor.
+    Stored { from: String, sender_key: PublicKey, sent_at: u64, message: DirectMessage },
+    /// Etapa de uma transferência de arquivo de ponta a ponta, para o cliente `to`.
+    File { to: String, transfer: FileTransfer },
+    /// Etapa de transferência de arquivo repassada pelo servidor, com a chave de quem a enviou.
+    FileForwarded { from: String, sender_key: PublicKey, transfer: FileTransfer },
+    /// Ticket de retomada emitido pelo servidor depois do registro, válido por `lifetime` segundos.
+    Ticket { ticket: [u8; 32], lifetime: u64 },
+    /// Abre a conexão retomando uma sessão anterior no lugar do `ClientHello`: o ticket,
+    /// o nonce do cliente e o MAC com que ele prova conhecer o segredo daquela sessão.
+    Resume { ticket: [u8; 32], nonce: [u8; 32], binder: [u8; 32] },
+    /// Aceita a retomada: nonce do servidor e o MAC com que ele prova o mesmo.
+    Resumed { nonce: [u8; 32], proof: [u8; 32] },
+}
+
+/// Dados associados de uma mensagem de chat: o remetente vai às claras, mas
+/// entra no hash/MAC para que não possa ser trocado no caminho.
+pub fn chat_associated_data(from: &Option<String>) -> Vec<u8> {
+    bincode::serialize(from).expect("remetente serializável")
+}
+
+/// Dados associados de uma mensagem de ponta a ponta: a sala (ou `None`, se for
+/// privada) entra na assinatura, para que o servidor não a apresente em outro contexto.
+pub fn direct_associated_data(room: &Option<String>) -> Vec<u8> {
+    bincode::serialize(room).expect("sala serializável")
+}
+
+/// Apelido reservado para as mensagens do operador do servidor.
+pub const SERVER_NICKNAME: &str = "SERVIDOR";
+
+/// Valida um apelido: de 1 a 20 letras, dígitos, `_` ou `-`, diferente do apelido do servidor.
+pub fn is_valid_nickname(nickname: &str) -> bool {
+    !nickname.is_empty()
+        && nickname.chars().count() <= 20
+        && nickname.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        && !nickname.eq_ignore_ascii_case(SERVER_NICKNAME)
+}
+
+/// Valida o nome de uma sala: de 1 a 32 caracteres, sem espaços.
+pub fn is_valid_room_name(room: &str) -> bool {
+    !room.is_empty() && room.chars().count() <= 32 && !room.chars().any(char::is_whitespace)
+}
+
+/// Texto cifrado de uma mensagem, no formato da cifra negociada.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Ciphertext {
+    /// Um bloco RSA por byte da mensagem.
+    RsaBlocks(Vec<u64>),
+    /// Bytes cifrados com a cifra de fluxo; o nonce é o número de sequência da mensagem.
+    Stream(Vec<u8>),
+}
+
+impl Ciphertext {
+    /// Altera o primeiro bloco ou byte, simulando adulteração no caminho.
+    pub fn tamper(&mut self) {
+        match self {
+            Ciphertext::RsaBlocks(blocks) => {
+                if let Some(first) = blocks.get_mut(0) {
+                    *first = first.wrapping_add(1);
+                }
+            }
+            Ciphertext::Stream(bytes) => {
+                if let Some(first) = bytes.get_mut(0) {
+                    *first ^= 1;
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Ciphertext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ciphertext::RsaBlocks(blocks) => write!(f, "(Vec<u64>): {:?}", blocks),
+            Ciphertext::Stream(bytes) => write!(f, "(fluxo): {}", hex::encode(bytes)),
+        }
+    }
+}
+
+/// Motivos pelos quais um pacote pode ser rejeitado.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    UnsupportedVersion,
+    UnknownPacket,
+    Unexpected,
+    NoCommonSuite,
+    AuthenticationFailed,
+    NicknameTaken,
+    InvalidNickname,
+    UnknownUser,
+    RelayOnly,
+    MailboxFull,
+    ResumptionRejected,
+    InvalidKey,
+    NicknameReserved,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            ErrorCode::UnsupportedVersion => "versão de protocolo não suportada",
+            ErrorCode::UnknownPacket => "tipo de pacote desconhecido",
+            ErrorCode::Unexpected => "pacote inesperado",
+            ErrorCode::NoCommonSuite => "nenhuma suíte criptográfica em comum",
+            ErrorCode::AuthenticationFailed => "falha na autenticação",
+            ErrorCode::NicknameTaken => "apelido já está em uso",
+            ErrorCode::InvalidNickname => "apelido inválido (1 a 20 letras, dígitos, _ ou -)",
+            ErrorCode::UnknownUser => "nenhum participante com esse apelido",
+            ErrorCode::RelayOnly => "servidor em modo relay: só repassa mensagens cifradas de ponta a ponta",
+            ErrorCode::MailboxFull => "caixa postal do destinatário está cheia",
+            ErrorCode::ResumptionRejected => "ticket de retomada desconhecido, expirado ou inválido",
+            ErrorCode::InvalidKey => "chave pública inválida",
+            ErrorCode::NicknameReserved => "apelido pertence a outra chave",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// Falha ao ler um envelope do fluxo.
+#[derive(Debug)]
+pub enum ReadError {
+    /// Erro de E/S ou frame inválido: a conexão não pode continuar.
+    Io(Error),
+    /// O frame chegou inteiro, mas o pacote foi recusado. A conexão pode seguir
+    /// após avisar o outro lado com um `Packet::Error`.
+    Rejected { id: u64, code: ErrorCode },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "{}", e),
+            ReadError::Rejected { id, code } => write!(f, "pacote #{} rejeitado: {}", id, code),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// Lê o próximo envelope do fluxo.
+///
+/// Primeiro decodifica apenas a versão e o ID; só então tenta decodificar o
+/// pacote. Assim, versões ou tipos de pacote desconhecidos são rejeitados sem
+/// derrubar a conexão.
+pub async fn read_envelope<R>(codec: &FrameCodec, reader: &mut R) -> Result<Envelope, ReadError>
+where
+    R: AsyncRead + Unpin,
+{
+    let bytes = codec.read_frame_bytes(reader).await.map_err(ReadError::Io)?;
+
+    // O cabeçalho (versão, ID) fica no início do payload serializado.
+    let (version, id): (u16, u64) = bincode::deserialize(&bytes)
+        .map_err(|e| ReadError::Io(Error::new(std::io::ErrorKind::InvalidData, e)))?;
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Err(ReadError::Rejected { id, code: ErrorCode::UnsupportedVersion });
+    }
+
+    bincode::deserialize(&bytes).map_err(|_| ReadError::Rejected { id, code: ErrorCode::UnknownPacket })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Frame com o payload bincode de `value`, como `FrameCodec::write_frame` escreveria
+    fn frame<T: Serialize>(value: &T) -> Vec<u8> {
+        let payload = bincode::serialize(value).unwrap();
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    async fn read(bytes: &[u8]) -> Result<Envelope, ReadError> {
+        read_envelope(&FrameCodec::default(), &mut &bytes[..]).await
+    }
+
+    #[tokio::test]
+    async fn reads_envelopes_of_this_version() {
+        let envelope = read(&frame(&Envelope::new(3, Packet::Ping { nonce: 42 }))).await.unwrap();
+        assert_eq!((envelope.version, envelope.id), (PROTOCOL_VERSION, 3));
+        assert!(matches!(envelope.packet, Packet::Ping { nonce: 42 }));
+    }
+
+    #[tokio::test]
+    async fn rejects_other_versions_keeping_the_id() {
+        let envelope = Envelope { version: PROTOCOL_VERSION + 1, id: 9, packet: Packet::Ping { nonce: 1 } };
+        assert!(matches!(read(&frame(&envelope)).await, Err(ReadError::Rejected { id: 9, code: ErrorCode::UnsupportedVersion })));
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_packets_keeping_the_id() {
+        // Cabeçalho válido seguido de uma variante que não existe.
+        let unknown = (PROTOCOL_VERSION, 5u64, u32::MAX);
+        assert!(matches!(read(&frame(&unknown)).await, Err(ReadError::Rejected { id: 5, code: ErrorCode::UnknownPacket })));
+    }
+
+    #[tokio::test]
+    async fn malformed_header_is_an_io_error() {
+        assert!(matches!(read(&frame(&0u8)).await, Err(ReadError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData));
+    }
+}
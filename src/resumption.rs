@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use rand::Rng;
+use crate::session::Resumable;
+
+/// Por quanto tempo um ticket de retomada vale depois de emitido.
+pub const TICKET_LIFETIME: Duration = Duration::from_secs(10 * 60);
+
+/// Identificador opaco de um ticket, sorteado pelo servidor.
+pub type TicketId = [u8; 32];
+
+/// Ticket de retomada: o identificador dado pelo servidor e a sessão a que ele se refere.
+#[derive(Clone)]
+pub struct Ticket {
+    pub id: TicketId,
+    pub expires: Instant,
+    pub state: Resumable,
+}
+
+impl Ticket {
+    /// Ticket recebido do servidor, válido por `lifetime` segundos a partir de agora;
+    /// `None` se a validade passar de `TICKET_LIFETIME`, o máximo que o cliente aceita.
+    pub fn new(id: TicketId, lifetime: u64, state: Resumable) -> Option<Self> {
+        let lifetime = Duration::from_secs(lifetime);
+        if lifetime > TICKET_LIFETIME {
+            return None;
+        }
+        Some(Ticket { id, expires: Instant::now().checked_add(lifetime)?, state })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires
+    }
+}
+
+/// Tickets emitidos pelo servidor e ainda não usados.
+///
+/// Ficam só na memória: reiniciar o servidor invalida todos, e o cliente volta
+/// ao handshake completo. Cada ticket vale uma retomada; a sessão retomada
+/// recebe um ticket novo depois do registro.
+#[derive(Default)]
+pub struct TicketStore {
+    tickets: Mutex<HashMap<TicketId, Ticket>>,
+}
+
+impl TicketStore {
+    /// Guarda a sessão e devolve o identificador do ticket que a retoma.
+    pub fn issue(&self, state: Resumable) -> TicketId {
+        let id: TicketId = rand::thread_rng().gen();
+        let mut tickets = self.tickets.lock().unwrap();
+        tickets.retain(|_, ticket| !ticket.is_expired());
+        tickets.insert(id, Ticket { id, expires: Instant::now() + TICKET_LIFETIME, state });
+        id
+    }
+
+    /// Retira o ticket da lista se `proves` aceitar a prova de quem o apresenta;
+    /// `None` se ele não existe, já expirou ou a prova não confere.
+    ///
+    /// Uma prova errada não gasta o ticket: o identificador vai às claras, e quem
+    /// só o copiou do fio não pode impedir o dono de retomar a sessão.
+    pub fn redeem(&self, id: &TicketId, proves: impl FnOnce(&Ticket) -> bool) -> Option<Ticket> {
+        let mut tickets = self.tickets.lock().unwrap();
+        if tickets.get(id)?.is_expired() {
+            tickets.remove(id);
+            return None;
+        }
+        if !proves(&tickets[id]) {
+            return None;
+        }
+        tickets.remove(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handshake::Offer;
+    use crate::rsa;
+    use crate::session::{Role, Session};
+
+    fn state() -> Resumable {
+        let offer = Offer::supported();
+        let (peer_key, _) = rsa::new_keypair(None);
+        let (my_pub_key, my_priv_key) = rsa::new_keypair(None);
+        Session::new(offer.negotiate(&offer).unwrap(), Role::Client, [5; 32], peer_key, my_pub_key, my_priv_key).resumable()
+    }
+
+    #[test]
+    fn ticket_lifetime_is_capped() {
+        let ticket = Ticket::new([1; 32], TICKET_LIFETIME.as_secs(), state()).unwrap();
+        assert!(!ticket.is_expired());
+        assert!(Ticket::new([1; 32], TICKET_LIFETIME.as_secs() + 1, state()).is_none());
+        assert!(Ticket::new([1; 32], u64::MAX, state()).is_none());
+        assert!(Ticket::new([1; 32], 0, state()).unwrap().is_expired());
+    }
+
+    #[test]
+    fn tickets_are_redeemed_once_with_a_valid_proof() {
+        let store = TicketStore::default();
+        let id = store.issue(state());
+        assert!(store.redeem(&id, |_| false).is_none());
+        assert_eq!(store.redeem(&id, |_| true).unwrap().id, id);
+        assert!(store.redeem(&id, |_| true).is_none());
+        assert!(store.redeem(&[0; 32], |_| true).is_none());
+    }
+
+    #[test]
+    fn expired_tickets_are_refused_and_dropped() {
+        let store = TicketStore::default();
+        let expired = Ticket { id: [7; 32], expires: Instant::now() - Duration::from_secs(1), state: state() };
+        store.tickets.lock().unwrap().insert(expired.id, expired.clone());
+        assert!(store.redeem(&expired.id, |_| true).is_none());
+        assert!(store.tickets.lock().unwrap().is_empty());
+
+        // Emitir outro ticket limpa os vencidos.
+        store.tickets.lock().unwrap().insert(expired.id, expired.clone());
+        let id = store.issue(state());
+        let tickets = store.tickets.lock().unwrap();
+        assert!(tickets.contains_key(&id) && !tickets.contains_key(&expired.id));
+    }
+}
@@ -1,31 +1,50 @@
 use rand::Rng;
 use serde::{Serialize, Deserialize};
+use std::io;
+use std::path::Path;
+use std::string::FromUtf8Error;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct PublicKey{
     pub e: u64,
     pub n: u64
 }
 
 impl PublicKey{
-    pub fn to_bytes(&self) -> Vec<u8> {
-        bincode::serialize(self).unwrap()
+    // Impressão digital da chave: primeiros 8 bytes do SHA-256 da chave serializada, em hex
+    pub fn fingerprint(&self) -> String {
+        let bytes = bincode::serialize(self).unwrap();
+        let hash = crate::sha::sha256(&bytes);
+        hash[..8].iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
     }
 
-    pub fn from_bytes(data: &[u8]) -> Self{
-        bincode::deserialize(data).unwrap()
+    // Chave que `mod_exp` consegue usar: n cifra qualquer byte (n >= 256) sem estourar
+    // o u64 ao multiplicar (n < 2^32), e 1 < e < n. Chaves vindas da rede passam por aqui.
+    pub fn is_valid(&self) -> bool {
+        (256..1u64 << MAX_KEY_BITS).contains(&self.n) && 1 < self.e && self.e < self.n
     }
-
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PrivateKey{
     pub d: u64,
     pub n: u64
 }
 
+/// Menor tamanho de n aceito: com 9 bits, n passa de 255 e cifra qualquer byte.
+pub const MIN_KEY_BITS: u32 = 9;
+
+/// Maior tamanho de n aceito: `mod_exp` multiplica dois valores menores que n em u64.
+pub const MAX_KEY_BITS: u32 = 32;
+
 pub fn generate_keypair() -> (PublicKey, PrivateKey){
-    let (_p, _q, n, tot) = generate_keys();
+    // N precisa ser maior que 255 para que qualquer byte (0..=255) seja cifrado sem perda
+    let (_p, _q, n, tot) = loop {
+        let keys = generate_keys();
+        if keys.2 > 255 {
+            break keys;
+        }
+    };
     let e = choose_random_e(tot);
     let d = modinv(e, tot);
 
@@ -35,6 +54,61 @@ pub fn generate_keypair() -> (PublicKey, PrivateKey){
     )
 }
 
+// Gera um par de chaves cujo n tem exatamente `bits` bits (entre MIN_KEY_BITS e MAX_KEY_BITS)
+pub fn generate_keypair_with_bits(bits: u32) -> (PublicKey, PrivateKey){
+    let bits = bits.clamp(MIN_KEY_BITS, MAX_KEY_BITS);
+    // p fica com a metade maior dos bits; o produto tem a soma dos bits ou um a menos
+    let p_bits = bits.div_ceil(2);
+    let q_bits = bits - p_bits + 1;
+    let (n, tot) = loop {
+        let p = generate_prime_with_bits(p_bits);
+        let q = generate_prime_with_bits(q_bits);
+        let n = p * q;
+        if p != q && u64::BITS - n.leading_zeros() == bits {
+            break (n, (p - 1) * (q - 1));
+        }
+    };
+    let e = choose_random_e(tot);
+    let d = modinv(e, tot);
+
+    (
+        PublicKey { e, n },
+        PrivateKey { d, n }
+    )
+}
+
+// Com `bits`, um par com esse tamanho de n; sem, o tamanho padrão de `generate_keypair`
+pub fn new_keypair(bits: Option<u32>) -> (PublicKey, PrivateKey){
+    match bits {
+        Some(bits) => generate_keypair_with_bits(bits),
+        None => generate_keypair(),
+    }
+}
+
+// Lê o par de chaves salvo em `caminho` (JSON); se o arquivo não existe, gera um par novo
+// (com `bits` bits, se indicado) e o salva lá.
+// Assim o cliente mantém a mesma identidade entre execuções.
+pub fn load_or_create_keypair(caminho: &Path, bits: Option<u32>) -> io::Result<(PublicKey, PrivateKey)> {
+    match load_keypair(caminho) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let par = new_keypair(bits);
+            save_keypair(caminho, &par)?;
+            Ok(par)
+        }
+        result => result,
+    }
+}
+
+// Lê o par de chaves salvo em `caminho` (JSON)
+pub fn load_keypair(caminho: &Path) -> io::Result<(PublicKey, PrivateKey)> {
+    let bytes = std::fs::read(caminho)?;
+    serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Salva o par de chaves em `caminho` (JSON), no formato lido por `load_keypair`
+pub fn save_keypair(caminho: &Path, par: &(PublicKey, PrivateKey)) -> io::Result<()> {
+    std::fs::write(caminho, serde_json::to_vec_pretty(par)?)
+}
 
 //Outras funções
 fn gcd(a: u64, b: u64) -> u64 {
@@ -48,7 +122,7 @@ fn gcd(a: u64, b: u64) -> u64 {
 //Exponenciacao modula rapida
 pub fn mod_exp(mut base: u64, mut exp: u64, modulo: u64) -> u64 {
     let mut result = 1;
-    base = base % modulo;
+    base %= modulo;
 
     while exp > 0 {
         if exp % 2 == 1 {
@@ -67,7 +141,7 @@ pub fn is_prime(n: u64) -> bool{
         return false;
     }
     for i in 2..=((n as f64).sqrt() as u64) {
-        if n % i == 0 {
+        if n.is_multiple_of(i) {
             return false;
         }
     }
@@ -84,6 +158,17 @@ fn generate_small_prime() -> u64 {
     }
 }
 
+// Primo aleatório com exatamente `bits` bits
+fn generate_prime_with_bits(bits: u32) -> u64 {
+    let mut rng = rand::thread_rng();
+    loop {
+        let n = rng.gen_range((1u64 << (bits - 1))..(1u64 << bits));
+        if is_prime(n) {
+            return n;
+        }
+    }
+}
+
 pub fn generate_two_distinct_primes() -> (u64, u64) {
     let p = generate_small_prime();
     let mut q = generate_small_prime();
@@ -141,23 +226,65 @@ pub fn modinv(e: u64, tot: u64) -> u64 {
 //   mod_exp(message, e, n)
 //}
 
+// Criptografa os bytes UTF-8 da mensagem, então acentos e emoji viram vários blocos
 pub fn encrypt_string(mensagem: &str, e: u64, n: u64) -> Vec<u64> {
-    mensagem.chars().map(|c| {
-        let m = c as u8 as u64;
-        mod_exp(m, e, n)
-    }).collect()
+    encrypt_bytes(mensagem.as_bytes(), e, n)
+}
+
+
+// Criptografa bytes arbitrários, um bloco por byte
+pub fn encrypt_bytes(dados: &[u8], e: u64, n: u64) -> Vec<u64> {
+    dados.iter().map(|&b| mod_exp(b as u64, e, n)).collect()
+}
+
+// Descriptografa blocos de volta em bytes
+pub fn decrypt_bytes(criptografado: &[u64], d: u64, n: u64) -> Vec<u8> {
+    criptografado.iter().map(|&c| mod_exp(c, d, n) as u8).collect()
+}
+
+// Encapsula uma chave simétrica nova para o dono de `chave` (RSA-KEM): sorteia r em [2, n),
+// envia r^e mod n num bloco só e deriva a chave de r. Com r sorteado a cada chamada, a mesma
+// chave pública nunca produz blocos repetidos que deixem montar uma tabela de volta.
+// Devolve o bloco a enviar e a chave derivada.
+pub fn encapsulate(chave: &PublicKey) -> (u64, [u8; 32]) {
+    let r = rand::thread_rng().gen_range(2..chave.n);
+    (mod_exp(r, chave.e, chave.n), encapsulated_key(r))
+}
+
+// Recupera, com a chave privada, a chave simétrica encapsulada em `bloco`
+pub fn decapsulate(bloco: u64, chave: &PrivateKey) -> [u8; 32] {
+    encapsulated_key(mod_exp(bloco, chave.d, chave.n))
+}
+
+fn encapsulated_key(r: u64) -> [u8; 32] {
+    crate::cipher::derive_key(&r.to_be_bytes(), "chave encapsulada")
+}
+
+// Assina o hash inteiro com a chave privada: o hash vira um único bloco h mod n e a
+// assinatura é h^d mod n. Assinar byte a byte deixaria juntar pedaços de assinaturas
+// observadas (um bloco para cada valor de byte) e montar a de qualquer outro hash.
+pub fn sign(hash: &[u8], chave: &PrivateKey) -> u64 {
+    mod_exp(hash_block(hash, chave.n), chave.d, chave.n)
 }
 
+// Verifica a assinatura com a chave pública: elevada a e, ela deve voltar ao bloco do hash
+// (uma chave inválida nunca confere)
+pub fn verify(hash: &[u8], assinatura: u64, chave: &PublicKey) -> bool {
+    chave.is_valid() && assinatura < chave.n && mod_exp(assinatura, chave.e, chave.n) == hash_block(hash, chave.n)
+}
+
+// Lê os bytes do hash como um número big-endian e o reduz módulo n
+fn hash_block(hash: &[u8], n: u64) -> u64 {
+    hash.iter().fold(0, |bloco, &b| ((bloco as u128 * 256 + b as u128) % n as u128) as u64)
+}
 
 //pub fn decrypt(cipher: u64, d: u64, n: u64) -> u64 {
 //    mod_exp(cipher, d, n)
 //}
 
-pub fn decrypt_string(criptografado: &[u64], d: u64, n: u64) -> String {
-    criptografado.iter().map(|&c| {
-        let m = mod_exp(c, d, n);
-        m as u8 as char
-    }).collect()
+// Descriptografa e remonta o texto; bytes que não formam UTF-8 válido viram erro
+pub fn decrypt_string(criptografado: &[u64], d: u64, n: u64) -> Result<String, FromUtf8Error> {
+    String::from_utf8(decrypt_bytes(criptografado, d, n))
 }
 
 
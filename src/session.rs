@@ -0,0 +1,410 @@
+use crate::cipher;
+use crate::handshake::{CipherAlgorithm, CipherSuite};
+use crate::inspector::{Direction, Inspection};
+use crate::protocol::Ciphertext;
+use crate::rsa::{self, PrivateKey, PublicKey};
+use crate::sha::{sha256, compare_hashes};
+
+/// Quantas mensagens atrás da mais recente ainda são aceitas fora de ordem.
+const REPLAY_WINDOW: u64 = 64;
+
+/// Lado da conexão, usado para separar as chaves de cada direção.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// Resultado do handshake: a suíte negociada, a chave pública do outro lado
+/// e o segredo compartilhado da conexão.
+pub struct Session {
+    pub suite: CipherSuite,
+    pub peer_key: PublicKey,
+    pub my_pub_key: PublicKey,
+    /// Se a sessão veio de um ticket de retomada, sem nova troca de chaves.
+    pub resumed: bool,
+    my_priv_key: PrivateKey,
+    secret: [u8; 32],
+    role: Role,
+}
+
+impl Session {
+    pub fn new(suite: CipherSuite, role: Role, secret: [u8; 32], peer_key: PublicKey, my_pub_key: PublicKey, my_priv_key: PrivateKey) -> Self {
+        Session { suite, peer_key, my_pub_key, resumed: false, my_priv_key, secret, role }
+    }
+
+    /// O que é preciso guardar para retomar esta sessão em outra conexão.
+    pub fn resumable(&self) -> Resumable {
+        Resumable {
+            suite: self.suite,
+            peer_key: self.peer_key.clone(),
+            my_pub_key: self.my_pub_key.clone(),
+            my_priv_key: self.my_priv_key.clone(),
+            secret: self.secret,
+        }
+    }
+
+    /// Separa a sessão em uma metade de envio e uma de recebimento, para que a
+    /// tarefa de leitura e o laço de escrita tenham cada um o seu estado.
+    pub fn split(self) -> (SendCipher, RecvCipher) {
+        let (send_label, recv_label) = match self.role {
+            Role::Client => ("cliente->servidor", "servidor->cliente"),
+            Role::Server => ("servidor->cliente", "cliente->servidor"),
+        };
+        let send_key = cipher::derive_key(&self.secret, send_label);
+        let recv_key = cipher::derive_key(&self.secret, recv_label);
+
+        let sender = SendCipher {
+            algorithm: self.suite.cipher,
+            peer_key: self.peer_key.clone(),
+            enc_key: cipher::derive_key(&send_key, "cifra"),
+            mac_key: cipher::derive_key(&send_key, "mac"),
+            next_seq: 1,
+        };
+        let receiver = RecvCipher {
+            algorithm: self.suite.cipher,
+            my_pub_key: self.my_pub_key,
+            my_priv_key: self.my_priv_key,
+            enc_key: cipher::derive_key(&recv_key, "cifra"),
+            mac_key: cipher::derive_key(&recv_key, "mac"),
+            window: ReplayWindow::default(),
+        };
+        (sender, receiver)
+    }
+}
+
+/// Estado de uma sessão guardado para retomá-la sem repetir a troca de chaves.
+///
+/// O segredo anterior nunca é reutilizado como está: prova a posse do ticket
+/// e dá origem, junto com os nonces da retomada, ao segredo da sessão nova.
+#[derive(Clone)]
+pub struct Resumable {
+    pub suite: CipherSuite,
+    pub peer_key: PublicKey,
+    my_pub_key: PublicKey,
+    my_priv_key: PrivateKey,
+    secret: [u8; 32],
+}
+
+impl Resumable {
+    /// MAC com que o lado `role` prova conhecer o segredo da sessão anterior.
+    pub fn proof(&self, role: Role, data: &[u8]) -> [u8; 32] {
+        let label = match role {
+            Role::Client => "retomada do cliente",
+            Role::Server => "retomada do servidor",
+        };
+        cipher::mac(&cipher::derive_key(&self.secret, label), 0, &[], data)
+    }
+
+    /// Sessão nova com um segredo derivado do anterior e dos nonces dos dois lados.
+    pub fn resume(self, role: Role, client_nonce: &[u8; 32], server_nonce: &[u8; 32]) -> Session {
+        let mut secret_input = self.secret.to_vec();
+        secret_input.extend_from_slice(b"retomada");
+        secret_input.extend_from_slice(client_nonce);
+        secret_input.extend_from_slice(server_nonce);
+        let mut session = Session::new(self.suite, role, sha256(&secret_input), self.peer_key, self.my_pub_key, self.my_priv_key);
+        session.resumed = true;
+        session
+    }
+}
+
+/// Metade de envio da sessão: cifra e autentica as mensagens que saem.
+pub struct SendCipher {
+    algorithm: CipherAlgorithm,
+    peer_key: PublicKey,
+    enc_key: [u8; 32],
+    mac_key: [u8; 32],
+    next_seq: u64,
+}
+
+/// Mensagem cifrada pronta para ir no pacote `Chat`.
+pub struct Sealed {
+    pub seq: u64,
+    pub hash: [u8; 32],
+    pub body: Ciphertext,
+}
+
+impl SendCipher {
+    /// Cifra o texto com o algoritmo negociado e atribui o próximo número de sequência.
+    ///
+    /// O valor de integridade (hash SHA-256 no modo RSA, HMAC no modo de fluxo)
+    /// cobre também o número de sequência e os dados associados `associated`,
+    /// que seguem às claras mas não podem ser trocados no caminho.
+    pub fn seal(&mut self, text: &str, associated: &[u8]) -> Sealed {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        match self.algorithm {
+            CipherAlgorithm::RsaBlock => {
+                let blocks = rsa::encrypt_string(text, self.peer_key.e, self.peer_key.n);
+                Sealed { seq, hash: sequenced_hash(seq, associated, text.as_bytes()), body: Ciphertext::RsaBlocks(blocks) }
+            }
+            CipherAlgorithm::Sha256Ctr => {
+                // O número de sequência também serve de nonce: nunca se repete na mesma direção.
+                let mut bytes = text.as_bytes().to_vec();
+                cipher::apply_keystream(&self.enc_key, seq, &mut bytes);
+                Sealed { seq, hash: cipher::mac(&self.mac_key, seq, associated, &bytes), body: Ciphertext::Stream(bytes) }
+            }
+        }
+    }
+
+    /// Descreve para o inspetor uma mensagem já cifrada por `seal`, como foi para a rede.
+    pub fn inspect(&self, peer: &str, text: &str, seq: u64, hash: &[u8; 32], body: &Ciphertext) -> Inspection {
+        Inspection {
+            direction: Direction::Sent,
+            peer: peer.to_string(),
+            seq,
+            algorithm: self.algorithm,
+            plaintext: text.as_bytes().to_vec(),
+            ciphertext: body.clone(),
+            integrity: *hash,
+            verdict: None,
+            key: self.peer_key.clone(),
+        }
+    }
+}
+
+// Hash SHA-256 da sequência, dos dados associados e dos bytes UTF-8 do texto, usado no modo RSA
+fn sequenced_hash(seq: u64, associated: &[u8], text: &[u8]) -> [u8; 32] {
+    sha256(&cipher::authenticated_data(seq, associated, text))
+}
+
+/// Resultado da verificação de uma mensagem recebida.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Integridade confirmada e número de sequência inédito.
+    Valid,
+    /// O hash/MAC não confere: a mensagem foi alterada.
+    Invalid,
+    /// Número de sequência já recebido: possível ataque de replay.
+    Replayed,
+    /// Número de sequência antigo demais para a janela de aceitação.
+    OutOfWindow,
+    /// Íntegra, mas o texto decifrado não é UTF-8 válido.
+    InvalidUtf8,
+}
+
+/// Janela deslizante dos números de sequência já recebidos.
+///
+/// Aceita mensagens fora de ordem dentro da janela, mas nunca a mesma duas vezes.
+#[derive(Default)]
+struct ReplayWindow {
+    highest: u64,
+    seen: u64, // bit i = mensagem `highest - i` já recebida
+}
+
+impl ReplayWindow {
+    fn check(&self, seq: u64) -> Verdict {
+        if seq == 0 || (seq <= self.highest && self.highest - seq >= REPLAY_WINDOW) {
+            return Verdict::OutOfWindow;
+        }
+        if seq <= self.highest && self.seen & (1 << (self.highest - seq)) != 0 {
+            return Verdict::Replayed;
+        }
+        Verdict::Valid
+    }
+
+    fn accept(&mut self, seq: u64) {
+        if seq > self.highest {
+            let shift = seq - self.highest;
+            self.seen = if shift >= REPLAY_WINDOW { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.highest = seq;
+        } else {
+            self.seen |= 1 << (self.highest - seq);
+        }
+    }
+}
+
+/// Metade de recebimento da sessão: decifra e verifica as mensagens que chegam.
+pub struct RecvCipher {
+    algorithm: CipherAlgorithm,
+    my_pub_key: PublicKey,
+    my_priv_key: PrivateKey,
+    enc_key: [u8; 32],
+    mac_key: [u8; 32],
+    window: ReplayWindow,
+}
+
+impl RecvCipher {
+    /// Verifica o número de sequência, decifra a mensagem e confere sua integridade.
+    ///
+    /// Repetições e mensagens fora da janela são descartadas sem decifrar. Só
+    /// mensagens íntegras avançam a janela, para que um pacote forjado não possa
+    /// "queimar" números de sequência legítimos. Uma mensagem cifrada com um
+    /// algoritmo diferente do negociado nunca é considerada válida.
+    pub fn open(&mut self, seq: u64, associated: &[u8], hash: &[u8; 32], body: &Ciphertext) -> (String, Verdict) {
+        let window_verdict = self.window.check(seq);
+        if window_verdict != Verdict::Valid {
+            return (String::new(), window_verdict);
+        }
+
+        // A integridade é conferida sobre os bytes, antes de exigir UTF-8 válido.
+        let (decoded, valid) = match (self.algorithm, body) {
+            (CipherAlgorithm::RsaBlock, Ciphertext::RsaBlocks(blocks)) => {
+                let decoded = rsa::decrypt_string(blocks, self.my_priv_key.d, self.my_priv_key.n);
+                let bytes = match &decoded {
+                    Ok(text) => text.as_bytes(),
+                    Err(e) => e.as_bytes(),
+                };
+                let valid = compare_hashes(&sequenced_hash(seq, associated, bytes), hash);
+                (decoded, valid)
+            }
+            (CipherAlgorithm::Sha256Ctr, Ciphertext::Stream(bytes)) => {
+                let valid = cipher::macs_equal(&cipher::mac(&self.mac_key, seq, associated, bytes), hash);
+                let mut plain = bytes.clone();
+                cipher::apply_keystream(&self.enc_key, seq, &mut plain);
+                (String::from_utf8(plain), valid)
+            }
+            _ => return ("<mensagem com cifra diferente da negociada>".to_string(), Verdict::Invalid),
+        };
+
+        if !valid {
+            let text = match decoded {
+                Ok(text) => text,
+                Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+            };
+            return (text, Verdict::Invalid);
+        }
+
+        self.window.accept(seq);
+        match decoded {
+            Ok(text) => (text, Verdict::Valid),
+            Err(e) => (e.to_string(), Verdict::InvalidUtf8),
+        }
+    }
+    /// Descreve para o inspetor uma mensagem recebida, com o texto e o veredito de `open`.
+    pub fn inspect(&self, peer: &str, seq: u64, hash: &[u8; 32], body: &Ciphertext, text: &str, verdict: Verdict) -> Inspection {
+        Inspection {
+            direction: Direction::Received,
+            peer: peer.to_string(),
+            seq,
+            algorithm: self.algorithm,
+            plaintext: text.as_bytes().to_vec(),
+            ciphertext: body.clone(),
+            integrity: *hash,
+            verdict: Some(verdict),
+            key: self.my_pub_key.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handshake::Offer;
+
+    // Sessões dos dois lados de uma mesma conexão, com a cifra dada
+    fn pair(cipher: CipherAlgorithm) -> (Session, Session) {
+        let offer = Offer::with_ciphers(&[cipher]);
+        let suite = offer.negotiate(&offer).unwrap();
+        let (client_pub, client_priv) = rsa::new_keypair(None);
+        let (server_pub, server_priv) = rsa::new_keypair(None);
+        let secret = [9; 32];
+        let client = Session::new(suite, Role::Client, secret, server_pub.clone(), client_pub.clone(), client_priv);
+        let server = Session::new(suite, Role::Server, secret, client_pub, server_pub, server_priv);
+        (client, server)
+    }
+
+    #[test]
+    fn window_accepts_in_order_and_rejects_replays() {
+        let mut window = ReplayWindow::default();
+        for seq in 1..=3 {
+            assert_eq!(window.check(seq), Verdict::Valid);
+            window.accept(seq);
+        }
+        assert_eq!(window.check(2), Verdict::Replayed);
+        assert_eq!(window.check(3), Verdict::Replayed);
+    }
+
+    #[test]
+    fn window_accepts_reordered_messages_once() {
+        let mut window = ReplayWindow::default();
+        window.accept(10);
+        assert_eq!(window.check(7), Verdict::Valid);
+        window.accept(7);
+        assert_eq!(window.check(7), Verdict::Replayed);
+        assert_eq!(window.check(8), Verdict::Valid);
+    }
+
+    #[test]
+    fn window_rejects_zero_and_old_sequences() {
+        let mut window = ReplayWindow::default();
+        assert_eq!(window.check(0), Verdict::OutOfWindow);
+        window.accept(100);
+        assert_eq!(window.check(100 - REPLAY_WINDOW), Verdict::OutOfWindow);
+        assert_eq!(window.check(100 - REPLAY_WINDOW + 1), Verdict::Valid);
+    }
+
+    #[test]
+    fn window_forgets_after_a_long_jump() {
+        let mut window = ReplayWindow::default();
+        window.accept(1);
+        window.accept(1 + REPLAY_WINDOW * 2);
+        assert_eq!(window.check(REPLAY_WINDOW * 2), Verdict::Valid);
+        assert_eq!(window.check(1 + REPLAY_WINDOW * 2), Verdict::Replayed);
+    }
+
+    #[test]
+    fn sealed_messages_open_on_the_other_side() {
+        for cipher in [CipherAlgorithm::Sha256Ctr, CipherAlgorithm::RsaBlock] {
+            let (client, server) = pair(cipher);
+            let (mut sender, _) = client.split();
+            let (_, mut receiver) = server.split();
+            let sealed = sender.seal("olá, ção €", b"geral");
+            assert_eq!(receiver.open(sealed.seq, b"geral", &sealed.hash, &sealed.body), ("olá, ção €".to_string(), Verdict::Valid));
+            assert_eq!(receiver.open(sealed.seq, b"geral", &sealed.hash, &sealed.body).1, Verdict::Replayed);
+        }
+    }
+
+    #[test]
+    fn tampered_messages_do_not_advance_the_window() {
+        let (client, server) = pair(CipherAlgorithm::Sha256Ctr);
+        let (mut sender, _) = client.split();
+        let (_, mut receiver) = server.split();
+        let sealed = sender.seal("oi", b"geral");
+        let Ciphertext::Stream(bytes) = &sealed.body else { panic!("esperava cifra de fluxo") };
+        let mut forged = bytes.clone();
+        forged[0] ^= 1;
+        assert_eq!(receiver.open(sealed.seq, b"geral", &sealed.hash, &Ciphertext::Stream(forged)).1, Verdict::Invalid);
+        assert_eq!(receiver.open(sealed.seq, b"outra", &sealed.hash, &sealed.body).1, Verdict::Invalid);
+        assert_eq!(receiver.open(sealed.seq, b"geral", &sealed.hash, &sealed.body).1, Verdict::Valid);
+    }
+
+    #[test]
+    fn each_direction_has_its_own_keys() {
+        let (client, _) = pair(CipherAlgorithm::Sha256Ctr);
+        let (mut sender, mut receiver) = client.split();
+        let sealed = sender.seal("eco", &[]);
+        assert_eq!(receiver.open(sealed.seq, &[], &sealed.hash, &sealed.body).1, Verdict::Invalid);
+    }
+
+    #[test]
+    fn resumed_sessions_agree_on_a_new_secret() {
+        let (client, server) = pair(CipherAlgorithm::Sha256Ctr);
+        let (client_nonce, server_nonce) = ([1; 32], [2; 32]);
+        let resumed_client = client.resumable().resume(Role::Client, &client_nonce, &server_nonce);
+        let resumed_server = server.resumable().resume(Role::Server, &client_nonce, &server_nonce);
+        assert!(resumed_client.resumed && resumed_server.resumed);
+
+        let (mut sender, _) = resumed_client.split();
+        let (_, mut receiver) = resumed_server.split();
+        let sealed = sender.seal("de volta", &[]);
+        assert_eq!(receiver.open(sealed.seq, &[], &sealed.hash, &sealed.body).1, Verdict::Valid);
+
+        // A sessão anterior não abre mensagens da retomada.
+        let (_, mut old_receiver) = server.split();
+        assert_eq!(old_receiver.open(sealed.seq, &[], &sealed.hash, &sealed.body).1, Verdict::Invalid);
+    }
+
+    #[test]
+    fn resumption_proofs_depend_on_role_and_secret() {
+        let (client, server) = pair(CipherAlgorithm::Sha256Ctr);
+        let (client_state, server_state) = (client.resumable(), server.resumable());
+        assert_eq!(client_state.proof(Role::Client, b"dados"), server_state.proof(Role::Client, b"dados"));
+        assert_ne!(client_state.proof(Role::Client, b"dados"), client_state.proof(Role::Server, b"dados"));
+        let (other, _) = pair(CipherAlgorithm::Sha256Ctr);
+        let other_state = Session { secret: [8; 32], ..other }.resumable();
+        assert_ne!(other_state.proof(Role::Client, b"dados"), client_state.proof(Role::Client, b"dados"));
+    }
+}
@@ -1,6 +1,4 @@
-use std::fs::File;
-use std::io::{Read, Error, ErrorKind};
-use std::path::Path;
+use std::io::Read;
 
 // SHA-256 constants (cube roots of first 64 primes)
 const K: [u32; 64] = [
@@ -36,7 +34,7 @@ const H0: [u32; 8] = [
 
 // Rotate right operation
 fn rotr(x: u32, n: u32) -> u32 {
-    (x >> n) | (x << (32 - n))
+    x.rotate_right(n)
 }
 
 // SHA-256 functions
@@ -64,14 +62,6 @@ fn sigma_upper1(x: u32) -> u32 {
     rotr(x, 6) ^ rotr(x, 11) ^ rotr(x, 25)
 }
 
-/// Reads a file into a byte vector
-pub fn read_file(path: &str) -> Result<Vec<u8>, std::io::Error> {
-    let mut file = File::open(path)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    Ok(buffer)
-}
-
 /// Compares two SHA-256 hashes
 pub fn compare_hashes(hash1: &[u8; 32], hash2: &[u8; 32]) -> bool {
     hash1 == hash2
@@ -85,36 +75,6 @@ pub fn display_hash(hash: &[u8; 32]) {
     println!();
 }
 
-/// Creates the message block with padding
-fn create_message_block(message: &[u8]) -> Vec<u8> {
-    let msg_len = message.len() as u64;
-    let bit_len = msg_len * 8;
-    
-    // Calculate padding length
-    let padding_len = 64 - ((msg_len + 1 + 8) % 64);
-    let padding_len = if (msg_len + 1 + 8) % 64 == 0 {
-        0
-    } else {
-        padding_len
-    };
-    
-    let new_len = msg_len + 1 + padding_len as u64 + 8;
-    let mut block = vec![0u8; new_len as usize];
-    
-    // Copy original message
-    block[..message.len()].copy_from_slice(message);
-    
-    // Add padding
-    block[message.len()] = 0x80;
-    
-    // Add length in bits at the end
-    for i in 0..8 {
-        block[new_len as usize - 8 + i as usize] = (bit_len >> (8 * (7 - i))) as u8;
-    }
-    
-    block
-}
-
 /// Processes a 512-bit block
 fn process_block(block: &[u8], h: &mut [u32; 8]) {
     let mut w = [0u32; 64];
@@ -173,24 +133,152 @@ fn create_message_schedule(block: &[u8], w: &mut [u32; 64]) {
     }
 }
 
+/// Incremental SHA-256, for data that arrives (or is read) in pieces
+pub struct Sha256 {
+    h: [u32; 8],
+    buffer: Vec<u8>,
+    len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Sha256 { h: H0, buffer: Vec::with_capacity(64), len: 0 }
+    }
+
+    /// Feeds more of the message, processing every complete 64-byte block
+    pub fn update(&mut self, data: &[u8]) {
+        self.len += data.len() as u64;
+        let mut data = data;
+        if !self.buffer.is_empty() {
+            let take = (64 - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() < 64 {
+                return;
+            }
+            process_block(&self.buffer, &mut self.h);
+            self.buffer.clear();
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            process_block(block, &mut self.h);
+        }
+        self.buffer.extend_from_slice(blocks.remainder());
+    }
+
+    /// Pads the message with its length in bits and returns the hash
+    pub fn finalize(mut self) -> [u8; 32] {
+        let bit_len = self.len.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        padding.resize(1 + (64 + 55 - self.buffer.len()) % 64, 0);
+        padding.extend_from_slice(&bit_len.to_be_bytes());
+        // Only the padding itself is not part of the message length
+        let len = self.len;
+        self.update(&padding);
+        self.len = len;
+
+        // Convert the hash to byte array
+        let mut hash = [0u8; 32];
+        for (i, &word) in self.h.iter().enumerate() {
+            hash[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        hash
+    }
+}
+
 /// Computes the SHA-256 hash of a message
 pub fn sha256(message: &[u8]) -> [u8; 32] {
-    let blocks = create_message_block(message);
-    let mut h = H0;
-    
-    // Process each 64-byte (512-bit) block
-    for chunk in blocks.chunks(64) {
-        process_block(chunk, &mut h);
+    let mut hasher = Sha256::new();
+    hasher.update(message);
+    hasher.finalize()
+}
+
+/// Computes the SHA-256 hash of everything read from `reader`, without holding it in memory
+pub fn sha256_reader(mut reader: impl Read) -> Result<[u8; 32], std::io::Error> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(hasher.finalize()),
+            Ok(n) => hasher.update(&buffer[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
     }
-    
-    // Convert the hash to byte array
-    let mut hash = [0u8; 32];
-    for (i, &word) in h.iter().enumerate() {
-        hash[i * 4] = (word >> 24) as u8;
-        hash[i * 4 + 1] = (word >> 16) as u8;
-        hash[i * 4 + 2] = (word >> 8) as u8;
-        hash[i * 4 + 3] = word as u8;
+}
+
+/// Computes HMAC-SHA-256 (RFC 2104) of a message under the given key
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    // Keys longer than the block size are hashed first
+    let mut block_key = [0u8; 64];
+    if key.len() > 64 {
+        block_key[..32].copy_from_slice(&sha256(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Vec::with_capacity(64 + message.len());
+    inner.extend(block_key.iter().map(|b| b ^ 0x36));
+    inner.extend_from_slice(message);
+    let inner_hash = sha256(&inner);
+
+    let mut outer = Vec::with_capacity(64 + 32);
+    outer.extend(block_key.iter().map(|b| b ^ 0x5c));
+    outer.extend_from_slice(&inner_hash);
+    sha256(&outer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(hash: &[u8; 32]) -> String {
+        hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn known_digests() {
+        assert_eq!(hex(&sha256(b"")), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex(&sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        );
+    }
+
+    #[test]
+    fn incremental_matches_one_shot_at_any_split() {
+        let message: Vec<u8> = (0..300u32).map(|i| (i * 7) as u8).collect();
+        for len in [0, 55, 56, 63, 64, 65, 128, 300] {
+            let expected = sha256(&message[..len]);
+            for split in [0, 1, 32, 63, 64, 65, len] {
+                let split = split.min(len);
+                let mut hasher = Sha256::new();
+                hasher.update(&message[..split]);
+                hasher.update(&message[split..len]);
+                assert_eq!(hasher.finalize(), expected, "{} bytes, split at {}", len, split);
+            }
+        }
+    }
+
+    #[test]
+    fn reader_digest_matches_one_shot() {
+        let message = vec![42u8; 200_000];
+        assert_eq!(sha256_reader(message.as_slice()).unwrap(), sha256(&message));
+    }
+
+    #[test]
+    fn hmac_rfc4231() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        );
     }
-    
-    hash
 }
@@ -0,0 +1,77 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use crate::mailbox;
+
+/// Contadores de uma conexão: bytes recebidos e enviados (com o cabeçalho dos
+/// frames) e o momento em que o outro lado mandou algo pela última vez.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    last_activity: AtomicU64,
+}
+
+impl Traffic {
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// Segundos desde 1970 (UTC) da última leitura.
+    pub fn last_activity(&self) -> u64 {
+        self.last_activity.load(Ordering::Relaxed)
+    }
+}
+
+/// Fluxo que conta os bytes lidos e escritos em um `Traffic` compartilhado.
+///
+/// Fica por baixo do handshake e das duas metades do `split`, por isso conta
+/// tudo o que passa pela conexão sem que o restante do código saiba dele.
+pub struct Metered<S> {
+    inner: S,
+    traffic: Arc<Traffic>,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S, traffic: Arc<Traffic>) -> Self {
+        Metered { inner, traffic }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = (buf.filled().len() - before) as u64;
+        if read > 0 {
+            self.traffic.bytes_in.fetch_add(read, Ordering::Relaxed);
+            self.traffic.last_activity.store(mailbox::now(), Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            self.traffic.bytes_out.fetch_add(written as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
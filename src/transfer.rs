@@ -0,0 +1,467 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use crate::cipher;
+use crate::direct::DirectMessage;
+use crate::merkle::{self, MerkleTree};
+use crate::rsa::{PrivateKey, PublicKey};
+use crate::sha::{self, Sha256};
+
+/// Tamanho de cada pedaço cifrado do arquivo.
+pub const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Quantos pedaços podem esperar em cada fila do caminho até o destinatário.
+///
+/// As filas de arquivos são limitadas: quem envia só lê o próximo pedaço do disco
+/// quando há vaga, então um destinatário lento segura o envio em vez de o arquivo
+/// se acumular na memória do cliente ou do servidor.
+pub const FILE_QUEUE: usize = 16;
+
+/// Dados associados da oferta, para que ela não seja confundida com uma mensagem privada.
+const OFFER_ASSOCIATED_DATA: &[u8] = b"arquivo";
+
+/// Etapas de uma transferência de arquivo, trocadas de ponta a ponta: o servidor
+/// só vê o identificador, o número e o tamanho de cada pedaço.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum FileTransfer {
+    /// Oferta do arquivo, cifrada para o destinatário e assinada por quem envia.
+    Offer { id: u64, offer: DirectMessage },
+    /// Resposta do destinatário à oferta; ao aceitar, diz a partir de qual pedaço
+    /// quer receber (0, ou o primeiro que falta de uma transferência interrompida).
+    Answer { id: u64, accepted: bool, resume_from: u64 },
+    /// Pedaço `index` do arquivo, cifrado com a chave da oferta, com a prova de
+    /// Merkle que o liga à raiz anunciada.
+    Chunk { id: u64, index: u64, data: Vec<u8>, proof: Vec<[u8; 32]> },
+}
+
+// Conteúdo da oferta: descrição do arquivo, raiz de Merkle dos pedaços e a chave que os cifra
+#[derive(Serialize, Deserialize)]
+struct Offer {
+    name: String,
+    size: u64,
+    digest: [u8; 32],
+    root: [u8; 32],
+    key: [u8; 32],
+}
+
+// Quantos pedaços tem um arquivo de `size` bytes (um arquivo vazio tem um pedaço vazio)
+fn chunk_count(size: u64) -> u64 {
+    size.div_ceil(CHUNK_SIZE as u64).max(1)
+}
+
+// Chave de cifra dos pedaços, derivada da chave da oferta
+fn chunk_key(key: &[u8; 32]) -> [u8; 32] {
+    cipher::derive_key(key, "arquivo-cifra")
+}
+
+/// Arquivo sendo enviado. Só a árvore de Merkle fica em memória: cada pedaço é
+/// lido do disco na hora de ser enviado.
+pub struct Outgoing {
+    pub id: u64,
+    pub to: String,
+    pub name: String,
+    path: PathBuf,
+    size: u64,
+    digest: [u8; 32],
+    tree: MerkleTree,
+    key: [u8; 32],
+}
+
+impl Outgoing {
+    /// Lê o arquivo uma vez, pedaço a pedaço, calculando o SHA-256 dele e as
+    /// folhas da árvore de Merkle.
+    pub fn read(to: &str, path: &str) -> std::io::Result<Outgoing> {
+        let mut file = File::open(path)?;
+        let name = Path::new(path).file_name().map_or("arquivo".to_string(), |name| name.to_string_lossy().into_owned());
+        let mut hasher = Sha256::new();
+        let mut leaves = Vec::new();
+        let mut size = 0;
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        loop {
+            let len = read_chunk(&mut file, &mut chunk)?;
+            // Um arquivo vazio tem um pedaço vazio.
+            if len > 0 || leaves.is_empty() {
+                hasher.update(&chunk[..len]);
+                leaves.push(merkle::leaf_hash(&chunk[..len]));
+                size += len as u64;
+            }
+            if len < CHUNK_SIZE {
+                break;
+            }
+        }
+        Ok(Outgoing {
+            id: rand::random(),
+            to: to.to_string(),
+            name,
+            path: PathBuf::from(path),
+            size,
+            digest: hasher.finalize(),
+            tree: MerkleTree::new(leaves),
+            key: rand::random(),
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    pub fn chunk_count(&self) -> u64 {
+        chunk_count(self.size)
+    }
+
+    /// Oferta cifrada para o destinatário: nome, tamanho, SHA-256, raiz de Merkle
+    /// e chave do arquivo. `None` se a chave do destinatário for inválida.
+    pub fn offer(&self, recipient: &PublicKey, my_priv_key: &PrivateKey) -> Option<FileTransfer> {
+        let offer = Offer { name: self.name.clone(), size: self.size, digest: self.digest, root: self.tree.root(), key: self.key };
+        let bytes = bincode::serialize(&offer).expect("oferta serializável");
+        let offer = DirectMessage::seal_bytes(&bytes, OFFER_ASSOCIATED_DATA, recipient, my_priv_key)?;
+        Some(FileTransfer::Offer { id: self.id, offer })
+    }
+
+    /// Pedaços cifrados do arquivo a partir de `first`, em ordem, junto com quantos
+    /// bytes terão sido enviados depois de cada um. Cada pedaço é lido do disco
+    /// quando o iterador chega nele; um arquivo que mudou desde a oferta dá erro.
+    pub fn chunks(&self, first: u64) -> std::io::Result<impl Iterator<Item = std::io::Result<(FileTransfer, usize)>> + '_> {
+        let mut file = File::open(&self.path)?;
+        let encryption_key = chunk_key(&self.key);
+        Ok((first..self.chunk_count()).map(move |index| {
+            let start = (index * CHUNK_SIZE as u64).min(self.size);
+            let end = (start + CHUNK_SIZE as u64).min(self.size);
+            let mut data = vec![0u8; (end - start) as usize];
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(&mut data)?;
+            if merkle::leaf_hash(&data) != self.tree.leaf(index as usize) {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "o arquivo mudou depois de oferecido"));
+            }
+            cipher::apply_keystream(&encryption_key, index, &mut data);
+            let proof = self.tree.proof(index as usize);
+            Ok((FileTransfer::Chunk { id: self.id, index, data, proof }, end as usize))
+        }))
+    }
+}
+
+// Preenche `buffer` com o que houver no arquivo e devolve quantos bytes leu
+// (menos que o tamanho do buffer só no fim do arquivo)
+fn read_chunk(file: &mut File, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Arquivo oferecido por outro cliente.
+///
+/// Os pedaços conferidos vão sendo gravados em um arquivo parcial, nomeado pela
+/// raiz de Merkle. Se a transferência cair, uma nova oferta do mesmo arquivo
+/// (mesma raiz) continua do primeiro pedaço que falta.
+pub struct Incoming {
+    pub id: u64,
+    pub from: String,
+    pub sender: String,
+    pub name: String,
+    pub size: u64,
+    accepted: bool,
+    digest: [u8; 32],
+    root: [u8; 32],
+    key: [u8; 32],
+    // Próximo pedaço esperado; os anteriores já estão conferidos no arquivo parcial.
+    next: u64,
+    partial: Option<File>,
+}
+
+/// Resultado de um pedaço recebido.
+pub enum Progress {
+    /// Pedaço aceito; ainda faltam outros.
+    Partial,
+    /// Último pedaço gravado: falta conferir o SHA-256 do arquivo inteiro com
+    /// [`Incoming::verify`].
+    Complete,
+}
+
+impl Incoming {
+    /// Decifra e confere a oferta assinada por `sender_key`. Devolve `None` se a
+    /// assinatura ou o conteúdo forem inválidos.
+    pub fn open(id: u64, from: &str, sender_key: &PublicKey, offer: &DirectMessage, my_pub_key: &PublicKey, my_priv_key: &PrivateKey) -> Option<Incoming> {
+        let bytes = offer.open_bytes(OFFER_ASSOCIATED_DATA, my_pub_key, my_priv_key, sender_key)?;
+        let offer: Offer = bincode::deserialize(&bytes).ok()?;
+        let mut incoming = Incoming {
+            id,
+            from: from.to_string(),
+            sender: sender_key.fingerprint(),
+            name: offer.name,
+            size: offer.size,
+            accepted: false,
+            digest: offer.digest,
+            root: offer.root,
+            key: offer.key,
+            next: 0,
+            partial: None,
+        };
+        // Pedaços inteiros já gravados de uma transferência anterior do mesmo arquivo.
+        if let Ok(metadata) = std::fs::metadata(incoming.partial_path()) {
+            incoming.next = (metadata.len() / CHUNK_SIZE as u64).min(chunk_count(incoming.size) - 1);
+        }
+        Some(incoming)
+    }
+
+    /// Arquivo parcial desta transferência, no diretório atual.
+    pub fn partial_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}.parte", &hex::encode(self.root)[..16]))
+    }
+
+    /// SHA-256 anunciado na oferta, em hex.
+    pub fn digest_hex(&self) -> String {
+        hex::encode(self.digest)
+    }
+
+    /// Bytes já conferidos.
+    pub fn received_len(&self) -> usize {
+        (self.next as usize * CHUNK_SIZE).min(self.size as usize)
+    }
+
+    /// Aceita a oferta: abre o arquivo parcial, descartando o que passar dos
+    /// pedaços já conferidos, e devolve o pedaço a pedir primeiro.
+    pub fn start(&mut self) -> std::io::Result<u64> {
+        let mut file = OpenOptions::new().create(true).truncate(false).write(true).open(self.partial_path())?;
+        file.set_len(self.received_len() as u64)?;
+        file.seek(SeekFrom::End(0))?;
+        self.partial = Some(file);
+        self.accepted = true;
+        Ok(self.next)
+    }
+
+    /// Decifra o pedaço e confere sua prova contra a raiz de Merkle antes de
+    /// gravá-lo. O SHA-256 do arquivo inteiro fica para [`Incoming::verify`],
+    /// que relê o arquivo parcial e pode demorar em arquivos grandes.
+    pub fn accept_chunk(&mut self, index: u64, data: &[u8], proof: &[[u8; 32]]) -> Result<Progress, String> {
+        if index != self.next {
+            return Err(format!("pedaço {} fora de ordem (esperado {})", index, self.next));
+        }
+        let mut plain = data.to_vec();
+        cipher::apply_keystream(&chunk_key(&self.key), index, &mut plain);
+        let expected_len = (self.size as usize - self.received_len()).min(CHUNK_SIZE);
+        if plain.len() != expected_len || !merkle::verify(&self.root, chunk_count(self.size), index, merkle::leaf_hash(&plain), proof) {
+            return Err(format!("o pedaço {} não confere com a raiz de Merkle", index));
+        }
+        let partial = self.partial.as_mut().ok_or("transferência não aceita")?;
+        partial.write_all(&plain).and_then(|_| partial.flush()).map_err(|e| e.to_string())?;
+        self.next += 1;
+
+        if self.next < chunk_count(self.size) {
+            return Ok(Progress::Partial);
+        }
+        Ok(Progress::Complete)
+    }
+
+    /// Confere o SHA-256 do arquivo recebido com o da oferta. Se não conferir, o
+    /// arquivo parcial é apagado para que uma nova tentativa comece do zero.
+    pub fn verify(&self) -> Result<(), String> {
+        let received = File::open(self.partial_path()).and_then(sha::sha256_reader).map_err(|e| e.to_string())?;
+        if !sha::compare_hashes(&received, &self.digest) {
+            let _ = std::fs::remove_file(self.partial_path());
+            return Err("o SHA-256 do arquivo recebido não confere com o anunciado".to_string());
+        }
+        Ok(())
+    }
+
+    /// Move o arquivo completo para o nome oferecido, no diretório atual, sem
+    /// sobrescrever arquivos existentes, e devolve o caminho usado.
+    pub fn save(&mut self) -> std::io::Result<PathBuf> {
+        self.partial = None;
+        // Só o nome final: a oferta não escolhe o diretório.
+        let name = Path::new(&self.name).file_name().map_or("arquivo".into(), |name| name.to_string_lossy().into_owned());
+        let mut path = PathBuf::from(&name);
+        let mut copy = 1;
+        while path.exists() {
+            path = PathBuf::from(format!("{}-{}", copy, name));
+            copy += 1;
+        }
+        std::fs::rename(self.partial_path(), &path)?;
+        Ok(path)
+    }
+}
+
+/// Transferências em andamento de um cliente. Cada oferta recebida ganha um
+/// número curto, usado em `/accept` e `/decline`.
+#[derive(Default)]
+pub struct Transfers {
+    outgoing: HashMap<u64, Outgoing>,
+    incoming: BTreeMap<u32, Incoming>,
+    next_number: u32,
+}
+
+impl Transfers {
+    /// Guarda o arquivo oferecido até o destinatário responder.
+    pub fn offered(&mut self, outgoing: Outgoing) {
+        self.outgoing.insert(outgoing.id, outgoing);
+    }
+
+    /// Retira o arquivo oferecido a `to`, quando chega a resposta dele.
+    pub fn take_outgoing(&mut self, id: u64, to: &str) -> Option<Outgoing> {
+        self.outgoing.get(&id).filter(|outgoing| outgoing.to.eq_ignore_ascii_case(to))?;
+        self.outgoing.remove(&id)
+    }
+
+    /// Registra uma oferta recebida e devolve o número dela. Uma oferta anterior
+    /// do mesmo arquivo (interrompida) é substituída pela nova.
+    pub fn add_incoming(&mut self, incoming: Incoming) -> u32 {
+        self.incoming.retain(|_, previous| previous.root != incoming.root);
+        self.next_number += 1;
+        self.incoming.insert(self.next_number, incoming);
+        self.next_number
+    }
+
+    /// Oferta recebida que ainda espera `/accept` ou `/decline`.
+    pub fn pending_mut(&mut self, number: u32) -> Option<&mut Incoming> {
+        self.incoming.get_mut(&number).filter(|incoming| !incoming.accepted)
+    }
+
+    pub fn remove_incoming(&mut self, number: u32) -> Option<Incoming> {
+        self.incoming.remove(&number)
+    }
+
+    /// Transferência aceita com o identificador `id`, vinda da chave `sender`.
+    pub fn receiving_mut(&mut self, id: u64, sender: &str) -> Option<(u32, &mut Incoming)> {
+        self.incoming.iter_mut()
+            .find(|(_, incoming)| incoming.accepted && incoming.id == id && incoming.sender == sender)
+            .map(|(number, incoming)| (*number, incoming))
+    }
+}
+
+/// Porcentagem de `done` em relação a `total`.
+pub fn percent(done: usize, total: usize) -> usize {
+    (done * 100).checked_div(total).unwrap_or(100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rsa;
+
+    // Arquivo temporário com `data`, apagado ao fim do teste
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, data: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("chat_rsa_envio_{}_{}", std::process::id(), name));
+            std::fs::write(&path, data).unwrap();
+            TempFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    // Decifra e confere os pedaços enviados, devolvendo o arquivo remontado
+    fn received(outgoing: &Outgoing, first: u64) -> Vec<u8> {
+        let mut data = Vec::new();
+        for chunk in outgoing.chunks(first).unwrap() {
+            let (FileTransfer::Chunk { index, data: mut bytes, proof, .. }, sent) = chunk.unwrap() else { panic!("esperava um pedaço") };
+            cipher::apply_keystream(&chunk_key(&outgoing.key), index, &mut bytes);
+            assert!(merkle::verify(&outgoing.tree.root(), outgoing.chunk_count(), index, merkle::leaf_hash(&bytes), &proof));
+            data.extend_from_slice(&bytes);
+            assert_eq!(sent, (first as usize * CHUNK_SIZE + data.len()).min(outgoing.size()));
+        }
+        data
+    }
+
+    #[test]
+    fn streamed_chunks_rebuild_the_file() {
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| i as u8).collect();
+        let file = TempFile::new("inteiro", &data);
+        let outgoing = Outgoing::read("bia", file.path()).unwrap();
+        assert_eq!((outgoing.size(), outgoing.chunk_count()), (data.len(), 3));
+        assert_eq!(outgoing.digest, sha::sha256(&data));
+        assert_eq!(received(&outgoing, 0), data);
+        assert_eq!(received(&outgoing, 2), &data[CHUNK_SIZE * 2..]);
+    }
+
+    #[test]
+    fn empty_file_has_one_empty_chunk() {
+        let file = TempFile::new("vazio", &[]);
+        let outgoing = Outgoing::read("bia", file.path()).unwrap();
+        assert_eq!((outgoing.size(), outgoing.chunk_count()), (0, 1));
+        assert!(received(&outgoing, 0).is_empty());
+    }
+
+    #[test]
+    fn file_changed_after_the_offer_stops_the_upload() {
+        let file = TempFile::new("mudou", &vec![1u8; CHUNK_SIZE + 10]);
+        let outgoing = Outgoing::read("bia", file.path()).unwrap();
+        std::fs::write(&file.0, vec![2u8; CHUNK_SIZE + 10]).unwrap();
+        let error = outgoing.chunks(0).unwrap().next().unwrap().err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn offer_opens_only_for_the_recipient() {
+        let file = TempFile::new("oferta", b"conteudo");
+        let outgoing = Outgoing::read("bia", file.path()).unwrap();
+        let (ana_pub, ana_priv) = rsa::new_keypair(None);
+        let (bia_pub, bia_priv) = rsa::new_keypair(None);
+        let Some(FileTransfer::Offer { id, offer }) = outgoing.offer(&bia_pub, &ana_priv) else { panic!("esperava a oferta") };
+
+        let incoming = Incoming::open(id, "ana", &ana_pub, &offer, &bia_pub, &bia_priv).unwrap();
+        assert_eq!((incoming.name.as_str(), incoming.size), (outgoing.name.as_str(), 8));
+        assert_eq!(incoming.digest_hex(), hex::encode(sha::sha256(b"conteudo")));
+        // Outra chave não abre a oferta, e uma assinatura de outro remetente não confere.
+        assert!(Incoming::open(id, "ana", &ana_pub, &offer, &ana_pub, &ana_priv).is_none());
+        assert!(Incoming::open(id, "ana", &bia_pub, &offer, &bia_pub, &bia_priv).is_none());
+    }
+
+    #[test]
+    fn received_file_is_verified_after_the_last_chunk() {
+        let data: Vec<u8> = (0..CHUNK_SIZE + 100).map(|i| (i * 7) as u8).collect();
+        let file = TempFile::new("conferido", &data);
+        let outgoing = Outgoing::read("bia", file.path()).unwrap();
+        let (ana_pub, ana_priv) = rsa::new_keypair(None);
+        let (bia_pub, bia_priv) = rsa::new_keypair(None);
+        let Some(FileTransfer::Offer { id, offer }) = outgoing.offer(&bia_pub, &ana_priv) else { panic!("esperava a oferta") };
+        let mut incoming = Incoming::open(id, "ana", &ana_pub, &offer, &bia_pub, &bia_priv).unwrap();
+        let partial = TempFile(incoming.partial_path());
+        assert_eq!(incoming.start().unwrap(), 0);
+
+        let mut chunks = outgoing.chunks(0).unwrap().map(|chunk| chunk.unwrap().0);
+        let Some(FileTransfer::Chunk { index, data: bytes, proof, .. }) = chunks.next() else { panic!("esperava um pedaço") };
+        assert!(matches!(incoming.accept_chunk(index, &bytes, &proof), Ok(Progress::Partial)));
+        let Some(FileTransfer::Chunk { index, data: bytes, proof, .. }) = chunks.next() else { panic!("esperava um pedaço") };
+        assert!(matches!(incoming.accept_chunk(index, &bytes, &proof), Ok(Progress::Complete)));
+        assert_eq!(incoming.verify(), Ok(()));
+        assert_eq!(std::fs::read(&partial.0).unwrap(), data);
+
+        // Um arquivo parcial adulterado no disco não passa e é descartado.
+        let mut altered = data.clone();
+        altered[0] ^= 1;
+        std::fs::write(&partial.0, &altered).unwrap();
+        assert!(incoming.verify().is_err());
+        assert!(!partial.0.exists());
+    }
+
+    #[test]
+    fn no_offer_for_an_invalid_key() {
+        let file = TempFile::new("chave", b"x");
+        let outgoing = Outgoing::read("bia", file.path()).unwrap();
+        let (_, ana_priv) = rsa::new_keypair(None);
+        assert!(outgoing.offer(&PublicKey { e: 3, n: 100 }, &ana_priv).is_none());
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        assert!(Outgoing::read("bia", "/nao/existe/arquivo").is_err());
+    }
+}
@@ -0,0 +1,458 @@
+use std::io;
+use std::time::Duration;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::{Frame, Terminal};
+use tokio::sync::mpsc;
+use crate::ui::{StatusUpdate, Theme, Tone, UiEvent, PROMPT};
+
+/// Quantas mensagens ficam no terminal depois que a interface fecha.
+pub const KEPT_ON_EXIT: usize = 5;
+
+/// Título do painel do inspetor criptográfico.
+pub const INSPECTOR_TITLE: &str = "Inspetor (F2 fecha, Shift-PgUp/PgDn rola)";
+
+/// Intervalo entre redesenhos quando nada é digitado.
+pub const TICK: Duration = Duration::from_millis(50);
+
+pub type Screen = Terminal<CrosstermBackend<io::Stdout>>;
+
+/// Roda a interface de tela cheia até receber `UiEvent::Close`.
+///
+/// Fica em uma thread própria: lê o teclado com `crossterm`, manda cada linha
+/// confirmada com Enter para `lines` e redesenha a tela com as mensagens e o
+/// status recebidos por `events`.
+pub fn run(events: mpsc::UnboundedReceiver<UiEvent>, lines: mpsc::UnboundedSender<String>, theme: Theme) {
+    let mut app = App::new(lines, theme);
+    if let Err(e) = setup().and_then(|mut terminal| {
+        let result = app.event_loop(&mut terminal, events);
+        restore();
+        result
+    }) {
+        restore();
+        eprintln!("Erro na interface: {}", e);
+    }
+    // O que aconteceu por último (por exemplo, o motivo de a conexão cair) continua visível.
+    print_last(&app.messages);
+}
+
+/// Coloca o terminal em modo cru, na tela alternativa.
+pub fn setup() -> io::Result<Screen> {
+    terminal::enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    // Um pânico em qualquer tarefa não pode deixar o terminal em modo cru.
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore();
+        previous(info);
+    }));
+    Terminal::new(CrosstermBackend::new(io::stdout()))
+}
+
+/// Devolve o terminal ao normal.
+pub fn restore() {
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, crossterm::cursor::Show);
+}
+
+/// Imprime as últimas mensagens depois que a tela alternativa foi fechada.
+pub fn print_last(messages: &[(Tone, String)]) {
+    for (_, text) in &messages[messages.len().saturating_sub(KEPT_ON_EXIT)..] {
+        println!("{}", text);
+    }
+}
+
+/// Próxima tecla pressionada, se alguma chegar dentro de `TICK`.
+pub fn next_key() -> io::Result<Option<KeyEvent>> {
+    if event::poll(TICK)? {
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                return Ok(Some(key));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// F2: mostra ou esconde o inspetor.
+pub fn is_inspector_toggle(key: &KeyEvent) -> bool {
+    key.code == KeyCode::F(2)
+}
+
+/// Shift-PageUp/PageDown: rolam o inspetor em vez da conversa.
+pub fn is_inspector_scroll(key: &KeyEvent) -> bool {
+    key.modifiers.contains(KeyModifiers::SHIFT) && matches!(key.code, KeyCode::PageUp | KeyCode::PageDown)
+}
+
+/// Divide a área da conversa ao meio quando o inspetor está aberto; devolve
+/// a área da conversa e, se houver, a do inspetor.
+pub fn split_inspector(area: Rect, open: bool) -> (Rect, Option<Rect>) {
+    if !open {
+        return (area, None);
+    }
+    let halves = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(area);
+    (halves[0], Some(halves[1]))
+}
+
+/// Ctrl-C ou Ctrl-D: fecha a entrada.
+pub fn is_quit(key: &KeyEvent) -> bool {
+    key.modifiers.contains(KeyModifiers::CONTROL) && matches!(key.code, KeyCode::Char('c') | KeyCode::Char('d'))
+}
+
+/// Linha de entrada com edição e histórico das linhas já enviadas.
+#[derive(Default)]
+pub struct InputLine {
+    text: Vec<char>,
+    cursor: usize,
+    // Linhas já enviadas, para Cima/Baixo
+    sent: Vec<String>,
+    browsing: Option<usize>,
+}
+
+impl InputLine {
+    /// Trata uma tecla de edição. Devolve a linha quando o usuário aperta Enter.
+    /// Com `secret`, a linha não entra no histórico.
+    pub fn key(&mut self, key: KeyEvent, secret: bool) -> Option<String> {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char(c) if !control => {
+                self.text.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            KeyCode::Enter => {
+                let line: String = self.text.drain(..).collect();
+                self.cursor = 0;
+                self.browsing = None;
+                if !secret && !line.is_empty() && self.sent.last() != Some(&line) {
+                    self.sent.push(line.clone());
+                }
+                return Some(line);
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.text.remove(self.cursor);
+            }
+            KeyCode::Delete if self.cursor < self.text.len() => {
+                self.text.remove(self.cursor);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.text.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.text.len(),
+            KeyCode::Esc => self.set(String::new()),
+            KeyCode::Up if !secret && !self.sent.is_empty() => {
+                let index = self.browsing.map_or(self.sent.len() - 1, |i| i.saturating_sub(1));
+                self.browsing = Some(index);
+                self.set(self.sent[index].clone());
+            }
+            KeyCode::Down if !secret => match self.browsing {
+                Some(i) if i + 1 < self.sent.len() => {
+                    self.browsing = Some(i + 1);
+                    self.set(self.sent[i + 1].clone());
+                }
+                Some(_) => {
+                    self.browsing = None;
+                    self.set(String::new());
+                }
+                None => {}
+            },
+            _ => {}
+        }
+        None
+    }
+
+    fn set(&mut self, text: String) {
+        self.text = text.chars().collect();
+        self.cursor = self.text.len();
+    }
+
+    /// Desenha a linha em uma caixa com `label` no título; com `secret`, mostra só pontos.
+    pub fn draw(&self, frame: &mut Frame, area: Rect, label: &str, secret: bool) {
+        let width = area.width.saturating_sub(2) as usize;
+        // Rola a linha para o cursor sempre ficar visível
+        let offset = (self.cursor + 1).saturating_sub(width);
+        let shown: String = if secret {
+            "•".repeat(self.text.len().saturating_sub(offset))
+        } else {
+            self.text[offset..].iter().collect()
+        };
+        let title = format!(" {} ", label.trim_end().trim_end_matches([':', '>']));
+        let block = Block::default().borders(Borders::ALL).title(title);
+        frame.render_widget(Paragraph::new(shown).block(block), area);
+        frame.set_cursor(area.x + 1 + (self.cursor - offset) as u16, area.y + 1);
+    }
+}
+
+/// Área de mensagens que acompanha as novas e rola com PageUp/PageDown.
+pub struct MessagePane {
+    theme: Theme,
+    // Linhas de tela acima do fim da conversa (0 = acompanhando as novas mensagens)
+    scroll: usize,
+    // Altura da área no último desenho
+    page: usize,
+}
+
+impl MessagePane {
+    pub fn new(theme: Theme) -> Self {
+        MessagePane { theme, scroll: 0, page: 0 }
+    }
+
+    /// Trata PageUp/PageDown; devolve `false` para as outras teclas.
+    pub fn key(&mut self, key: &KeyEvent) -> bool {
+        let step = self.page.saturating_sub(1).max(1);
+        match key.code {
+            KeyCode::PageUp => self.scroll += step,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(step),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Volta a acompanhar as mensagens novas.
+    pub fn follow(&mut self) {
+        self.scroll = 0;
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame, area: Rect, title: &str, messages: &[(Tone, String)]) {
+        let width = area.width.saturating_sub(2) as usize;
+        let height = area.height.saturating_sub(2) as usize;
+        self.page = height;
+
+        let mut rows = Vec::new();
+        for (tone, text) in messages {
+            let (marker, style) = tone_style(self.theme, *tone);
+            let mut first = true;
+            for row in text.lines().flat_map(|line| wrap(line, width.saturating_sub(2))) {
+                let marker = if first { marker } else { "  " };
+                first = false;
+                rows.push(Line::from(vec![Span::styled(marker, style), Span::styled(row, style)]));
+            }
+        }
+
+        self.scroll = self.scroll.min(rows.len().saturating_sub(height));
+        let end = rows.len() - self.scroll;
+        let visible: Vec<Line> = rows.drain(end.saturating_sub(height)..end).collect();
+        let title = if self.scroll > 0 {
+            format!(" {} (↑ {} linhas, PageDown volta) ", title, self.scroll)
+        } else {
+            format!(" {} ", title)
+        };
+        let block = Block::default().borders(Borders::ALL).title(title);
+        frame.render_widget(Paragraph::new(visible).block(block), area);
+    }
+}
+
+// Marcador e cor de cada tipo de mensagem
+fn tone_style(theme: Theme, tone: Tone) -> (&'static str, Style) {
+    match tone {
+        Tone::Info => ("  ", Style::default()),
+        Tone::Notice => ("• ", colored(theme, Color::Cyan, Color::Blue)),
+        Tone::Valid => ("✔ ", colored(theme, Color::Green, Color::Green)),
+        Tone::Invalid => ("✘ ", colored(theme, Color::Red, Color::Red).add_modifier(Modifier::BOLD)),
+        Tone::Warning => ("! ", colored(theme, Color::Yellow, Color::Magenta)),
+    }
+}
+
+// Texto com a cor do tema; no tema sem cores, o estilo normal
+fn colored(theme: Theme, dark: Color, light: Color) -> Style {
+    match theme {
+        Theme::Dark => Style::default().fg(dark),
+        Theme::Light => Style::default().fg(light),
+        Theme::Mono => Style::default(),
+    }
+}
+
+// Quebra uma linha em pedaços de no máximo `width` caracteres
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = line.chars().collect();
+    if chars.is_empty() || width == 0 {
+        return vec![line.to_string()];
+    }
+    chars.chunks(width).map(|chunk| chunk.iter().collect()).collect()
+}
+
+/// Estilo das barras de status.
+pub fn bar_style(theme: Theme) -> Style {
+    match theme {
+        Theme::Dark => Style::default().bg(Color::DarkGray).fg(Color::White),
+        Theme::Light => Style::default().bg(Color::Gray).fg(Color::Black),
+        Theme::Mono => Style::default().add_modifier(Modifier::REVERSED),
+    }
+}
+
+/// Estilo da aba aberta.
+pub fn tab_style(theme: Theme) -> Style {
+    match theme {
+        Theme::Mono => Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+        _ => colored(theme, Color::Yellow, Color::Blue).add_modifier(Modifier::BOLD),
+    }
+}
+
+/// Estilo da linha selecionada em uma tabela.
+pub fn selected_style(theme: Theme) -> Style {
+    match theme {
+        Theme::Dark => Style::default().bg(Color::DarkGray),
+        Theme::Light => Style::default().bg(Color::Gray),
+        Theme::Mono => Style::default().add_modifier(Modifier::REVERSED),
+    }
+}
+
+// Campos da barra de status
+struct Status {
+    connection: String,
+    server: String,
+    server_fingerprint: String,
+    nickname: String,
+    fingerprint: String,
+    room: String,
+}
+
+struct App {
+    theme: Theme,
+    messages: Vec<(Tone, String)>,
+    pane: MessagePane,
+    // Passos criptográficos de cada mensagem, uma entrada por mensagem
+    inspections: Vec<(Tone, String)>,
+    inspector: MessagePane,
+    inspector_open: bool,
+    status: Status,
+    prompt: String,
+    secret: bool,
+    input: InputLine,
+    // Fechado com Ctrl-C: o cliente vê o fim da entrada e encerra
+    lines: Option<mpsc::UnboundedSender<String>>,
+}
+
+impl App {
+    fn new(lines: mpsc::UnboundedSender<String>, theme: Theme) -> Self {
+        App {
+            theme,
+            messages: Vec::new(),
+            pane: MessagePane::new(theme),
+            inspections: Vec::new(),
+            inspector: MessagePane::new(theme),
+            inspector_open: false,
+            status: Status {
+                connection: "conectando".to_string(),
+                server: String::new(),
+                server_fingerprint: String::new(),
+                nickname: String::new(),
+                fingerprint: String::new(),
+                room: String::new(),
+            },
+            prompt: PROMPT.to_string(),
+            secret: false,
+            input: InputLine::default(),
+            lines: Some(lines),
+        }
+    }
+
+    fn event_loop(&mut self, terminal: &mut Screen, mut events: mpsc::UnboundedReceiver<UiEvent>) -> io::Result<()> {
+        loop {
+            loop {
+                match events.try_recv() {
+                    Ok(UiEvent::Message(tone, text)) => self.messages.push((tone, text)),
+                    Ok(UiEvent::Status(update)) => self.update_status(update),
+                    Ok(UiEvent::Prompt { label, secret }) => {
+                        self.prompt = label;
+                        self.secret = secret;
+                    }
+                    Ok(UiEvent::Inspect(lines)) => self.inspections.push((Tone::Info, lines.join("\n"))),
+                    Ok(UiEvent::ToggleInspector) => self.inspector_open = !self.inspector_open,
+                    Ok(UiEvent::Close) | Err(mpsc::error::TryRecvError::Disconnected) => return Ok(()),
+                    Err(mpsc::error::TryRecvError::Empty) => break,
+                }
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Some(key) = next_key()? {
+                self.key(key);
+            }
+        }
+    }
+
+    fn update_status(&mut self, update: StatusUpdate) {
+        match update {
+            StatusUpdate::Connection(state) => self.status.connection = state,
+            StatusUpdate::Server { addr, fingerprint } => {
+                self.status.server = addr;
+                self.status.server_fingerprint = fingerprint;
+            }
+            StatusUpdate::Identity { nickname, fingerprint } => {
+                self.status.nickname = nickname;
+                self.status.fingerprint = fingerprint;
+            }
+            StatusUpdate::Room(room) => self.status.room = room,
+        }
+    }
+
+    fn key(&mut self, key: KeyEvent) {
+        if is_quit(&key) {
+            self.messages.push((Tone::Notice, "Encerrando...".to_string()));
+            self.lines = None;
+        } else if is_inspector_toggle(&key) {
+            self.inspector_open = !self.inspector_open;
+        } else if self.inspector_open && is_inspector_scroll(&key) {
+            self.inspector.key(&key);
+        } else if !self.pane.key(&key) {
+            if let Some(line) = self.input.key(key, self.secret) {
+                self.pane.follow();
+                // As linhas digitadas aparecem na conversa, como no terminal comum.
+                if !self.secret {
+                    self.messages.push((Tone::Info, format!("{}{}", self.prompt, line)));
+                }
+                if let Some(lines) = &self.lines {
+                    let _ = lines.send(line);
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(3), Constraint::Length(1)])
+            .split(frame.size());
+        let (conversation, inspector) = split_inspector(areas[0], self.inspector_open);
+        self.pane.draw(frame, conversation, "Conversa", &self.messages);
+        if let Some(area) = inspector {
+            self.inspector.draw(frame, area, INSPECTOR_TITLE, &self.inspections);
+        }
+        self.input.draw(frame, areas[1], &self.prompt, self.secret);
+        self.draw_status(frame, areas[2]);
+    }
+
+    fn draw_status(&self, frame: &mut Frame, area: Rect) {
+        let status = &self.status;
+        let bar = bar_style(self.theme);
+        let state = match status.connection.as_str() {
+            "conectado" => colored(self.theme, Color::Green, Color::Green),
+            "desconectado" => colored(self.theme, Color::Red, Color::Red),
+            _ => colored(self.theme, Color::Yellow, Color::Magenta),
+        };
+        let unknown = |text: &str| if text.is_empty() { "?".to_string() } else { text.to_string() };
+        let line = Line::from(vec![
+            Span::styled(format!(" {} ", status.connection), bar.patch(state).add_modifier(Modifier::BOLD)),
+            Span::styled(
+                format!(
+                    "│ servidor {} [{}] │ {} [{}] │ sala {} │ PgUp/PgDn rola, F2 inspetor, Ctrl-C sai",
+                    unknown(&status.server),
+                    unknown(&status.server_fingerprint),
+                    unknown(&status.nickname),
+                    unknown(&status.fingerprint),
+                    unknown(&status.room),
+                ),
+                bar,
+            ),
+        ]);
+        frame.render_widget(Paragraph::new(line).style(bar), area);
+    }
+}
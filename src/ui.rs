@@ -0,0 +1,541 @@
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, BufReader as TokioBufReader, Stdin};
+use tokio::sync::{mpsc, Notify};
+use crate::dashboard;
+use crate::hub::{ClientId, Hub};
+use crate::tui;
+
+/// Prompt da conversa no terminal.
+pub const PROMPT: &str = "Cliente, sua resposta para o servidor: ";
+
+/// Prompt do console do servidor.
+pub const SERVER_PROMPT: &str = "Servidor> ";
+
+/// Como o cliente (ou o console do servidor) conversa com o usuário.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum UiMode {
+    /// Linhas impressas no terminal, com o prompt reimpresso depois de cada mensagem.
+    #[value(help = "linhas no terminal")]
+    Plain,
+    /// Interface de tela cheia (ver `tui` e `dashboard`).
+    #[value(help = "tela cheia")]
+    Tui,
+}
+
+/// Cores da interface de tela cheia (o terminal comum não usa cores).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Theme {
+    /// Para terminais de fundo escuro.
+    #[default]
+    #[value(help = "fundo escuro")]
+    Dark,
+    /// Para terminais de fundo claro.
+    #[value(help = "fundo claro")]
+    Light,
+    /// Sem cores: só negrito, sublinhado e vídeo reverso.
+    #[value(help = "sem cores")]
+    Mono,
+}
+
+/// Quanto o cliente (ou o servidor) mostra além da conversa.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    /// Esconde as linhas de progresso da conexão (ver `ClientUi::detail`).
+    Quiet,
+    Normal,
+    /// Começa com o inspetor criptográfico ligado.
+    Verbose,
+}
+
+/// Tipo de cada mensagem mostrada, para a interface destacá-la.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tone {
+    /// Respostas a comandos e informações gerais.
+    Info,
+    /// Avisos do chat: entradas, saídas, transferências.
+    Notice,
+    /// Mensagem com assinatura ou MAC válido.
+    Valid,
+    /// Assinatura inválida, mensagem adulterada ou erro.
+    Invalid,
+    /// Repetições, recusas do servidor e outros alertas.
+    Warning,
+}
+
+/// Mudanças na barra de status.
+#[derive(Debug, Clone)]
+pub enum StatusUpdate {
+    /// Estado da conexão: conectando, handshake, conectado, desconectado...
+    Connection(String),
+    /// Endereço do servidor e a impressão digital da chave dele.
+    Server { addr: String, fingerprint: String },
+    /// Apelido e impressão digital da própria chave.
+    Identity { nickname: String, fingerprint: String },
+    /// Sala atual.
+    Room(String),
+}
+
+/// O que as tarefas do cliente mandam para a interface de tela cheia.
+pub enum UiEvent {
+    Message(Tone, String),
+    Status(StatusUpdate),
+    /// Troca o rótulo da linha de entrada; com `secret`, o texto digitado fica escondido.
+    Prompt { label: String, secret: bool },
+    /// Passos criptográficos de uma mensagem ou do handshake, para o inspetor.
+    Inspect(Vec<String>),
+    /// Mostra ou esconde o painel do inspetor.
+    ToggleInspector,
+    /// O cliente terminou: a interface restaura o terminal e sai.
+    Close,
+}
+
+/// Saída do cliente, compartilhada pela tarefa de leitura, pelo laço de entrada
+/// e pelas transferências de arquivo.
+#[derive(Clone)]
+pub struct ClientUi {
+    inner: Inner,
+    quiet: bool,
+}
+
+#[derive(Clone)]
+enum Inner {
+    Plain(PlainOutput),
+    Tui(mpsc::UnboundedSender<UiEvent>),
+}
+
+/// Estado da saída no terminal comum, compartilhado com a entrada.
+#[derive(Clone, Default)]
+struct PlainOutput {
+    // Prompt mostrado no momento, para reimprimi-lo depois de uma mensagem que chega no meio da digitação
+    prompt: Arc<Mutex<Option<String>>>,
+    // Com o inspetor ligado, os passos criptográficos são impressos junto com a conversa
+    inspector: Arc<AtomicBool>,
+}
+
+impl PlainOutput {
+    fn inspect(&self, lines: &[String]) {
+        if self.inspector.load(Ordering::Relaxed) {
+            print_plain(&self.prompt, &lines.join("\n"));
+        }
+    }
+
+    fn toggle_inspector(&self) {
+        let on = !self.inspector.fetch_xor(true, Ordering::Relaxed);
+        print_plain(&self.prompt, if on { "Inspetor criptográfico ligado" } else { "Inspetor criptográfico desligado" });
+    }
+}
+
+// Imprime uma mensagem; se o prompt estava na tela, ele é reimpresso embaixo dela
+fn print_plain(prompt: &Mutex<Option<String>>, text: &str) {
+    let prompt = prompt.lock().unwrap();
+    match prompt.as_deref() {
+        Some(label) => print!("\n{}\n{}", text, label),
+        None => println!("{}", text),
+    }
+    let _ = std::io::stdout().flush();
+}
+
+// Mostra o prompt e anota que ele está na tela
+fn prompt_plain(prompt: &Mutex<Option<String>>, label: &str) {
+    print!("{}", label);
+    let _ = std::io::stdout().flush();
+    *prompt.lock().unwrap() = Some(label.to_string());
+}
+
+impl ClientUi {
+    pub fn show(&self, tone: Tone, text: impl Into<String>) {
+        let text = text.into();
+        match &self.inner {
+            Inner::Plain(plain) => print_plain(&plain.prompt, &text),
+            Inner::Tui(events) => {
+                let _ = events.send(UiEvent::Message(tone, text));
+            }
+        }
+    }
+
+    pub fn info(&self, text: impl Into<String>) {
+        self.show(Tone::Info, text);
+    }
+
+    /// Progresso da conexão (conectado, handshake concluído...), omitido com `Verbosity::Quiet`.
+    pub fn detail(&self, text: impl Into<String>) {
+        if !self.quiet {
+            self.show(Tone::Info, text);
+        }
+    }
+
+    pub fn notice(&self, text: impl Into<String>) {
+        self.show(Tone::Notice, text);
+    }
+
+    pub fn valid(&self, text: impl Into<String>) {
+        self.show(Tone::Valid, text);
+    }
+
+    pub fn invalid(&self, text: impl Into<String>) {
+        self.show(Tone::Invalid, text);
+    }
+
+    pub fn warning(&self, text: impl Into<String>) {
+        self.show(Tone::Warning, text);
+    }
+
+    /// Pede a próxima linha ao usuário.
+    pub fn prompt(&self, label: &str) {
+        self.ask(label, false);
+    }
+
+    /// Pede uma linha que não deve aparecer na tela; leia-a com `InputSource::next_secret`.
+    pub fn prompt_secret(&self, label: &str) {
+        self.ask(label, true);
+    }
+
+    fn ask(&self, label: &str, secret: bool) {
+        match &self.inner {
+            Inner::Plain(plain) => prompt_plain(&plain.prompt, label),
+            Inner::Tui(events) => {
+                let _ = events.send(UiEvent::Prompt { label: label.to_string(), secret });
+            }
+        }
+    }
+
+    pub fn status(&self, update: StatusUpdate) {
+        if let Inner::Tui(events) = &self.inner {
+            let _ = events.send(UiEvent::Status(update));
+        }
+    }
+
+    /// Passos criptográficos para o inspetor (ver `inspector`). No terminal comum
+    /// só aparecem com o inspetor ligado; a interface de tela cheia guarda todos.
+    pub fn inspect(&self, lines: Vec<String>) {
+        match &self.inner {
+            Inner::Plain(plain) => plain.inspect(&lines),
+            Inner::Tui(events) => {
+                let _ = events.send(UiEvent::Inspect(lines));
+            }
+        }
+    }
+
+    /// Liga ou desliga o inspetor.
+    pub fn toggle_inspector(&self) {
+        match &self.inner {
+            Inner::Plain(plain) => plain.toggle_inspector(),
+            Inner::Tui(events) => {
+                let _ = events.send(UiEvent::ToggleInspector);
+            }
+        }
+    }
+}
+
+/// O que as conexões e o console do servidor mandam para o painel.
+pub enum DashboardEvent {
+    /// Linha do log; com `client`, também vai para a aba da conversa desse cliente.
+    Log { client: Option<ClientId>, tone: Tone, text: String },
+    /// Mensagem do operador, mostrada na aba do destinatário (ou de todos, com `None`).
+    Operator { to: Option<ClientId>, text: String },
+    /// Passos criptográficos da conexão de um cliente, para o inspetor.
+    Inspect { client: ClientId, lines: Vec<String> },
+    /// Mostra ou esconde o painel do inspetor.
+    ToggleInspector,
+    /// O servidor está desligando: o painel restaura o terminal e sai.
+    Close,
+}
+
+/// Saída do servidor, compartilhada pelas conexões e pelo console do operador.
+#[derive(Clone)]
+pub struct ServerUi {
+    inner: ServerInner,
+    quiet: bool,
+}
+
+#[derive(Clone)]
+enum ServerInner {
+    Plain(PlainOutput),
+    Dashboard(mpsc::UnboundedSender<DashboardEvent>),
+}
+
+impl ServerUi {
+    /// Linha do log geral do servidor.
+    pub fn log(&self, tone: Tone, text: impl Into<String>) {
+        self.send(None, tone, text.into());
+    }
+
+    /// Linha de progresso do log (servidor no ar, handshakes), omitida com `Verbosity::Quiet`.
+    pub fn detail(&self, text: impl Into<String>) {
+        if !self.quiet {
+            self.send(None, Tone::Notice, text.into());
+        }
+    }
+
+    /// Linha sobre um cliente registrado; no painel, vai também para a aba dele.
+    pub fn client(&self, id: ClientId, tone: Tone, text: impl Into<String>) {
+        self.send(Some(id), tone, text.into());
+    }
+
+    fn send(&self, client: Option<ClientId>, tone: Tone, text: String) {
+        match &self.inner {
+            ServerInner::Plain(plain) => print_plain(&plain.prompt, &text),
+            ServerInner::Dashboard(events) => {
+                let _ = events.send(DashboardEvent::Log { client, tone, text });
+            }
+        }
+    }
+
+    /// Anota uma mensagem enviada pelo operador. No console comum ela já está na
+    /// tela, digitada pelo próprio operador.
+    pub fn operator(&self, to: Option<ClientId>, text: &str) {
+        if let ServerInner::Dashboard(events) = &self.inner {
+            let _ = events.send(DashboardEvent::Operator { to, text: text.to_string() });
+        }
+    }
+
+    /// Passos criptográficos da conexão de um cliente; no painel, ficam no
+    /// inspetor da aba dele.
+    pub fn inspect(&self, client: ClientId, lines: Vec<String>) {
+        match &self.inner {
+            ServerInner::Plain(plain) => plain.inspect(&lines),
+            ServerInner::Dashboard(events) => {
+                let _ = events.send(DashboardEvent::Inspect { client, lines });
+            }
+        }
+    }
+
+    /// Liga ou desliga o inspetor.
+    pub fn toggle_inspector(&self) {
+        match &self.inner {
+            ServerInner::Plain(plain) => plain.toggle_inspector(),
+            ServerInner::Dashboard(events) => {
+                let _ = events.send(DashboardEvent::ToggleInspector);
+            }
+        }
+    }
+
+    /// Mostra o prompt do console (o painel tem a própria linha de entrada).
+    pub fn prompt(&self) {
+        if let ServerInner::Plain(plain) = &self.inner {
+            prompt_plain(&plain.prompt, SERVER_PROMPT);
+        }
+    }
+}
+
+/// Linhas digitadas pelo usuário, do teclado ou da interface de tela cheia.
+pub struct InputSource {
+    inner: Source,
+}
+
+enum Source {
+    // O teclado só é lido quando o cliente pede uma linha, para não prender o fim do programa.
+    Plain {
+        reader: TokioBufReader<Stdin>,
+        prompt: Arc<Mutex<Option<String>>>,
+        // Avisado no Ctrl-C (ver `listen_for_interrupt`); depois dele a entrada fica fechada.
+        interrupt: Arc<Notify>,
+        interrupted: bool,
+    },
+    Tui(mpsc::UnboundedReceiver<String>),
+}
+
+impl InputSource {
+    /// Próxima linha, sem o fim de linha; `None` quando o usuário fecha a entrada
+    /// (Ctrl-D, ou Ctrl-C, que na tela cheia chega como tecla).
+    pub async fn next_line(&mut self) -> Option<String> {
+        match &mut self.inner {
+            Source::Plain { reader, prompt, interrupt, interrupted } => {
+                if *interrupted {
+                    return None;
+                }
+                let mut line = String::new();
+                let read = tokio::select! {
+                    read = reader.read_line(&mut line) => read.unwrap_or(0),
+                    _ = interrupt.notified() => {
+                        *interrupted = true;
+                        // O ^C fica na linha do prompt.
+                        println!();
+                        0
+                    }
+                };
+                // Depois do Enter o cursor já está em uma linha nova.
+                *prompt.lock().unwrap() = None;
+                (read > 0).then(|| line.trim_end_matches(['\r', '\n']).to_string())
+            }
+            Source::Tui(lines) => lines.recv().await,
+        }
+    }
+
+    /// Como `next_line`, mas sem mostrar o que é digitado: no terminal, a linha é lida
+    /// em modo cru, sem eco; na tela cheia, ela já aparece só como pontos.
+    pub async fn next_secret(&mut self) -> Option<String> {
+        match &mut self.inner {
+            Source::Plain { prompt, .. } if std::io::stdin().is_terminal() => {
+                let line = tokio::task::spawn_blocking(read_hidden_line).await.ok().flatten();
+                *prompt.lock().unwrap() = None;
+                line
+            }
+            _ => self.next_line().await,
+        }
+    }
+
+    /// Espera o Ctrl-C depois de a entrada terminar; volta na hora se ela terminou por
+    /// ele, ou se era a tela cheia, que só fecha quando o usuário sai.
+    pub async fn interrupted(&mut self) {
+        if let Source::Plain { interrupt, interrupted: false, .. } = &mut self.inner {
+            interrupt.notified().await;
+        }
+    }
+
+    /// Se a entrada terminou por um pedido do usuário (Ctrl-C, ou sair da tela
+    /// cheia), e não pelo fim do teclado, como em um serviço sem terminal.
+    pub fn is_interrupted(&self) -> bool {
+        match &self.inner {
+            Source::Plain { interrupted, .. } => *interrupted,
+            Source::Tui(_) => true,
+        }
+    }
+}
+
+/// Mantém a interface de tela cheia viva até `close`.
+pub struct UiHandle {
+    thread: Option<std::thread::JoinHandle<()>>,
+    close: Box<dyn FnOnce() + Send>,
+}
+
+impl UiHandle {
+    // No terminal comum, fechar só tira da tela um prompt que ficou sem resposta.
+    fn plain(plain: &PlainOutput) -> Self {
+        let prompt = plain.prompt.clone();
+        let close = move || {
+            if prompt.lock().unwrap().take().is_some() {
+                println!();
+            }
+        };
+        UiHandle { thread: None, close: Box::new(close) }
+    }
+
+    /// Fecha a interface e espera o terminal ser restaurado.
+    pub async fn close(self) {
+        (self.close)();
+        if let Some(thread) = self.thread {
+            let _ = tokio::task::spawn_blocking(move || thread.join()).await;
+        }
+    }
+}
+
+// Lê uma linha do terminal em modo cru, sem eco; Ctrl-C, Ctrl-D e Esc desistem
+fn read_hidden_line() -> Option<String> {
+    crossterm::terminal::enable_raw_mode().ok()?;
+    let mut line = String::new();
+    let read = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => match key.code {
+                KeyCode::Enter => break Some(line),
+                KeyCode::Char('c' | 'd') if key.modifiers.contains(KeyModifiers::CONTROL) => break None,
+                KeyCode::Esc => break None,
+                KeyCode::Backspace => {
+                    line.pop();
+                }
+                KeyCode::Char(c) => line.push(c),
+                _ => {}
+            },
+            Ok(_) => {}
+            Err(_) => break None,
+        }
+    };
+    let _ = crossterm::terminal::disable_raw_mode();
+    // Nada foi ecoado, nem o Enter: o cursor ainda está na linha do prompt.
+    println!();
+    read
+}
+
+fn plain_input(plain: &PlainOutput) -> InputSource {
+    InputSource {
+        inner: Source::Plain {
+            reader: TokioBufReader::new(tokio::io::stdin()),
+            prompt: plain.prompt.clone(),
+            interrupt: listen_for_interrupt(),
+            interrupted: false,
+        },
+    }
+}
+
+// No terminal comum, o primeiro Ctrl-C fecha a entrada como o Ctrl-D, para que o
+// cliente e o servidor se despeçam do outro lado; o segundo encerra na hora.
+fn listen_for_interrupt() -> Arc<Notify> {
+    let interrupt = Arc::new(Notify::new());
+    let notify = interrupt.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            notify.notify_one();
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
+            }
+        }
+    });
+    interrupt
+}
+
+/// Inicia a interface escolhida e devolve a saída, a entrada e o controle dela.
+pub fn start(mode: UiMode, verbosity: Verbosity, theme: Theme) -> (ClientUi, InputSource, UiHandle) {
+    let quiet = verbosity == Verbosity::Quiet;
+    match mode {
+        UiMode::Plain => {
+            let plain = PlainOutput::default();
+            plain.inspector.store(verbosity == Verbosity::Verbose, Ordering::Relaxed);
+            let input = plain_input(&plain);
+            let handle = UiHandle::plain(&plain);
+            (ClientUi { inner: Inner::Plain(plain), quiet }, input, handle)
+        }
+        UiMode::Tui => {
+            let (event_tx, event_rx) = mpsc::unbounded_channel();
+            let (line_tx, line_rx) = mpsc::unbounded_channel();
+            let thread = std::thread::spawn(move || tui::run(event_rx, line_tx, theme));
+            let close_tx = event_tx.clone();
+            let handle = UiHandle {
+                thread: Some(thread),
+                close: Box::new(move || {
+                    let _ = close_tx.send(UiEvent::Close);
+                }),
+            };
+            if verbosity == Verbosity::Verbose {
+                let _ = event_tx.send(UiEvent::ToggleInspector);
+            }
+            (ClientUi { inner: Inner::Tui(event_tx), quiet }, InputSource { inner: Source::Tui(line_rx) }, handle)
+        }
+    }
+}
+
+/// Inicia a saída do servidor; com `UiMode::Tui`, o painel (ver `dashboard`),
+/// que consulta o `hub` para listar os clientes conectados.
+pub fn start_server(mode: UiMode, verbosity: Verbosity, theme: Theme, hub: Arc<Hub>) -> (ServerUi, InputSource, UiHandle) {
+    let quiet = verbosity == Verbosity::Quiet;
+    match mode {
+        UiMode::Plain => {
+            let plain = PlainOutput::default();
+            plain.inspector.store(verbosity == Verbosity::Verbose, Ordering::Relaxed);
+            let input = plain_input(&plain);
+            let handle = UiHandle::plain(&plain);
+            (ServerUi { inner: ServerInner::Plain(plain), quiet }, input, handle)
+        }
+        UiMode::Tui => {
+            let (event_tx, event_rx) = mpsc::unbounded_channel();
+            let (line_tx, line_rx) = mpsc::unbounded_channel();
+            let thread = std::thread::spawn(move || dashboard::run(hub, event_rx, line_tx, theme));
+            let close_tx = event_tx.clone();
+            let handle = UiHandle {
+                thread: Some(thread),
+                close: Box::new(move || {
+                    let _ = close_tx.send(DashboardEvent::Close);
+                }),
+            };
+            if verbosity == Verbosity::Verbose {
+                let _ = event_tx.send(DashboardEvent::ToggleInspector);
+            }
+            (ServerUi { inner: ServerInner::Dashboard(event_tx), quiet }, InputSource { inner: Source::Tui(line_rx) }, handle)
+        }
+    }
+}